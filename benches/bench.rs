@@ -4,7 +4,7 @@ use http::{HeaderName, HeaderValue, StatusCode};
 use nanoid::nanoid;
 use pingap::config::LocationConf;
 use pingap::http_extra::{convert_headers, HttpResponse};
-use pingap::proxy::{Location, Parser};
+use pingap::proxy::{Location, Parser, Router};
 use pingap::state::{CompressionStat, State};
 use pingap::util::{self, get_super_ts};
 use pingora::http::{RequestHeader, ResponseHeader};
@@ -148,6 +148,66 @@ fn bench_location_filter(c: &mut Criterion) {
     group.finish();
 }
 
+fn bench_location_router(c: &mut Criterion) {
+    let mut group = c.benchmark_group("location router");
+    // 400 locations of prefix, equal and regex paths
+    let mut confs = HashMap::new();
+    for i in 0..400 {
+        let path = match i % 4 {
+            0 => format!("=/api/v{i}/users"),
+            1 => format!("~^/api/v{i}/.+/me$"),
+            _ => format!("/api/v{i}"),
+        };
+        let host = if i % 5 == 0 {
+            format!("pingap{i}.io")
+        } else {
+            "".to_string()
+        };
+        confs.insert(
+            format!("lo{i}"),
+            LocationConf {
+                upstream: Some("charts".to_string()),
+                path: Some(path),
+                host: Some(host),
+                ..Default::default()
+            },
+        );
+    }
+    let mut names: Vec<String> = confs.keys().cloned().collect();
+    names.sort_by_key(|name| std::cmp::Reverse(confs[name].get_weight()));
+    let locations: Vec<Location> = names
+        .iter()
+        .map(|name| Location::new(name, &confs[name]).unwrap())
+        .collect();
+    let location_map: HashMap<String, &Location> = locations
+        .iter()
+        .map(|item| (item.name.clone(), item))
+        .collect();
+    let router = Router::new(&names, &confs);
+
+    group.bench_function("linear", |b| {
+        b.iter(|| {
+            locations
+                .iter()
+                .find(|item| item.matched("", "/api/v398/users/me"))
+                .unwrap();
+        });
+    });
+    group.bench_function("router", |b| {
+        b.iter(|| {
+            router
+                .candidates("", "/api/v398/users/me")
+                .into_iter()
+                .find(|name| {
+                    location_map[*name].matched("", "/api/v398/users/me")
+                })
+                .unwrap();
+        });
+    });
+
+    group.finish();
+}
+
 fn bench_location_rewrite_path(c: &mut Criterion) {
     let upstream_name = "charts";
 
@@ -165,7 +225,7 @@ fn bench_location_rewrite_path(c: &mut Criterion) {
         b.iter(|| {
             let mut req_header =
                 RequestHeader::build("GET", b"/users/v1/me", None).unwrap();
-            let _ = lo.rewrite(&mut req_header);
+            let _ = lo.rewrite(&mut req_header, None);
         })
     });
 }
@@ -287,6 +347,7 @@ criterion_group!(
     bench_insert_header_name,
     bench_get_response_header,
     bench_location_filter,
+    bench_location_router,
     bench_location_rewrite_path,
     bench_get_super_ts,
     bench_logger_format,
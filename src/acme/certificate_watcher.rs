@@ -0,0 +1,178 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::{get_current_config, CertificateConf};
+use crate::proxy::init_certificates;
+use crate::service::{CommonServiceTask, ServiceTask};
+use crate::util;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+struct CertificateWatcher {
+    // the modified time of certificate files
+    modified_times: Mutex<AHashMap<String, SystemTime>>,
+}
+
+/// Get the files of certificates, include the certificate file of acme
+/// and the cert, key or chain which is set as file path.
+fn get_certificate_files(
+    certificates: &HashMap<String, CertificateConf>,
+) -> Vec<String> {
+    let mut files = vec![];
+    for conf in certificates.values() {
+        let values = [
+            &conf.certificate_file,
+            &conf.tls_cert,
+            &conf.tls_key,
+            &conf.tls_chain,
+        ];
+        for value in values.into_iter().flatten() {
+            if util::is_pem(value) {
+                continue;
+            }
+            let file = util::resolve_path(value);
+            if Path::new(&file).is_file() && !files.contains(&file) {
+                files.push(file);
+            }
+        }
+    }
+    files
+}
+
+impl CertificateWatcher {
+    /// Update the modified time of files,
+    /// and return the files which are modified after last check.
+    fn get_modified_files(&self, files: &[String]) -> Vec<String> {
+        let Ok(mut modified_times) = self.modified_times.lock() else {
+            return vec![];
+        };
+        let mut updated_times = AHashMap::new();
+        let mut modified_files = vec![];
+        for file in files.iter() {
+            let Ok(modified) =
+                std::fs::metadata(file).and_then(|meta| meta.modified())
+            else {
+                continue;
+            };
+            if let Some(value) = modified_times.get(file) {
+                if value != &modified {
+                    modified_files.push(file.clone());
+                }
+            }
+            updated_times.insert(file.clone(), modified);
+        }
+        *modified_times = updated_times;
+        modified_files
+    }
+}
+
+#[cfg(feature = "full")]
+fn update_certificate_expiry_days() {
+    let gauge = &crate::state::CERTIFICATE_EXPIRY_DAYS;
+    let now = util::now().as_secs() as i64;
+    gauge.reset();
+    for (name, info) in crate::proxy::get_certificate_info_list() {
+        gauge
+            .with_label_values(&[&name])
+            .set((info.not_after - now) / (24 * 3600));
+    }
+}
+
+#[async_trait]
+impl ServiceTask for CertificateWatcher {
+    async fn run(&self) -> Option<bool> {
+        let certificates = get_current_config().certificates.clone();
+        let modified_files =
+            self.get_modified_files(&get_certificate_files(&certificates));
+        if !modified_files.is_empty() {
+            info!(
+                files = modified_files.join(","),
+                "certificate files are modified, reload certificates"
+            );
+            init_certificates(&certificates);
+        }
+        #[cfg(feature = "full")]
+        update_certificate_expiry_days();
+        None
+    }
+    fn description(&self) -> String {
+        let count = self
+            .modified_times
+            .lock()
+            .map(|value| value.len())
+            .unwrap_or_default();
+        format!("CertificateWatcher: {count}")
+    }
+}
+
+/// Create a certificate watcher service, the certificates are reloaded
+/// when their files are modified, e.g. renewed by certbot.
+pub fn new_certificate_watcher_service() -> CommonServiceTask {
+    CommonServiceTask::new(
+        // check interval: thirty seconds
+        Duration::from_secs(30),
+        CertificateWatcher {
+            modified_times: Mutex::new(AHashMap::new()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_certificate_files, CertificateWatcher};
+    use crate::config::CertificateConf;
+    use ahash::AHashMap;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_certificate_watcher() {
+        let cert = tempfile::NamedTempFile::new().unwrap();
+        let key = tempfile::NamedTempFile::new().unwrap();
+        let cert_file = cert.path().to_string_lossy().to_string();
+        let key_file = key.path().to_string_lossy().to_string();
+        let mut certificates = HashMap::new();
+        certificates.insert(
+            "pingap".to_string(),
+            CertificateConf {
+                tls_cert: Some(cert_file.clone()),
+                tls_key: Some(key_file.clone()),
+                tls_chain: Some("-----BEGIN CERTIFICATE-----".to_string()),
+                certificate_file: Some("/tmp/not-exists.json".to_string()),
+                ..Default::default()
+            },
+        );
+        let files = get_certificate_files(&certificates);
+        assert_eq!(vec![cert_file.clone(), key_file.clone()], files);
+
+        let watcher = CertificateWatcher {
+            modified_times: Mutex::new(AHashMap::new()),
+        };
+        assert_eq!(true, watcher.get_modified_files(&files).is_empty());
+        assert_eq!(true, watcher.get_modified_files(&files).is_empty());
+
+        cert.as_file()
+            .set_modified(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(vec![cert_file], watcher.get_modified_files(&files));
+        assert_eq!(true, watcher.get_modified_files(&files).is_empty());
+    }
+}
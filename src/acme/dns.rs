@@ -0,0 +1,538 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, Result};
+use crate::util;
+use async_trait::async_trait;
+use hickory_proto::op::{update_message, Message, ResponseCode};
+use hickory_proto::rr::dnssec::rdata::tsig::TsigAlgorithm;
+use hickory_proto::rr::dnssec::tsig::TSigner;
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{Name, RData, RecordSet, RecordType};
+use humantime::parse_duration;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::info;
+use url::Url;
+
+/// The solver of dns-01 challenge, it adds the txt record
+/// before validation and removes it after validation.
+#[async_trait]
+pub trait DnsSolver: Send + Sync {
+    /// Add the txt record of challenge.
+    async fn present(&self, fqdn: &str, value: &str) -> Result<()>;
+    /// Remove the txt record of challenge.
+    async fn cleanup(&self, fqdn: &str, value: &str) -> Result<()>;
+}
+
+fn new_fail_error(category: &str, message: String) -> Error {
+    Error::Fail {
+        category: category.to_string(),
+        message,
+    }
+}
+
+/// Update the txt record by rfc2136 dynamic update,
+/// the message is signed by tsig if the key is set.
+struct Rfc2136Solver {
+    // the server is resolved when sending message,
+    // so validating the config doesn't block on dns lookup
+    host: String,
+    port: u16,
+    zone: Name,
+    signer: Option<TSigner>,
+    ttl: u32,
+    timeout: Duration,
+}
+
+impl Rfc2136Solver {
+    fn new(url: &Url) -> Result<Self> {
+        let new_error = |message: String| new_fail_error("rfc2136", message);
+        let host = url.host_str().unwrap_or_default();
+        if host.is_empty() {
+            return Err(new_error("server is required".to_string()));
+        }
+        let mut zone = "".to_string();
+        let mut key_name = "".to_string();
+        let mut key_secret = "".to_string();
+        let mut key_algorithm = "hmac-sha256".to_string();
+        let mut ttl = 60;
+        let mut timeout = Duration::from_secs(10);
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "zone" => zone = value.to_string(),
+                "key_name" => key_name = value.to_string(),
+                "key_secret" => key_secret = value.to_string(),
+                "key_algorithm" => key_algorithm = value.to_lowercase(),
+                "ttl" => ttl = value.parse().unwrap_or(ttl),
+                "timeout" => {
+                    timeout = parse_duration(&value)
+                        .map_err(|e| new_error(e.to_string()))?
+                },
+                _ => {},
+            }
+        }
+        if zone.is_empty() {
+            return Err(new_error("zone is required".to_string()));
+        }
+        let zone = Name::from_ascii(format!("{}.", zone.trim_end_matches('.')))
+            .map_err(|e| new_error(e.to_string()))?;
+        let signer = if key_name.is_empty() {
+            None
+        } else {
+            let algorithm = match key_algorithm.as_str() {
+                "hmac-sha256" => TsigAlgorithm::HmacSha256,
+                "hmac-sha384" => TsigAlgorithm::HmacSha384,
+                "hmac-sha512" => TsigAlgorithm::HmacSha512,
+                _ => {
+                    return Err(new_error(format!(
+                        "key algorithm({key_algorithm}) is not supported"
+                    )))
+                },
+            };
+            let key = util::base64_decode(&key_secret)
+                .map_err(|e| new_error(e.to_string()))?;
+            let name = Name::from_ascii(&key_name)
+                .map_err(|e| new_error(e.to_string()))?;
+            let signer = TSigner::new(key, algorithm, name, 300)
+                .map_err(|e| new_error(e.to_string()))?;
+            Some(signer)
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port: url.port().unwrap_or(53),
+            zone,
+            signer,
+            ttl,
+            timeout,
+        })
+    }
+    fn new_record_set(&self, fqdn: &str, value: &str) -> Result<RecordSet> {
+        let name = Name::from_ascii(fqdn)
+            .map_err(|e| new_fail_error("rfc2136", e.to_string()))?;
+        if !self.zone.zone_of(&name) {
+            return Err(new_fail_error(
+                "rfc2136",
+                format!("{fqdn} is not in zone {}", self.zone),
+            ));
+        }
+        let mut rrset = RecordSet::with_ttl(name, RecordType::TXT, self.ttl);
+        rrset.add_rdata(RData::TXT(TXT::new(vec![value.to_string()])));
+        Ok(rrset)
+    }
+    async fn send(&self, mut message: Message) -> Result<()> {
+        let new_error = |message: String| new_fail_error("rfc2136", message);
+        // the verifier checks the tsig of response with the request mac
+        let verifier = if let Some(signer) = &self.signer {
+            message
+                .finalize(signer, util::now().as_secs() as u32)
+                .map_err(|e| new_error(e.to_string()))?
+        } else {
+            None
+        };
+        let buf = message.to_vec().map_err(|e| new_error(e.to_string()))?;
+        let server = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await
+            .map_err(|e| new_error(e.to_string()))?
+            .next()
+            .ok_or_else(|| new_error(format!("{} is invalid", self.host)))?;
+        let addr = if server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(addr)
+            .await
+            .map_err(|e| new_error(e.to_string()))?;
+        // only the datagram from the dns server is received
+        socket
+            .connect(server)
+            .await
+            .map_err(|e| new_error(e.to_string()))?;
+        socket
+            .send(&buf)
+            .await
+            .map_err(|e| new_error(e.to_string()))?;
+        let mut data = vec![0; 4096];
+        let size = tokio::time::timeout(self.timeout, socket.recv(&mut data))
+            .await
+            .map_err(|e| new_error(e.to_string()))?
+            .map_err(|e| new_error(e.to_string()))?;
+        let resp = if let Some(mut verify) = verifier {
+            verify(&data[..size])
+                .map_err(|e| new_error(format!("verify tsig fail, {e}")))?
+                .into_message()
+        } else {
+            Message::from_vec(&data[..size])
+                .map_err(|e| new_error(e.to_string()))?
+        };
+        if resp.id() != message.id() {
+            return Err(new_error("response id is mismatched".to_string()));
+        }
+        if resp.response_code() != ResponseCode::NoError {
+            return Err(new_error(format!(
+                "update fail, code: {}",
+                resp.response_code()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsSolver for Rfc2136Solver {
+    async fn present(&self, fqdn: &str, value: &str) -> Result<()> {
+        let rrset = self.new_record_set(fqdn, value)?;
+        self.send(update_message::append(
+            rrset,
+            self.zone.clone(),
+            false,
+            false,
+        ))
+        .await
+    }
+    async fn cleanup(&self, fqdn: &str, value: &str) -> Result<()> {
+        let rrset = self.new_record_set(fqdn, value)?;
+        self.send(update_message::delete_by_rdata(
+            rrset,
+            self.zone.clone(),
+            false,
+        ))
+        .await
+    }
+}
+
+/// Run the command as `{cmd} present|cleanup {fqdn} {value}`,
+/// it's failed if the exit code is not zero.
+struct ExecSolver {
+    cmd: String,
+}
+
+impl ExecSolver {
+    async fn run(&self, action: &str, fqdn: &str, value: &str) -> Result<()> {
+        let output = tokio::process::Command::new(&self.cmd)
+            .args([action, fqdn, value])
+            .output()
+            .await
+            .map_err(|e| new_fail_error("exec", e.to_string()))?;
+        if !output.status.success() {
+            return Err(new_fail_error(
+                "exec",
+                format!(
+                    "{} {action} fail, {}",
+                    self.cmd,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsSolver for ExecSolver {
+    async fn present(&self, fqdn: &str, value: &str) -> Result<()> {
+        self.run("present", fqdn, value).await
+    }
+    async fn cleanup(&self, fqdn: &str, value: &str) -> Result<()> {
+        self.run("cleanup", fqdn, value).await
+    }
+}
+
+/// Post the json of challenge to the webhook,
+/// it's failed if the response status is not 2xx.
+struct WebhookSolver {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl WebhookSolver {
+    async fn post(&self, action: &str, fqdn: &str, value: &str) -> Result<()> {
+        let resp = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({
+                "action": action,
+                "fqdn": fqdn,
+                "value": value,
+            }))
+            .send()
+            .await
+            .map_err(|e| new_fail_error("webhook", e.to_string()))?;
+        if !resp.status().is_success() {
+            return Err(new_fail_error(
+                "webhook",
+                format!("{action} fail, status: {}", resp.status()),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DnsSolver for WebhookSolver {
+    async fn present(&self, fqdn: &str, value: &str) -> Result<()> {
+        self.post("present", fqdn, value).await
+    }
+    async fn cleanup(&self, fqdn: &str, value: &str) -> Result<()> {
+        self.post("cleanup", fqdn, value).await
+    }
+}
+
+/// The dns-01 challenge of acme, the txt record is updated by solver,
+/// and validation starts after the propagation delay.
+pub struct DnsChallenge {
+    solver: Box<dyn DnsSolver>,
+    pub propagation: Duration,
+}
+
+impl DnsChallenge {
+    /// Create a dns challenge from provider url:
+    /// rfc2136://ns1.example.com:53?zone=example.com&key_name=acme&key_secret=base64
+    /// exec:///usr/local/bin/dns-hook
+    /// https://example.com/acme/dns
+    /// The `propagation` query sets the delay before validation(default 30s).
+    pub fn new(value: &str) -> Result<Self> {
+        let mut url = Url::parse(value)
+            .map_err(|e| new_fail_error("dns_provider", e.to_string()))?;
+        let mut propagation = Duration::from_secs(30);
+        let mut pairs = vec![];
+        for (key, value) in url.query_pairs() {
+            if key == "propagation" {
+                propagation = parse_duration(&value).map_err(|e| {
+                    new_fail_error("dns_provider", e.to_string())
+                })?;
+            } else {
+                pairs.push((key.to_string(), value.to_string()));
+            }
+        }
+        if pairs.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+        let solver: Box<dyn DnsSolver> = match url.scheme() {
+            "rfc2136" => Box::new(Rfc2136Solver::new(&url)?),
+            "exec" => Box::new(ExecSolver {
+                cmd: url.path().to_string(),
+            }),
+            "http" | "https" => Box::new(WebhookSolver {
+                url: url.to_string(),
+                client: reqwest::Client::builder()
+                    .timeout(Duration::from_secs(30))
+                    .build()
+                    .map_err(|e| {
+                        new_fail_error("dns_provider", e.to_string())
+                    })?,
+            }),
+            scheme => {
+                return Err(new_fail_error(
+                    "dns_provider",
+                    format!("{scheme} is not supported"),
+                ))
+            },
+        };
+        Ok(Self {
+            solver,
+            propagation,
+        })
+    }
+    /// Add the txt record `_acme-challenge.{domain}` for validation.
+    pub async fn present(&self, domain: &str, value: &str) -> Result<()> {
+        let fqdn = get_challenge_fqdn(domain);
+        info!(fqdn, "present dns challenge");
+        self.solver.present(&fqdn, value).await
+    }
+    /// Remove the txt record `_acme-challenge.{domain}`.
+    pub async fn cleanup(&self, domain: &str, value: &str) -> Result<()> {
+        let fqdn = get_challenge_fqdn(domain);
+        info!(fqdn, "cleanup dns challenge");
+        self.solver.cleanup(&fqdn, value).await
+    }
+}
+
+fn get_challenge_fqdn(domain: &str) -> String {
+    let domain = domain.trim_start_matches("*.").trim_end_matches('.');
+    format!("_acme-challenge.{domain}.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_challenge_fqdn, DnsChallenge};
+    use crate::util;
+    use hickory_proto::op::{Message, MessageType, OpCode, UpdateMessage};
+    use hickory_proto::rr::dnssec::rdata::tsig::{
+        make_tsig_record, message_tbs, TsigAlgorithm, TSIG,
+    };
+    use hickory_proto::rr::dnssec::rdata::DNSSECRData;
+    use hickory_proto::rr::dnssec::tsig::TSigner;
+    use hickory_proto::rr::{Name, RData};
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+
+    #[test]
+    fn test_dns_challenge_params() {
+        assert_eq!(
+            "_acme-challenge.pingap.io.",
+            get_challenge_fqdn("*.pingap.io")
+        );
+        let challenge =
+            DnsChallenge::new("exec:///bin/true?propagation=5s").unwrap();
+        assert_eq!(Duration::from_secs(5), challenge.propagation);
+
+        let result = DnsChallenge::new("rfc2136://127.0.0.1");
+        assert_eq!(
+            "Lets encrypt fail, category: rfc2136, zone is required",
+            result.err().unwrap().to_string()
+        );
+        let result = DnsChallenge::new(
+            "rfc2136://127.0.0.1?zone=pingap.io&key_name=acme&key_secret=MTIz&key_algorithm=hmac-md5",
+        );
+        assert_eq!(
+            "Lets encrypt fail, category: rfc2136, key algorithm(hmac-md5) is not supported",
+            result.err().unwrap().to_string()
+        );
+        // the server is resolved lazily
+        assert_eq!(
+            true,
+            DnsChallenge::new("rfc2136://ns.pingap.invalid?zone=pingap.io")
+                .is_ok()
+        );
+        let result = DnsChallenge::new("ftp://127.0.0.1");
+        assert_eq!(
+            "Lets encrypt fail, category: dns_provider, ftp is not supported",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exec_dns_challenge() {
+        let challenge = DnsChallenge::new("exec:///bin/true").unwrap();
+        challenge.present("pingap.io", "abc").await.unwrap();
+        challenge.cleanup("pingap.io", "abc").await.unwrap();
+
+        let challenge = DnsChallenge::new("exec:///bin/false").unwrap();
+        assert_eq!(true, challenge.present("pingap.io", "abc").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_rfc2136_dns_challenge() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let signer = TSigner::new(
+                b"123456".to_vec(),
+                TsigAlgorithm::HmacSha256,
+                Name::from_ascii("acme").unwrap(),
+                300,
+            )
+            .unwrap();
+            let mut updates = vec![];
+            for index in 0..3 {
+                let mut buf = vec![0; 4096];
+                let (size, peer) = socket.recv_from(&mut buf).await.unwrap();
+                let req = Message::from_vec(&buf[..size]).unwrap();
+                assert_eq!(OpCode::Update, req.op_code());
+                // the tsig record is appended as additional
+                assert_eq!(true, req.signature().len() == 1);
+                let record = &req.updates()[0];
+                let value = match record.data() {
+                    Some(RData::TXT(txt)) => txt.to_string(),
+                    _ => "".to_string(),
+                };
+                updates.push(format!(
+                    "{} {} {value}",
+                    record.name(),
+                    record.dns_class()
+                ));
+                let mut resp = Message::new();
+                resp.set_id(req.id())
+                    .set_message_type(MessageType::Response)
+                    .set_op_code(OpCode::Update);
+                // the last response is not signed
+                if index < 2 {
+                    let Some(RData::DNSSEC(DNSSECRData::TSIG(req_tsig))) =
+                        req.signature()[0].data()
+                    else {
+                        panic!("tsig is not found");
+                    };
+                    let pre_tsig = TSIG::new(
+                        TsigAlgorithm::HmacSha256,
+                        util::now().as_secs(),
+                        300,
+                        vec![],
+                        resp.id(),
+                        0,
+                        vec![],
+                    );
+                    let tbs = message_tbs(
+                        Some(req_tsig.mac()),
+                        &resp,
+                        &pre_tsig,
+                        signer.signer_name(),
+                    )
+                    .unwrap();
+                    let mac = signer.sign(&tbs).unwrap();
+                    resp.add_tsig(make_tsig_record(
+                        signer.signer_name().clone(),
+                        pre_tsig.set_mac(mac),
+                    ));
+                }
+                // the forged response from other address is ignored
+                if index == 0 {
+                    let forged = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                    let mut forged_resp = Message::new();
+                    forged_resp
+                        .set_id(req.id())
+                        .set_message_type(MessageType::Response)
+                        .set_op_code(OpCode::Update);
+                    forged
+                        .send_to(&forged_resp.to_vec().unwrap(), peer)
+                        .await
+                        .unwrap();
+                }
+                socket.send_to(&resp.to_vec().unwrap(), peer).await.unwrap();
+            }
+            updates
+        });
+        let challenge = DnsChallenge::new(&format!(
+            "rfc2136://{addr}?zone=pingap.io&key_name=acme&key_secret=MTIzNDU2&timeout=3s"
+        ))
+        .unwrap();
+        challenge.present("*.pingap.io", "token").await.unwrap();
+        challenge.cleanup("*.pingap.io", "token").await.unwrap();
+        let result = challenge.present("*.pingap.io", "token").await;
+        assert_eq!(
+            true,
+            result.err().unwrap().to_string().starts_with(
+                "Lets encrypt fail, category: rfc2136, verify tsig fail"
+            )
+        );
+        assert_eq!(
+            vec![
+                "_acme-challenge.pingap.io. IN token".to_string(),
+                "_acme-challenge.pingap.io. NONE token".to_string(),
+                "_acme-challenge.pingap.io. IN token".to_string(),
+            ],
+            server.await.unwrap()
+        );
+
+        let result = challenge.present("pingap.com", "token").await;
+        assert_eq!(
+            "Lets encrypt fail, category: rfc2136, _acme-challenge.pingap.com. is not in zone pingap.io.",
+            result.err().unwrap().to_string()
+        );
+    }
+}
@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{get_certificate_info, Certificate, Error, Result};
+use super::{get_certificate_info, Certificate, DnsChallenge, Error, Result};
 use crate::config::get_current_config;
 use crate::http_extra::HttpResponse;
 use crate::proxy::init_certificates;
@@ -20,16 +20,25 @@ use crate::service::{CommonServiceTask, ServiceTask};
 use crate::state::State;
 use crate::util;
 use crate::webhook;
+use ahash::AHashMap;
 use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use http::StatusCode;
 use instant_acme::{
-    Account, ChallengeType, Identifier, LetsEncrypt, NewAccount, NewOrder,
+    Account, AccountCredentials, Authorization, ChallengeType,
+    ExternalAccountKey, Identifier, LetsEncrypt, NewAccount, NewOrder, Order,
     OrderStatus,
 };
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use pingora::proxy::Session;
+use pingora::tls::error::ErrorStack;
+use pingora::tls::pkey::{PKey, Private};
+use pingora::tls::x509::X509;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use std::time::Duration;
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
@@ -48,6 +57,220 @@ struct LetsEncryptService {
     certificate_file: PathBuf,
     // the domains list, they should be the same primary domain name
     domains: Vec<String>,
+    challenge: AcmeChallenge,
+    account: AcmeAccountParams,
+}
+
+/// The challenge type of acme
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum AcmeChallenge {
+    #[default]
+    Http01,
+    TlsAlpn01,
+    // the dns provider which updates the txt record
+    Dns01(String),
+}
+
+/// The alpn protocol of tls-alpn-01 challenge
+pub static ACME_TLS_ALPN_NAME: &[u8] = b"acme-tls/1";
+
+type TlsAlpnCertificate = Arc<(X509, PKey<Private>)>;
+
+// tls-alpn-01 challenge certificates, the key is domain
+static TLS_ALPN_CHALLENGE: Lazy<RwLock<AHashMap<String, TlsAlpnCertificate>>> =
+    Lazy::new(|| RwLock::new(AHashMap::new()));
+
+/// Get the certificate of tls-alpn-01 challenge for the domain.
+pub fn get_tls_alpn_certificate(domain: &str) -> Option<TlsAlpnCertificate> {
+    TLS_ALPN_CHALLENGE
+        .read()
+        .ok()
+        .and_then(|certs| certs.get(domain).cloned())
+}
+
+/// Generate the self-signed certificate with acmeIdentifier extension,
+/// which is served for the `acme-tls/1` handshake of validation.
+fn new_tls_alpn_certificate(
+    domain: &str,
+    digest: &[u8],
+) -> Result<TlsAlpnCertificate> {
+    let mut params = rcgen::CertificateParams::new(vec![domain.to_string()])
+        .map_err(|e| Error::Rcgen {
+            category: "new_tls_alpn_params".to_string(),
+            source: e,
+        })?;
+    params
+        .custom_extensions
+        .push(rcgen::CustomExtension::new_acme_identifier(digest));
+    let key_pair = rcgen::KeyPair::generate().map_err(|e| Error::Rcgen {
+        category: "generate_key_pair".to_string(),
+        source: e,
+    })?;
+    let cert = params.self_signed(&key_pair).map_err(|e| Error::Rcgen {
+        category: "self_signed".to_string(),
+        source: e,
+    })?;
+    let new_error = |e: ErrorStack| Error::Fail {
+        category: "tls_alpn_certificate".to_string(),
+        message: e.to_string(),
+    };
+    let cert = X509::from_der(cert.der()).map_err(new_error)?;
+    let key = PKey::private_key_from_der(&key_pair.serialize_der())
+        .map_err(new_error)?;
+    Ok(Arc::new((cert, key)))
+}
+
+/// The params of acme account
+#[derive(Debug, Clone, Default)]
+pub struct AcmeAccountParams {
+    // the acme provider, it can be a known provider or directory url
+    pub provider: String,
+    // the contact email of account
+    pub email: Option<String>,
+    // the key id of external account binding
+    pub eab_kid: Option<String>,
+    // the hmac key(base64url) of external account binding
+    pub eab_hmac_key: Option<String>,
+}
+
+static ZERO_SSL_DIRECTORY_URL: &str = "https://acme.zerossl.com/v2/DV90";
+static GOOGLE_DIRECTORY_URL: &str =
+    "https://dv.acme-v02.api.pki.goog/directory";
+
+impl AcmeAccountParams {
+    /// Get the directory url of acme provider,
+    /// the default provider is let's encrypt.
+    pub fn get_directory_url(&self) -> String {
+        match self.provider.as_str() {
+            "lets_encrypt_staging" => LetsEncrypt::Staging.url().to_string(),
+            "zero_ssl" => ZERO_SSL_DIRECTORY_URL.to_string(),
+            "google" => GOOGLE_DIRECTORY_URL.to_string(),
+            value
+                if value.starts_with("https://")
+                    || value.starts_with("http://") =>
+            {
+                value.to_string()
+            },
+            _ => LetsEncrypt::Production.url().to_string(),
+        }
+    }
+    fn get_contact(&self) -> Vec<String> {
+        self.email
+            .iter()
+            .filter(|email| !email.is_empty())
+            .map(|email| format!("mailto:{email}"))
+            .collect()
+    }
+}
+
+/// The account credentials which are saved for reusing,
+/// it will be recreated if the directory or contact is changed.
+#[derive(Deserialize, Serialize)]
+struct AcmeAccount {
+    directory_url: String,
+    contact: Vec<String>,
+    credentials: AccountCredentials,
+}
+
+fn get_account_file(certificate_file: &Path) -> PathBuf {
+    let mut file = certificate_file.as_os_str().to_owned();
+    file.push(".account");
+    PathBuf::from(file)
+}
+
+/// Save the credentials of account, only the owner can read it
+/// because it contains the private key.
+async fn save_account_file(file: &Path, buf: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut f = options.open(file).await?;
+    // the mode is only set for the new file
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        f.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    f.write_all(buf).await?;
+    f.flush().await
+}
+
+/// Get the acme account from the saved credentials,
+/// or create a new account and save its credentials.
+async fn get_acme_account(
+    certificate_file: &Path,
+    params: &AcmeAccountParams,
+) -> Result<Account> {
+    let directory_url = params.get_directory_url();
+    let contact = params.get_contact();
+    let account_file = get_account_file(certificate_file);
+    if let Ok(buf) = fs::read(&account_file).await {
+        match serde_json::from_slice::<AcmeAccount>(&buf) {
+            Ok(account)
+                if account.directory_url == directory_url
+                    && account.contact == contact =>
+            {
+                return Account::from_credentials(account.credentials)
+                    .await
+                    .map_err(|e| Error::Instant {
+                        category: "restore_account".to_string(),
+                        source: e,
+                    });
+            },
+            Ok(_) => info!(directory_url, "acme account is changed"),
+            Err(e) => error!(error = e.to_string(), "parse acme account fail"),
+        }
+    }
+    let external_account = if let (Some(kid), Some(hmac_key)) =
+        (&params.eab_kid, &params.eab_hmac_key)
+    {
+        let key = URL_SAFE_NO_PAD
+            .decode(hmac_key.trim_end_matches('='))
+            .map_err(|e| Error::Fail {
+                category: "eab_hmac_key".to_string(),
+                message: e.to_string(),
+            })?;
+        Some(ExternalAccountKey::new(kid.to_string(), &key))
+    } else {
+        None
+    };
+    let (account, credentials) = Account::create(
+        &NewAccount {
+            contact: &contact
+                .iter()
+                .map(|item| item.as_str())
+                .collect::<Vec<_>>(),
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        &directory_url,
+        external_account.as_ref(),
+    )
+    .await
+    .map_err(|e| Error::Instant {
+        category: "create_account".to_string(),
+        source: e,
+    })?;
+    let buf = serde_json::to_vec(&AcmeAccount {
+        directory_url,
+        contact,
+        credentials,
+    })
+    .map_err(|e| Error::SerdeJson {
+        category: "serde_account".to_string(),
+        source: e,
+    })?;
+    // the account can be created again, so ignore the save error
+    if let Err(e) = save_account_file(&account_file, &buf).await {
+        error!(
+            error = e.to_string(),
+            file = format!("{account_file:?}"),
+            "save acme account fail"
+        );
+    }
+    Ok(account)
 }
 
 static WELL_KNOWN_PAHT_PREFIX: &str = "/.well-known/acme-challenge/";
@@ -57,6 +280,8 @@ static WELL_KNOWN_PAHT_PREFIX: &str = "/.well-known/acme-challenge/";
 pub fn new_lets_encrypt_service(
     certificate_file: PathBuf,
     domains: Vec<String>,
+    challenge: AcmeChallenge,
+    account: AcmeAccountParams,
 ) -> CommonServiceTask {
     let mut domains = domains;
     // sort domain order
@@ -67,6 +292,8 @@ pub fn new_lets_encrypt_service(
         LetsEncryptService {
             certificate_file,
             domains,
+            challenge,
+            account,
         },
     )
 }
@@ -87,7 +314,14 @@ impl ServiceTask for LetsEncryptService {
         if !should_renew_now {
             return None;
         }
-        match new_lets_encrypt(&self.certificate_file, domains, true).await {
+        match new_lets_encrypt(
+            &self.certificate_file,
+            domains,
+            &self.account,
+            &self.challenge,
+        )
+        .await
+        {
             Ok(()) => {
                 info!(domains = domains.join(","), "renew certificate success");
                 webhook::send(webhook::SendNotificationParams {
@@ -158,36 +392,75 @@ pub async fn handle_lets_encrypt(
     Ok(false)
 }
 
+/// Set the challenges ready and wait for the order to be validated.
+async fn wait_order_ready(
+    order: &mut Order,
+    challenges: &[(String, String)],
+    detail_url: Option<&Authorization>,
+) -> Result<OrderStatus> {
+    // set challenge ready for verification
+    for (_, url) in challenges {
+        order
+            .set_challenge_ready(url)
+            .await
+            .map_err(|e| Error::Instant {
+                category: "set_challenge_ready".to_string(),
+                source: e,
+            })?;
+    }
+
+    // get order state, retry later if fail
+    let mut tries = 1u8;
+    let mut delay = Duration::from_millis(250);
+    loop {
+        let state = order.state();
+        info!(status = format!("{:?}", state.status), "get order status");
+        if let OrderStatus::Ready | OrderStatus::Invalid | OrderStatus::Valid =
+            state.status
+        {
+            return Ok(state.status);
+        }
+        order.refresh().await.map_err(|e| Error::Instant {
+            category: "refresh_order".to_string(),
+            source: e,
+        })?;
+
+        delay *= 2;
+        tries += 1;
+        match tries < 10 {
+            true => info!(
+                delay = format!("{delay:?}"),
+                "Order is not ready, waiting"
+            ),
+            false => {
+                return Err(Error::Fail {
+                    category: "retry_too_many".to_string(),
+                    message: format!("Giving up: order is not ready. For details, see the url: {detail_url:?}"),
+                });
+            },
+        }
+        tokio::time::sleep(delay).await;
+    }
+}
+
 /// Get the new cert from lets encrypt for all domains.
 /// The cert will be saved if success.
 async fn new_lets_encrypt(
     certificate_file: &PathBuf,
     domains: &[String],
-    production: bool,
+    account: &AcmeAccountParams,
+    challenge: &AcmeChallenge,
 ) -> Result<()> {
+    let dns_challenge = if let AcmeChallenge::Dns01(provider) = challenge {
+        Some(DnsChallenge::new(provider)?)
+    } else {
+        None
+    };
     let mut domains: Vec<String> = domains.to_vec();
     // sort domain for comparing later
     domains.sort();
     info!(domains = domains.join(","), "acme from let's encrypt");
-    let url = if production {
-        LetsEncrypt::Production.url()
-    } else {
-        LetsEncrypt::Staging.url()
-    };
-    let (account, _) = Account::create(
-        &NewAccount {
-            contact: &[],
-            terms_of_service_agreed: true,
-            only_return_existing: false,
-        },
-        url,
-        None,
-    )
-    .await
-    .map_err(|e| Error::Instant {
-        category: "create_account".to_string(),
-        source: e,
-    })?;
+    let account = get_acme_account(certificate_file, account).await?;
 
     let mut order = account
         .new_order(&NewOrder {
@@ -216,99 +489,113 @@ async fn new_lets_encrypt(
             source: e,
         })?;
     let mut challenges = Vec::with_capacity(authorizations.len());
+    let mut dns_records = vec![];
 
-    for authz in &authorizations {
-        info!(
-            status = format!("{:?}", authz.status),
-            "acme from let's encrypt"
-        );
-        match authz.status {
-            instant_acme::AuthorizationStatus::Pending => {},
-            instant_acme::AuthorizationStatus::Valid => continue,
-            _ => todo!(),
-        }
-
-        let challenge = authz
-            .challenges
-            .iter()
-            .find(|c| c.r#type == ChallengeType::Http01)
-            .ok_or_else(|| Error::NotFound {
-                message: "Http01 challenge not found".to_string(),
-            })?;
+    let detail_url = authorizations.first();
+    // the presented records are cleaned up even if it fails halfway
+    let result = async {
+        for authz in &authorizations {
+            info!(
+                status = format!("{:?}", authz.status),
+                "acme from let's encrypt"
+            );
+            match authz.status {
+                instant_acme::AuthorizationStatus::Pending => {},
+                instant_acme::AuthorizationStatus::Valid => continue,
+                status => {
+                    return Err(Error::Fail {
+                        category: "authorization_status".to_string(),
+                        message: format!(
+                            "authorization is not pending, status: {status:?}"
+                        ),
+                    });
+                },
+            }
 
-        let instant_acme::Identifier::Dns(identifier) = &authz.identifier;
+            let instant_acme::Identifier::Dns(identifier) = &authz.identifier;
+            let challenge_type = match challenge {
+                AcmeChallenge::Http01 => ChallengeType::Http01,
+                AcmeChallenge::TlsAlpn01 => ChallengeType::TlsAlpn01,
+                AcmeChallenge::Dns01(_) => ChallengeType::Dns01,
+            };
 
-        let key_auth = order.key_authorization(challenge);
+            let challenge = authz
+                .challenges
+                .iter()
+                .find(|c| c.r#type == challenge_type)
+                .ok_or_else(|| Error::NotFound {
+                    message: format!("{challenge_type:?} challenge not found"),
+                })?;
 
-        // http://your-domain/.well-known/acme-challenge/<TOKEN>
-        let well_known_path =
-            format!("{WELL_KNOWN_PAHT_PREFIX}{}", challenge.token);
-        info!(well_known_path, "let's encrypt well known path",);
+            let key_auth = order.key_authorization(challenge);
 
-        // save token for verification later
-        let mut map = get_lets_encrypt_challenge().lock().await;
-        map.insert(well_known_path, key_auth.as_str().to_string());
+            if let Some(dns_challenge) = &dns_challenge {
+                // _acme-challenge.your-domain TXT <DIGEST>
+                let value = key_auth.dns_value();
+                dns_challenge.present(identifier, &value).await?;
+                dns_records.push((identifier.to_string(), value));
+            } else if challenge_type == ChallengeType::TlsAlpn01 {
+                // the certificate is served for acme-tls/1 handshake
+                let cert = new_tls_alpn_certificate(
+                    identifier,
+                    key_auth.digest().as_ref(),
+                )?;
+                if let Ok(mut certs) = TLS_ALPN_CHALLENGE.write() {
+                    certs.insert(identifier.to_string(), cert);
+                }
+            } else {
+                // http://your-domain/.well-known/acme-challenge/<TOKEN>
+                let well_known_path =
+                    format!("{WELL_KNOWN_PAHT_PREFIX}{}", challenge.token);
+                info!(well_known_path, "let's encrypt well known path",);
 
-        challenges.push((identifier, &challenge.url));
-    }
-    // set challenge ready for verification
-    for (_, url) in &challenges {
-        order
-            .set_challenge_ready(url)
-            .await
-            .map_err(|e| Error::Instant {
-                category: "set_challenge_ready".to_string(),
-                source: e,
-            })?;
-    }
+                // save token for verification later
+                let mut map = get_lets_encrypt_challenge().lock().await;
+                map.insert(well_known_path, key_auth.as_str().to_string());
+            }
 
-    // get order state, retry later if fail
-    let mut tries = 1u8;
-    let mut delay = Duration::from_millis(250);
-    let detail_url = authorizations.first();
-    let state = loop {
-        let state = order.state();
-        info!(status = format!("{:?}", state.status), "get order status");
-        if let OrderStatus::Ready | OrderStatus::Invalid | OrderStatus::Valid =
-            state.status
-        {
-            break state;
+            challenges.push((identifier.to_string(), challenge.url.clone()));
+        }
+        if let Some(dns_challenge) = &dns_challenge {
+            if !dns_records.is_empty() {
+                info!(
+                    delay = format!("{:?}", dns_challenge.propagation),
+                    "wait for dns propagation"
+                );
+                tokio::time::sleep(dns_challenge.propagation).await;
+            }
         }
-        order.refresh().await.map_err(|e| Error::Instant {
-            category: "refresh_order".to_string(),
-            source: e,
-        })?;
 
-        delay *= 2;
-        tries += 1;
-        match tries < 10 {
-            true => info!(
-                delay = format!("{delay:?}"),
-                "Order is not ready, waiting"
-            ),
-            false => {
-                return Err(Error::Fail {
-                    category: "retry_too_many".to_string(),
-                    message: format!("Giving up: order is not ready. For details, see the url: {detail_url:?}"),
-                });
-            },
+        wait_order_ready(&mut order, &challenges, detail_url).await
+    }
+    .await;
+    // the txt records and certificates are useless after validation
+    if let Some(dns_challenge) = &dns_challenge {
+        for (domain, value) in dns_records.iter() {
+            if let Err(e) = dns_challenge.cleanup(domain, value).await {
+                error!(error = e.to_string(), domain, "cleanup dns fail");
+            }
         }
-        tokio::time::sleep(delay).await;
-    };
-    if state.status == OrderStatus::Invalid {
+    }
+    if *challenge == AcmeChallenge::TlsAlpn01 {
+        if let Ok(mut certs) = TLS_ALPN_CHALLENGE.write() {
+            for (domain, _) in challenges.iter() {
+                certs.remove(domain);
+            }
+        }
+    }
+    let status = result?;
+    if status == OrderStatus::Invalid {
         return Err(Error::Fail {
             category: "order_invalid".to_string(),
             message: format!("order is invalid, check {detail_url:?}"),
         });
     }
 
-    // generate certificate
-    let mut names = Vec::with_capacity(challenges.len());
-    for (identifier, _) in challenges {
-        names.push(identifier.to_owned());
-    }
+    // generate certificate, the names should be the same as order,
+    // the identifier of wildcard authorization is without `*.`
     let mut params =
-        rcgen::CertificateParams::new(names.clone()).map_err(|e| {
+        rcgen::CertificateParams::new(domains.clone()).map_err(|e| {
             Error::Rcgen {
                 category: "new_params".to_string(),
                 source: e,
@@ -389,16 +676,86 @@ async fn new_lets_encrypt(
 
 #[cfg(test)]
 mod tests {
-    use super::new_lets_encrypt;
+    use super::{
+        get_account_file, new_lets_encrypt, new_tls_alpn_certificate,
+        save_account_file, AcmeAccountParams, AcmeChallenge,
+    };
     use pretty_assertions::assert_eq;
     use std::path::Path;
 
+    #[test]
+    fn test_acme_account_params() {
+        let mut params = AcmeAccountParams {
+            provider: "lets_encrypt".to_string(),
+            email: Some("tree.xie@outlook.com".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "https://acme-v02.api.letsencrypt.org/directory",
+            params.get_directory_url()
+        );
+        assert_eq!(vec!["mailto:tree.xie@outlook.com"], params.get_contact());
+        params.provider = "zero_ssl".to_string();
+        assert_eq!(
+            "https://acme.zerossl.com/v2/DV90",
+            params.get_directory_url()
+        );
+        params.provider = "https://127.0.0.1:14000/dir".to_string();
+        assert_eq!("https://127.0.0.1:14000/dir", params.get_directory_url());
+
+        assert_eq!(
+            "/opt/pingap/cert.json.account",
+            get_account_file(Path::new("/opt/pingap/cert.json"))
+                .to_string_lossy()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_save_account_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("cert.json.account");
+        std::fs::write(&file, b"{}").unwrap();
+        std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o644))
+            .unwrap();
+
+        save_account_file(&file, b"{\"key\":\"pingap\"}")
+            .await
+            .unwrap();
+        let metadata = std::fs::metadata(&file).unwrap();
+        assert_eq!(0o600, metadata.permissions().mode() & 0o777);
+        assert_eq!(
+            r#"{"key":"pingap"}"#,
+            std::fs::read_to_string(&file).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_new_tls_alpn_certificate() {
+        let cert = new_tls_alpn_certificate("pingap.io", &[1; 32]).unwrap();
+        let (cert, key) = cert.as_ref();
+        assert_eq!(
+            "pingap.io",
+            cert.subject_alt_names().unwrap()[0].dnsname().unwrap()
+        );
+        assert_eq!(true, cert.public_key().unwrap().public_eq(key));
+        // the acmeIdentifier extension: 1.3.6.1.5.5.7.1.31
+        let der = cert.to_der().unwrap();
+        let oid = [0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f];
+        assert_eq!(true, der.windows(oid.len()).any(|item| item == oid));
+    }
+
     #[tokio::test]
     async fn test_new_lets_encrypt() {
         let result = new_lets_encrypt(
             &Path::new("~/pingap").to_path_buf(),
             &["pingap.io".to_string()],
-            false,
+            &AcmeAccountParams {
+                provider: "lets_encrypt_staging".to_string(),
+                ..Default::default()
+            },
+            &AcmeChallenge::Http01,
         )
         .await;
 
@@ -44,6 +44,8 @@ pub enum Error {
     },
     #[snafu(display("X509 error, category: {category}, {message}"))]
     X509 { category: String, message: String },
+    #[snafu(display("Ocsp error, category: {category}, {message}"))]
+    Ocsp { category: String, message: String },
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -114,12 +116,20 @@ impl Certificate {
     }
 }
 
+mod certificate_watcher;
+mod dns;
 mod lets_encrypt;
+mod ocsp;
 mod validity_checker;
 
+pub use certificate_watcher::new_certificate_watcher_service;
+pub use dns::DnsChallenge;
 pub use lets_encrypt::{
-    get_lets_encrypt_certificate, handle_lets_encrypt, new_lets_encrypt_service,
+    get_lets_encrypt_certificate, get_tls_alpn_certificate,
+    handle_lets_encrypt, new_lets_encrypt_service, AcmeAccountParams,
+    AcmeChallenge, ACME_TLS_ALPN_NAME,
 };
+pub use ocsp::{get_ocsp_staple, new_ocsp_stapling_service};
 pub use validity_checker::new_tls_validity_service;
 
 #[cfg(test)]
@@ -0,0 +1,580 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, Result};
+use crate::proxy::get_certificate_issuer_list;
+use crate::service::{CommonServiceTask, ServiceTask};
+use crate::util;
+use crate::webhook;
+use ahash::AHashMap;
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use foreign_types::ForeignTypeRef;
+use once_cell::sync::Lazy;
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::ocsp::{
+    OcspBasicResponseRef, OcspCertId, OcspCertIdRef, OcspCertStatus, OcspFlag,
+    OcspRequest, OcspResponse, OcspResponseStatus,
+};
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::verify::X509VerifyFlags;
+use openssl::x509::X509;
+use openssl_sys as ffi;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info};
+
+/// The ocsp response of certificate, which is stapled in tls handshake.
+#[derive(Debug, Clone, Default)]
+pub struct OcspStaple {
+    pub response: Vec<u8>,
+    pub this_update: i64,
+    pub next_update: i64,
+}
+
+impl OcspStaple {
+    /// The response is refreshed when it has passed half of its validity.
+    fn should_refresh(&self, now: i64) -> bool {
+        now >= self.this_update + (self.next_update - self.this_update) / 2
+    }
+}
+
+// the ocsp responses of certificates,
+// the key is the sha256 fingerprint of certificate
+static OCSP_STAPLES: Lazy<ArcSwap<AHashMap<String, Arc<OcspStaple>>>> =
+    Lazy::new(|| ArcSwap::from_pointee(AHashMap::new()));
+
+/// Get the valid ocsp response of certificate by its sha256 fingerprint.
+pub fn get_ocsp_staple(fingerprint: &str) -> Option<Arc<OcspStaple>> {
+    let staple = OCSP_STAPLES.load().get(fingerprint).cloned()?;
+    if staple.next_update <= util::now().as_secs() as i64 {
+        return None;
+    }
+    Some(staple)
+}
+
+fn new_ocsp_error(category: &str, message: String) -> Error {
+    Error::Ocsp {
+        category: category.to_string(),
+        message,
+    }
+}
+
+// Convert the asn1 time to timestamp by its difference from unix epoch.
+fn convert_asn1_time(value: &Asn1TimeRef) -> Result<i64> {
+    let diff = Asn1Time::from_unix(0)
+        .and_then(|epoch| epoch.diff(value))
+        .map_err(|e| new_ocsp_error("convert_time", e.to_string()))?;
+    Ok(diff.days as i64 * 86400 + diff.secs as i64)
+}
+
+/// Find the status of certificate in the ocsp response and check its
+/// validity period. `OcspBasicResponseRef::find_status` can't be used
+/// because it dereferences the optional next update without null check,
+/// so the response without next update is rejected here.
+fn find_ocsp_status(
+    basic: &OcspBasicResponseRef,
+    cert_id: &OcspCertIdRef,
+) -> Result<(i64, i64)> {
+    let mut status = ffi::V_OCSP_CERTSTATUS_UNKNOWN;
+    let mut reason = ffi::OCSP_REVOKED_STATUS_NOSTATUS;
+    let mut revocation_time = ptr::null_mut();
+    let mut this_update = ptr::null_mut();
+    let mut next_update = ptr::null_mut();
+    let found = unsafe {
+        ffi::OCSP_resp_find_status(
+            basic.as_ptr(),
+            cert_id.as_ptr(),
+            &mut status,
+            &mut reason,
+            &mut revocation_time,
+            &mut this_update,
+            &mut next_update,
+        )
+    };
+    if found != 1 || this_update.is_null() {
+        return Err(new_ocsp_error(
+            "find_status",
+            "certificate status not found".into(),
+        ));
+    }
+    let status = OcspCertStatus::from_raw(status);
+    if status != OcspCertStatus::GOOD {
+        return Err(new_ocsp_error(
+            "cert_status",
+            format!("certificate status: {status:?}"),
+        ));
+    }
+    if next_update.is_null() {
+        return Err(new_ocsp_error(
+            "next_update",
+            "next update of ocsp response is missing".into(),
+        ));
+    }
+    // five minutes for clock skew
+    if unsafe { ffi::OCSP_check_validity(this_update, next_update, 300, -1) }
+        != 1
+    {
+        return Err(new_ocsp_error(
+            "check_validity",
+            ErrorStack::get().to_string(),
+        ));
+    }
+    // the generalized time is a subtype of asn1 time
+    let (this_update, next_update) = unsafe {
+        (
+            Asn1TimeRef::from_ptr(this_update as *mut ffi::ASN1_TIME),
+            Asn1TimeRef::from_ptr(next_update as *mut ffi::ASN1_TIME),
+        )
+    };
+    Ok((
+        convert_asn1_time(this_update)?,
+        convert_asn1_time(next_update)?,
+    ))
+}
+
+/// Parse the ocsp response of certificate and verify it, the response is
+/// signed by the issuer or its delegated responder.
+fn parse_ocsp_staple(
+    data: &[u8],
+    cert: &X509,
+    issuer: &X509,
+) -> Result<OcspStaple> {
+    let ocsp_resp = OcspResponse::from_der(data)
+        .map_err(|e| new_ocsp_error("from_der", e.to_string()))?;
+    if ocsp_resp.status() != OcspResponseStatus::SUCCESSFUL {
+        return Err(new_ocsp_error(
+            "response_status",
+            format!("ocsp response status: {:?}", ocsp_resp.status()),
+        ));
+    }
+    let basic = ocsp_resp
+        .basic()
+        .map_err(|e| new_ocsp_error("basic", e.to_string()))?;
+    let verify_error = |e: ErrorStack| new_ocsp_error("verify", e.to_string());
+    // the issuer signing directly is trusted by the certs,
+    // and it's the trust anchor of the delegated responder,
+    // partial chain is allowed because the issuer is intermediate normally
+    let mut certs = Stack::new().map_err(verify_error)?;
+    certs.push(issuer.clone()).map_err(verify_error)?;
+    let mut builder = X509StoreBuilder::new().map_err(verify_error)?;
+    builder.add_cert(issuer.clone()).map_err(verify_error)?;
+    builder
+        .set_flags(X509VerifyFlags::PARTIAL_CHAIN)
+        .map_err(verify_error)?;
+    let store = builder.build();
+    basic
+        .verify(&certs, &store, OcspFlag::TRUST_OTHER)
+        .map_err(verify_error)?;
+
+    let cert_id = OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer)
+        .map_err(|e| new_ocsp_error("new_cert_id", e.to_string()))?;
+    let (this_update, next_update) = find_ocsp_status(&basic, &cert_id)?;
+
+    Ok(OcspStaple {
+        response: data.to_vec(),
+        this_update,
+        next_update,
+    })
+}
+
+/// Get the url of ocsp responder from the certificate.
+fn get_ocsp_responder(cert: &X509) -> Option<String> {
+    let responders = cert.ocsp_responders().ok()?;
+    responders.iter().next().map(|item| item.to_string())
+}
+
+/// Fetch the ocsp response of certificate from its responder.
+async fn fetch_ocsp_staple(
+    url: &str,
+    cert: &X509,
+    issuer: &X509,
+) -> Result<OcspStaple> {
+    let cert_id = OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer)
+        .map_err(|e| new_ocsp_error("new_cert_id", e.to_string()))?;
+    let mut req = OcspRequest::new()
+        .map_err(|e| new_ocsp_error("new_request", e.to_string()))?;
+    req.add_id(cert_id)
+        .map_err(|e| new_ocsp_error("add_id", e.to_string()))?;
+    let body = req
+        .to_der()
+        .map_err(|e| new_ocsp_error("to_der", e.to_string()))?;
+
+    let resp = reqwest::Client::new()
+        .post(url)
+        .header("Content-Type", "application/ocsp-request")
+        .timeout(Duration::from_secs(10))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| new_ocsp_error("request", e.to_string()))?;
+    let status = resp.status();
+    if !status.is_success() {
+        return Err(new_ocsp_error(
+            "request",
+            format!("ocsp responder response status: {status}"),
+        ));
+    }
+    let data = resp
+        .bytes()
+        .await
+        .map_err(|e| new_ocsp_error("read_response", e.to_string()))?;
+
+    parse_ocsp_staple(&data, cert, issuer)
+}
+
+struct OcspStapling {
+    // the webhook is sent when fetching fails this times continuously
+    max_failures: u32,
+    // the continuous fail count of certificates
+    failures: Mutex<AHashMap<String, u32>>,
+}
+
+#[async_trait]
+impl ServiceTask for OcspStapling {
+    async fn run(&self) -> Option<bool> {
+        let now = util::now().as_secs() as i64;
+        let staples = OCSP_STAPLES.load();
+        let mut updated_staples = AHashMap::new();
+        let mut fail_messages = vec![];
+        for (name, cert, issuer) in get_certificate_issuer_list() {
+            let Some(fingerprint) =
+                cert.digest(MessageDigest::sha256()).ok().map(hex::encode)
+            else {
+                continue;
+            };
+            if let Some(staple) = staples.get(&fingerprint) {
+                if !staple.should_refresh(now) {
+                    updated_staples.insert(fingerprint, staple.clone());
+                    continue;
+                }
+            }
+            // the certificate doesn't support ocsp
+            let Some(url) = get_ocsp_responder(&cert) else {
+                continue;
+            };
+            match fetch_ocsp_staple(&url, &cert, &issuer).await {
+                Ok(staple) => {
+                    info!(name, url, "fetch ocsp response success");
+                    if let Ok(mut failures) = self.failures.lock() {
+                        failures.remove(&name);
+                    }
+                    updated_staples.insert(fingerprint, Arc::new(staple));
+                },
+                Err(e) => {
+                    error!(
+                        error = e.to_string(),
+                        name, url, "fetch ocsp response fail"
+                    );
+                    // keep the previous response until it's expired
+                    if let Some(staple) = staples.get(&fingerprint) {
+                        updated_staples.insert(fingerprint, staple.clone());
+                    }
+                    let count = if let Ok(mut failures) = self.failures.lock() {
+                        let count = failures.entry(name.clone()).or_default();
+                        *count += 1;
+                        *count
+                    } else {
+                        0
+                    };
+                    if count > 0 && count % self.max_failures == 0 {
+                        fail_messages.push(format!(
+                            "{name} fetch ocsp response fail {count} times, {e}"
+                        ));
+                    }
+                },
+            }
+        }
+        OCSP_STAPLES.store(Arc::new(updated_staples));
+        if !fail_messages.is_empty() {
+            webhook::send(webhook::SendNotificationParams {
+                level: webhook::NotificationLevel::Warn,
+                category: webhook::NotificationCategory::OcspFail,
+                msg: fail_messages.join(";"),
+                ..Default::default()
+            });
+        }
+        None
+    }
+    fn description(&self) -> String {
+        format!("OcspStapling: {}", OCSP_STAPLES.load().len())
+    }
+}
+
+/// Create a ocsp stapling service, it fetches the ocsp responses of
+/// certificates and refreshes them before next update.
+pub fn new_ocsp_stapling_service() -> CommonServiceTask {
+    CommonServiceTask::new(
+        // check interval: one hour
+        Duration::from_secs(60 * 60),
+        OcspStapling {
+            max_failures: 3,
+            failures: Mutex::new(AHashMap::new()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        convert_asn1_time, get_ocsp_responder, get_ocsp_staple,
+        parse_ocsp_staple, OcspStaple,
+    };
+    use foreign_types::ForeignType;
+    use openssl::asn1::{Asn1Integer, Asn1Time};
+    use openssl::bn::BigNum;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::ocsp::{OcspBasicResponse, OcspCertId, OcspResponse};
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::{BasicConstraints, ExtendedKeyUsage};
+    use openssl::x509::{X509Builder, X509NameBuilder, X509};
+    use openssl_sys as ffi;
+    use pretty_assertions::assert_eq;
+    use std::ffi::{c_int, c_ulong, c_void};
+    use std::ptr;
+
+    extern "C" {
+        fn OCSP_basic_add1_status(
+            rsp: *mut ffi::OCSP_BASICRESP,
+            cid: *mut ffi::OCSP_CERTID,
+            status: c_int,
+            reason: c_int,
+            revtime: *mut ffi::ASN1_TIME,
+            thisupd: *mut ffi::ASN1_TIME,
+            nextupd: *mut ffi::ASN1_TIME,
+        ) -> *mut c_void;
+        fn OCSP_basic_sign(
+            brsp: *mut ffi::OCSP_BASICRESP,
+            signer: *mut ffi::X509,
+            key: *mut ffi::EVP_PKEY,
+            dgst: *const ffi::EVP_MD,
+            certs: *mut ffi::stack_st_X509,
+            flags: c_ulong,
+        ) -> c_int;
+    }
+
+    fn new_key() -> PKey<Private> {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+    }
+
+    fn new_cert(
+        name: &str,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        ca: bool,
+        ocsp_signing: bool,
+    ) -> X509 {
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(name.len() as u32).unwrap();
+        builder
+            .set_serial_number(&Asn1Integer::from_bn(&serial).unwrap())
+            .unwrap();
+        builder.set_subject_name(&subject).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(30).unwrap())
+            .unwrap();
+        if ca {
+            builder
+                .append_extension(BasicConstraints::new().ca().build().unwrap())
+                .unwrap();
+        }
+        if ocsp_signing {
+            builder
+                .append_extension(
+                    ExtendedKeyUsage::new()
+                        .other("OCSPSigning")
+                        .build()
+                        .unwrap(),
+                )
+                .unwrap();
+        }
+        let (issuer_name, sign_key) = match issuer {
+            Some((cert, key)) => (cert.subject_name(), key),
+            None => (subject.as_ref(), key),
+        };
+        builder.set_issuer_name(issuer_name).unwrap();
+        builder.sign(sign_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn new_ocsp_response(
+        cert: &X509,
+        issuer: &X509,
+        signer: &X509,
+        signer_key: &PKey<Private>,
+        with_next_update: bool,
+    ) -> Vec<u8> {
+        let cert_id =
+            OcspCertId::from_cert(MessageDigest::sha1(), cert, issuer).unwrap();
+        let this_update = Asn1Time::days_from_now(0).unwrap();
+        let next_update = Asn1Time::days_from_now(3).unwrap();
+        unsafe {
+            let basic = OcspBasicResponse::from_ptr(ffi::OCSP_BASICRESP_new());
+            assert_eq!(
+                false,
+                OCSP_basic_add1_status(
+                    basic.as_ptr(),
+                    cert_id.as_ptr(),
+                    ffi::V_OCSP_CERTSTATUS_GOOD,
+                    ffi::OCSP_REVOKED_STATUS_NOSTATUS,
+                    ptr::null_mut(),
+                    this_update.as_ptr(),
+                    if with_next_update {
+                        next_update.as_ptr()
+                    } else {
+                        ptr::null_mut()
+                    },
+                )
+                .is_null()
+            );
+            assert_eq!(
+                1,
+                OCSP_basic_sign(
+                    basic.as_ptr(),
+                    signer.as_ptr(),
+                    signer_key.as_ptr(),
+                    MessageDigest::sha256().as_ptr(),
+                    ptr::null_mut(),
+                    0,
+                )
+            );
+            let resp = OcspResponse::from_ptr(ffi::OCSP_response_create(
+                ffi::OCSP_RESPONSE_STATUS_SUCCESSFUL,
+                basic.as_ptr(),
+            ));
+            resp.to_der().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_convert_asn1_time() {
+        assert_eq!(
+            1794787200,
+            convert_asn1_time(&Asn1Time::from_unix(1794787200).unwrap())
+                .unwrap()
+        );
+        assert_eq!(
+            1740817805,
+            convert_asn1_time(&Asn1Time::from_unix(1740817805).unwrap())
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_ocsp_staple() {
+        let root_key = new_key();
+        let root = new_cert("pingap root", &root_key, None, true, false);
+        let issuer_key = new_key();
+        let issuer = new_cert(
+            "pingap intermediate",
+            &issuer_key,
+            Some((&root, &root_key)),
+            true,
+            false,
+        );
+        let cert_key = new_key();
+        let cert = new_cert(
+            "pingap.io",
+            &cert_key,
+            Some((&issuer, &issuer_key)),
+            false,
+            false,
+        );
+        let responder_key = new_key();
+        let responder = new_cert(
+            "pingap ocsp",
+            &responder_key,
+            Some((&issuer, &issuer_key)),
+            false,
+            true,
+        );
+
+        // signed by the issuer
+        let data =
+            new_ocsp_response(&cert, &issuer, &issuer, &issuer_key, true);
+        let staple = parse_ocsp_staple(&data, &cert, &issuer).unwrap();
+        assert_eq!(data, staple.response);
+        assert_eq!(3 * 86400, staple.next_update - staple.this_update);
+
+        // signed by the delegated responder
+        let data =
+            new_ocsp_response(&cert, &issuer, &responder, &responder_key, true);
+        let staple = parse_ocsp_staple(&data, &cert, &issuer).unwrap();
+        assert_eq!(3 * 86400, staple.next_update - staple.this_update);
+
+        // the responder without ocsp signing purpose
+        let data = new_ocsp_response(&cert, &issuer, &cert, &cert_key, true);
+        assert_eq!(
+            true,
+            parse_ocsp_staple(&data, &cert, &issuer)
+                .unwrap_err()
+                .to_string()
+                .starts_with("Ocsp error, category: verify")
+        );
+
+        // signed by other issuer
+        let data = new_ocsp_response(&cert, &issuer, &root, &root_key, true);
+        assert_eq!(
+            true,
+            parse_ocsp_staple(&data, &cert, &issuer)
+                .unwrap_err()
+                .to_string()
+                .starts_with("Ocsp error, category: verify")
+        );
+
+        // without next update
+        let data =
+            new_ocsp_response(&cert, &issuer, &issuer, &issuer_key, false);
+        assert_eq!(
+            "Ocsp error, category: next_update, next update of ocsp response is missing",
+            parse_ocsp_staple(&data, &cert, &issuer)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_ocsp_staple() {
+        let staple = OcspStaple {
+            this_update: 1000,
+            next_update: 2000,
+            ..Default::default()
+        };
+        assert_eq!(false, staple.should_refresh(1499));
+        assert_eq!(true, staple.should_refresh(1500));
+
+        assert_eq!(true, get_ocsp_staple("pingap").is_none());
+
+        let cert =
+            rcgen::generate_simple_self_signed(vec!["pingap.io".to_string()])
+                .unwrap();
+        let cert = X509::from_pem(cert.cert.pem().as_bytes()).unwrap();
+        assert_eq!(true, get_ocsp_responder(&cert).is_none());
+    }
+}
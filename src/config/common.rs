@@ -13,20 +13,23 @@
 // limitations under the License.
 
 use super::{Error, Result};
+use crate::acme::DnsChallenge;
 use crate::discovery::is_static_discovery;
 use crate::plugin::parse_plugins;
-use crate::proxy::Parser;
+use crate::proxy::{Parser, RequestMatcher, RewriteRule};
 use crate::util::{self, aes_decrypt, base64_decode};
 use arc_swap::ArcSwap;
 use bytesize::ByteSize;
 use http::{HeaderName, HeaderValue};
 use once_cell::sync::Lazy;
 use once_cell::sync::OnceCell;
+use openssl::x509::{X509Crl, X509};
 use regex::Regex;
 use serde::{Deserialize, Serialize, Serializer};
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::io::Cursor;
 use std::net::ToSocketAddrs;
+use std::path::Path;
 use std::sync::Arc;
 use std::time::Duration;
 use std::{collections::HashMap, str::FromStr};
@@ -80,6 +83,8 @@ pub enum PluginCategory {
     Csrf,
     Cors,
     AcceptEncoding,
+    Oidc,
+    ExtAuth,
 }
 
 impl Serialize for PluginCategory {
@@ -138,10 +143,13 @@ impl<'de> Deserialize<'de> for PluginStep {
     }
 }
 
-/// Convert pem to [u8]
+/// Convert pem to [u8], the value can be pem, base64 of pem or file path.
 fn convert_pem(value: &str) -> Result<Vec<u8>> {
+    let file = util::resolve_path(value);
     let buf = if util::is_pem(value) {
         value.as_bytes().to_vec()
+    } else if Path::new(&file).is_file() {
+        std::fs::read(&file).map_err(|e| Error::Io { source: e, file })?
     } else {
         base64_decode(value).map_err(|e| Error::Base64Decode { source: e })?
     };
@@ -157,6 +165,11 @@ pub struct CertificateConf {
     pub certificate_file: Option<String>,
     pub is_default: Option<bool>,
     pub acme: Option<String>,
+    pub acme_challenge: Option<String>,
+    pub acme_email: Option<String>,
+    pub acme_eab_kid: Option<String>,
+    pub acme_eab_hmac_key: Option<String>,
+    pub dns_provider: Option<String>,
     pub remark: Option<String>,
 }
 
@@ -181,6 +194,16 @@ fn validate_cert(value: &str) -> Result<()> {
     Ok(())
 }
 
+fn validate_key(value: &str) -> Result<()> {
+    let buf = convert_pem(value)?;
+    let mut key = Cursor::new(buf);
+    let _ =
+        rustls_pemfile::private_key(&mut key).map_err(|e| Error::Invalid {
+            message: e.to_string(),
+        })?;
+    Ok(())
+}
+
 impl CertificateConf {
     /// Get hash key of certificate config
     pub fn hash_key(&self) -> String {
@@ -192,13 +215,7 @@ impl CertificateConf {
     pub fn validate(&self) -> Result<()> {
         // convert private key
         if let Some(value) = &self.tls_key {
-            let buf = convert_pem(value)?;
-            let mut key = Cursor::new(buf);
-            let _ = rustls_pemfile::private_key(&mut key).map_err(|e| {
-                Error::Invalid {
-                    message: e.to_string(),
-                }
-            })?;
+            validate_key(value)?;
         }
         // convert certificate
         if let Some(value) = &self.tls_cert {
@@ -208,6 +225,40 @@ impl CertificateConf {
         if let Some(value) = &self.tls_chain {
             validate_cert(value)?;
         }
+        if let Some(value) = &self.dns_provider {
+            DnsChallenge::new(value).map_err(|e| Error::Invalid {
+                message: e.to_string(),
+            })?;
+        } else if self.acme.is_some()
+            && self.domains.clone().unwrap_or_default().contains("*.")
+        {
+            return Err(Error::Invalid {
+                message: "wildcard domain should use dns provider".to_string(),
+            });
+        }
+        if let Some(value) = &self.acme_challenge {
+            if !["", "http-01", "tls-alpn-01", "dns-01"]
+                .contains(&value.as_str())
+            {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "acme challenge({value}) is not supported"
+                    ),
+                });
+            }
+            if value == "dns-01" && self.dns_provider.is_none() {
+                return Err(Error::Invalid {
+                    message: "dns-01 challenge should set dns provider"
+                        .to_string(),
+                });
+            }
+        }
+        if self.acme_eab_kid.is_some() != self.acme_eab_hmac_key.is_some() {
+            return Err(Error::Invalid {
+                message: "eab kid and hmac key should be set together"
+                    .to_string(),
+            });
+        }
         Ok(())
     }
 }
@@ -222,6 +273,9 @@ pub struct UpstreamConf {
     pub algo: Option<String>,
     pub sni: Option<String>,
     pub verify_cert: Option<bool>,
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    pub tls_ca: Option<String>,
     pub health_check: Option<String>,
     pub ipv4_only: Option<bool>,
     pub enable_tracer: Option<bool>,
@@ -250,6 +304,16 @@ pub struct UpstreamConf {
     pub tcp_probe_count: Option<usize>,
     pub tcp_recv_buf: Option<ByteSize>,
     pub tcp_fast_open: Option<bool>,
+    pub retries: Option<usize>,
+    pub retry_on: Option<Vec<String>>,
+    pub retry_non_idempotent: Option<bool>,
+    pub outlier_consecutive_errors: Option<usize>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub outlier_base_ejection_time: Option<Duration>,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    pub outlier_max_ejection_time: Option<Duration>,
     pub includes: Option<Vec<String>>,
     pub remark: Option<String>,
 }
@@ -263,6 +327,8 @@ impl UpstreamConf {
     /// Validate the options of upstream config.
     /// 1. The address list can't be empty, and can be converted to socket addr.
     /// 2. The health check url can be parsed to Url if it exists.
+    /// 3. The retry conditions should be error types or http status codes.
+    /// 4. The client certificate and key should be set together.
     pub fn validate(&self, name: &str) -> Result<()> {
         if self.addrs.is_empty() {
             return Err(Error::Invalid {
@@ -299,6 +365,38 @@ impl UpstreamConf {
                 ),
             });
         }
+        for item in self.retry_on.clone().unwrap_or_default().iter() {
+            let valid = match item.as_str() {
+                "connect_error" | "connect_timeout" => true,
+                _ => item
+                    .parse::<u16>()
+                    .map(|code| (400..600).contains(&code))
+                    .unwrap_or_default(),
+            };
+            if !valid {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "retry on {item} is invalid(upstream:{name})"
+                    ),
+                });
+            }
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(Error::Invalid {
+                message: format!(
+                    "tls cert and key should be set together(upstream:{name})"
+                ),
+            });
+        }
+        if let Some(value) = &self.tls_key {
+            validate_key(value)?;
+        }
+        if let Some(value) = &self.tls_cert {
+            validate_cert(value)?;
+        }
+        if let Some(value) = &self.tls_ca {
+            validate_cert(value)?;
+        }
 
         Ok(())
     }
@@ -307,11 +405,22 @@ impl UpstreamConf {
 #[derive(Debug, Default, Deserialize, Clone, Serialize, Hash)]
 pub struct LocationConf {
     pub upstream: Option<String>,
+    pub upstreams: Option<Vec<String>>,
+    pub upstream_overrides: Option<Vec<String>>,
+    pub upstream_hash: Option<String>,
+    pub mirror_upstream: Option<String>,
+    pub mirror_percentage: Option<u8>,
     pub path: Option<String>,
     pub host: Option<String>,
+    pub methods: Option<Vec<String>>,
+    pub match_headers: Option<Vec<String>>,
+    pub match_queries: Option<Vec<String>>,
+    pub match_cookies: Option<Vec<String>>,
+    pub client_ips: Option<Vec<String>>,
     pub proxy_set_headers: Option<Vec<String>>,
     pub proxy_add_headers: Option<Vec<String>>,
     pub rewrite: Option<String>,
+    pub rewrites: Option<Vec<String>>,
     pub weight: Option<u16>,
     pub plugins: Option<Vec<String>>,
     pub client_max_body_size: Option<ByteSize>,
@@ -326,9 +435,38 @@ impl LocationConf {
         self.hash(&mut hasher);
         format!("{:x}", hasher.finish())
     }
+    /// Get all the upstreams of location, include the weighted upstreams,
+    /// the upstreams of override rules and the mirror upstream.
+    pub fn get_upstreams(&self) -> Vec<String> {
+        let mut upstreams = vec![];
+        if let Some(upstream) = &self.upstream {
+            upstreams.push(upstream.trim().to_string());
+        }
+        if let Some(upstream) = &self.mirror_upstream {
+            upstreams.push(upstream.trim().to_string());
+        }
+        for item in self.upstreams.clone().unwrap_or_default().iter() {
+            let item = item.trim();
+            let name =
+                item.split_once(' ').map(|(name, _)| name).unwrap_or(item);
+            upstreams.push(name.to_string());
+        }
+        for item in self.upstream_overrides.clone().unwrap_or_default().iter() {
+            if let Some((_, name)) = item.trim().split_once(' ') {
+                upstreams.push(name.trim().to_string());
+            }
+        }
+        upstreams.retain(|item| !item.is_empty());
+        upstreams.sort();
+        upstreams.dedup();
+        upstreams
+    }
     /// Validate the options of location config.
     /// 1. Convert add and set headers to (HeaderName, HeaderValue).
     /// 2. Parse rewrite path to regexp if it exists.
+    /// 3. The weighted upstreams and override rules should be valid.
+    /// 4. The methods, match conditions and client ips should be valid.
+    /// 5. The flags, conditions and query modifiers of rewrite rules should be valid.
     fn validate(&self, name: &str, upstream_names: &[String]) -> Result<()> {
         // validate header for http
         let validate = |headers: &Option<Vec<String>>| -> Result<()> {
@@ -356,25 +494,133 @@ impl LocationConf {
             Ok(())
         };
 
-        let upstream = self.upstream.clone().unwrap_or_default();
-        if !upstream.is_empty() && !upstream_names.contains(&upstream) {
+        for upstream in self.get_upstreams().iter() {
+            if !upstream_names.contains(upstream) {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "upstream({upstream}) is not found(location:{name})"
+                    ),
+                });
+            }
+        }
+        if self.mirror_percentage.unwrap_or_default() > 100 {
             return Err(Error::Invalid {
                 message: format!(
-                    "upstream({upstream}) is not found(location:{name})"
+                    "mirror percentage should be <= 100(location:{name})"
                 ),
             });
         }
+        let mut total_weight: u32 = 0;
+        for item in self.upstreams.clone().unwrap_or_default().iter() {
+            let weight = match item.trim().split_once(' ') {
+                Some((_, weight)) => weight.trim().parse::<u32>().ok(),
+                None => Some(1),
+            };
+            // the sum of weights should not overflow
+            total_weight = weight
+                .and_then(|weight| total_weight.checked_add(weight))
+                .ok_or_else(|| Error::Invalid {
+                    message: format!(
+                        "upstream weight {item} is invalid(location:{name})"
+                    ),
+                })?;
+        }
+        if let Some(value) = &self.upstream_hash {
+            let valid = value
+                .split_once(':')
+                .map(|(category, key)| {
+                    ["header", "cookie", "query"].contains(&category)
+                        && !key.is_empty()
+                })
+                .unwrap_or_default();
+            if !valid {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "upstream hash {value} is invalid(location:{name})"
+                    ),
+                });
+            }
+        }
+        for item in self.upstream_overrides.clone().unwrap_or_default().iter() {
+            let valid = item
+                .trim()
+                .split_once(' ')
+                .and_then(|(rule, _)| rule.split_once(':'))
+                .map(|(category, _)| {
+                    ["header", "cookie", "query"].contains(&category)
+                })
+                .unwrap_or_default();
+            if !valid {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "upstream override {item} is invalid(location:{name})"
+                    ),
+                });
+            }
+        }
         validate(&self.proxy_add_headers)?;
         validate(&self.proxy_set_headers)?;
 
+        for method in self.methods.clone().unwrap_or_default().iter() {
+            http::Method::from_bytes(method.trim().to_uppercase().as_bytes())
+                .map_err(|_| Error::Invalid {
+                message: format!(
+                    "method({method}) is invalid(location:{name})"
+                ),
+            })?;
+        }
+        for (category, values) in [
+            ("header", &self.match_headers),
+            ("query", &self.match_queries),
+            ("cookie", &self.match_cookies),
+        ] {
+            for item in values.clone().unwrap_or_default().iter() {
+                RequestMatcher::new(category, item).map_err(|e| {
+                    Error::Invalid {
+                        message: format!("{}(location:{name})", e.message()),
+                    }
+                })?;
+            }
+        }
+        for item in self.client_ips.clone().unwrap_or_default().iter() {
+            let item = item.trim();
+            if item.parse::<ipnet::IpNet>().is_err()
+                && item.parse::<std::net::IpAddr>().is_err()
+            {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "client ip({item}) is invalid(location:{name})"
+                    ),
+                });
+            }
+        }
+
         if let Some(value) = &self.rewrite {
             let arr: Vec<&str> = value.split(' ').collect();
             let _ =
                 Regex::new(arr[0]).map_err(|e| Error::Regex { source: e })?;
         }
+        for item in self.rewrites.clone().unwrap_or_default().iter() {
+            RewriteRule::new(item).map_err(|e| Error::Invalid {
+                message: format!("{}(location:{name})", e.message()),
+            })?;
+        }
 
         Ok(())
     }
+    /// Return `true` if the location has match conditions of request,
+    /// it has priority over the location with the same weight.
+    pub fn has_conditions(&self) -> bool {
+        [
+            &self.methods,
+            &self.match_headers,
+            &self.match_queries,
+            &self.match_cookies,
+            &self.client_ips,
+        ]
+        .iter()
+        .any(|values| values.as_ref().is_some_and(|values| !values.is_empty()))
+    }
     /// Get weight of location, which is calculated from the domain name, path and path length
     pub fn get_weight(&self) -> u16 {
         if let Some(weight) = self.weight {
@@ -414,6 +660,9 @@ pub struct ServerConf {
     pub tls_ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
+    pub tls_client_ca: Option<String>,
+    pub tls_client_auth: Option<String>,
+    pub tls_client_crl: Option<String>,
     pub global_certificates: Option<bool>,
     pub enabled_h2: Option<bool>,
     #[serde(default)]
@@ -435,6 +684,7 @@ impl ServerConf {
     /// 1. Parse listen addr to socket addr.
     /// 2. Check the locations are exists.
     /// 3. Parse access log layout success.
+    /// 4. Check the client auth mode of tls.
     fn validate(&self, name: &str, location_names: &[String]) -> Result<()> {
         for addr in self.addr.split(',') {
             let _ = addr.to_socket_addrs().map_err(|e| Error::Io {
@@ -462,6 +712,46 @@ impl ServerConf {
                 });
             }
         }
+        if let Some(client_auth) = &self.tls_client_auth {
+            if !["", "required", "optional"].contains(&client_auth.as_str()) {
+                return Err(Error::Invalid {
+                    message: format!(
+                        "tls client auth({client_auth}) should be required or optional(server:{name})"
+                    ),
+                });
+            }
+        }
+        if self.tls_client_crl.is_some() && self.tls_client_ca.is_none() {
+            return Err(Error::Invalid {
+                message: format!(
+                    "tls client crl should be used with client ca(server:{name})"
+                ),
+            });
+        }
+        if let Some(client_ca) = &self.tls_client_ca {
+            let certs = X509::stack_from_pem(&convert_pem(client_ca)?)
+                .map_err(|e| Error::Invalid {
+                    message: format!(
+                        "tls client ca is invalid, {e}(server:{name})"
+                    ),
+                })?;
+            if certs.is_empty() {
+                return Err(Error::Invalid {
+                    message: format!("tls client ca is empty(server:{name})"),
+                });
+            }
+        }
+        if let Some(client_crl) = &self.tls_client_crl {
+            // the crl is loaded from file
+            let file = util::resolve_path(client_crl);
+            let buf = std::fs::read(&file)
+                .map_err(|e| Error::Io { source: e, file })?;
+            X509Crl::from_pem(&buf).map_err(|e| Error::Invalid {
+                message: format!(
+                    "tls client crl is invalid, {e}(server:{name})"
+                ),
+            })?;
+        }
 
         Ok(())
     }
@@ -819,7 +1109,7 @@ impl PingapConf {
                 let upstreams: Vec<String> = self
                     .locations
                     .values()
-                    .map(|lo| lo.upstream.clone().unwrap_or_default())
+                    .flat_map(|lo| lo.get_upstreams())
                     .collect();
                 if upstreams.contains(&name.to_string()) {
                     return Err(Error::Invalid {
@@ -1055,6 +1345,7 @@ mod tests {
     };
     use pretty_assertions::assert_eq;
     use serde::{Deserialize, Serialize};
+    use std::io::Write;
 
     #[test]
     fn test_app_name() {
@@ -1129,6 +1420,26 @@ mod tests {
         assert_eq!(true, result.is_ok());
     }
 
+    #[test]
+    fn test_upstream_conf_retry_on() {
+        let mut conf = UpstreamConf {
+            addrs: vec!["127.0.0.1:8001 10 backup".to_string()],
+            retry_on: Some(vec![
+                "connect_error".to_string(),
+                "connect_timeout".to_string(),
+                "502".to_string(),
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(true, conf.validate("test").is_ok());
+
+        conf.retry_on = Some(vec!["200".to_string()]);
+        assert_eq!(
+            "Invalid error retry on 200 is invalid(upstream:test)",
+            conf.validate("test").expect_err("").to_string()
+        );
+    }
+
     #[test]
     fn test_location_conf() {
         let mut conf = LocationConf::default();
@@ -1177,6 +1488,134 @@ mod tests {
         conf.rewrite = Some(r"^/api /".to_string());
         let result = conf.validate("lo", &upstream_names);
         assert_eq!(true, result.is_ok());
+
+        conf.rewrites = Some(vec!["^/api".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error rewrite rule ^/api is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.rewrites = Some(vec!["^/api / last add_query=from".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error rewrite rule ^/api / last add_query=from is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.rewrites = Some(vec!["^/api / if~(".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            true,
+            result
+                .expect_err("")
+                .to_string()
+                .starts_with("Invalid error Regex value: (, regex parse error")
+        );
+
+        conf.rewrites = Some(vec![
+            r"^/api/(.*)$ /$1 break if!~^/api/v1 add_query=from:web remove_query=debug rename_query=q:keyword".to_string(),
+            r"^/old/(.*)$ /new/$1 permanent".to_string(),
+        ]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(true, result.is_ok());
+        conf.rewrites = None;
+
+        conf.upstreams = Some(vec!["upstream1 a".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error upstream weight upstream1 a is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.upstreams = Some(vec![
+            "upstream1 4294967295".to_string(),
+            "upstream1 1".to_string(),
+        ]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error upstream weight upstream1 1 is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.upstreams = Some(vec!["upstream1 95".to_string()]);
+        conf.upstream_hash = Some("ip:uid".to_string());
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error upstream hash ip:uid is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+        conf.upstream_hash = Some("cookie:uid".to_string());
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(true, result.is_ok());
+        conf.upstream_hash = None;
+
+        conf.upstreams = Some(vec!["upstream1 95".to_string()]);
+        conf.upstream_overrides =
+            Some(vec!["ip:1.1.1.1 upstream1".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error upstream override ip:1.1.1.1 upstream1 is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.upstream_overrides =
+            Some(vec!["header:X-Canary=1 upstream3".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error upstream(upstream3) is not found(location:lo)",
+            result.expect_err("").to_string()
+        );
+        assert_eq!(
+            vec!["upstream1".to_string(), "upstream3".to_string()],
+            conf.get_upstreams()
+        );
+
+        conf.upstream_overrides = None;
+        conf.mirror_upstream = Some("upstream1".to_string());
+        conf.mirror_percentage = Some(101);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error mirror percentage should be <= 100(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.mirror_percentage = None;
+        conf.methods = Some(vec!["GET".to_string(), "p o".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error method(p o) is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.methods = Some(vec!["post".to_string()]);
+        conf.match_headers = Some(vec!["~curl".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error header match ~curl is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.match_headers = Some(vec!["User-Agent~curl(".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            true,
+            result.expect_err("").to_string().starts_with(
+                "Invalid error Regex value: curl(, regex parse error"
+            )
+        );
+
+        conf.match_headers = Some(vec!["X-Beta=1".to_string()]);
+        conf.client_ips = Some(vec!["192.168.1.0/24".to_string(), "a".into()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(
+            "Invalid error client ip(a) is invalid(location:lo)",
+            result.expect_err("").to_string()
+        );
+
+        conf.client_ips = Some(vec!["192.168.1.0/24".to_string()]);
+        let result = conf.validate("lo", &upstream_names);
+        assert_eq!(true, result.is_ok());
     }
 
     #[test]
@@ -1204,6 +1643,27 @@ mod tests {
 
         conf.host = Some("".to_string());
         assert_eq!(0, conf.get_weight());
+
+        conf.path = Some("/api".to_string());
+        conf.methods = Some(vec!["POST".to_string()]);
+        assert_eq!(516, conf.get_weight());
+        assert_eq!(true, conf.has_conditions());
+
+        // the match conditions don't outrank the longer prefix
+        let catch_all = LocationConf {
+            path: Some("/".to_string()),
+            methods: Some(vec!["POST".to_string()]),
+            ..Default::default()
+        };
+        let upload = LocationConf {
+            path: Some("/api/upload".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            true,
+            (catch_all.get_weight(), catch_all.has_conditions())
+                < (upload.get_weight(), upload.has_conditions())
+        );
     }
 
     #[test]
@@ -1230,6 +1690,32 @@ mod tests {
         conf.locations = Some(vec!["lo".to_string()]);
         let result = conf.validate("test", &location_names);
         assert_eq!(true, result.is_ok());
+
+        conf.tls_client_ca = Some("-----BEGIN".to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            "Invalid error tls client ca is empty(server:test)",
+            result.expect_err("").to_string()
+        );
+
+        let cert =
+            rcgen::generate_simple_self_signed(vec!["pingap.io".to_string()])
+                .unwrap();
+        conf.tls_client_ca = Some(cert.cert.pem());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(true, result.is_ok());
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(b"-----BEGIN X509 CRL-----").unwrap();
+        conf.tls_client_crl = Some(file.path().to_string_lossy().to_string());
+        let result = conf.validate("test", &location_names);
+        assert_eq!(
+            true,
+            result
+                .expect_err("")
+                .to_string()
+                .starts_with("Invalid error tls client crl is invalid")
+        );
     }
 
     #[test]
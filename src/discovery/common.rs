@@ -12,8 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{format_addrs, Error, Result};
-use http::Extensions;
+use super::{format_addrs, new_backend_ext, Error, Result};
 use pingora::lb::discovery;
 use pingora::lb::{Backend, Backends};
 use pingora::protocols::l4::socket::SocketAddr;
@@ -34,7 +33,7 @@ pub fn new_common_discover_backends(
     let mut upstreams = BTreeSet::new();
     let mut backends = vec![];
     let addrs = format_addrs(addrs, tls);
-    for (ip, port, weight) in addrs.iter() {
+    for (ip, port, weight, backup) in addrs.iter() {
         let addr = format!("{ip}:{port}");
         // resolve to socket addr
         for item in addr.to_socket_addrs().map_err(|e| Error::Io {
@@ -47,7 +46,7 @@ pub fn new_common_discover_backends(
             let backend = Backend {
                 addr: SocketAddr::Inet(item),
                 weight: weight.to_owned(),
-                ext: Extensions::new(),
+                ext: new_backend_ext(*backup),
             };
             backends.push(backend)
         }
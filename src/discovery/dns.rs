@@ -12,7 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{format_addrs, Addr, Error, Result};
+use super::{format_addrs, new_backend_ext, Addr, Error, Result};
 use crate::webhook;
 use async_trait::async_trait;
 use hickory_resolver::config::{
@@ -22,7 +22,6 @@ use hickory_resolver::lookup_ip::LookupIp;
 use hickory_resolver::name_server::TokioConnectionProvider;
 use hickory_resolver::system_conf::read_system_conf;
 use hickory_resolver::AsyncResolver;
-use http::Extensions;
 use pingora::lb::discovery::ServiceDiscovery;
 use pingora::lb::{Backend, Backends};
 use pingora::protocols::l4::socket::SocketAddr;
@@ -65,7 +64,7 @@ impl Dns {
         let (config, options) = self.read_system_conf()?;
         let resolver = AsyncResolver::new(config, options, provider);
 
-        for (host, _, _) in self.hosts.iter() {
+        for (host, _, _, _) in self.hosts.iter() {
             let ip = resolver
                 .lookup_ip(host)
                 .await
@@ -84,7 +83,8 @@ impl Dns {
             "dns discover is running"
         );
         let lookup_ip_list = self.tokio_lookup_ip().await?;
-        for (index, (_, port, weight)) in self.hosts.iter().enumerate() {
+        for (index, (_, port, weight, backup)) in self.hosts.iter().enumerate()
+        {
             let lookup_ip =
                 lookup_ip_list.get(index).ok_or(Error::Invalid {
                     message: "lookup ip fail".to_string(),
@@ -106,7 +106,7 @@ impl Dns {
                     backends.push(Backend {
                         addr: SocketAddr::Inet(socket_addr),
                         weight: weight.to_owned(),
-                        ext: Extensions::new(),
+                        ext: new_backend_ext(*backup),
                     });
                 }
             }
@@ -12,12 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Error, Result};
+use super::{new_backend_ext, parse_addr_options, Error, Result};
 use crate::webhook;
 use async_trait::async_trait;
 use bollard::container::ListContainersOptions;
 use bollard::secret::ContainerSummary;
-use http::Extensions;
 use pingora::lb::discovery::ServiceDiscovery;
 use pingora::lb::{Backend, Backends};
 use pingora::protocols::l4::socket::SocketAddr;
@@ -30,6 +29,7 @@ use tracing::{debug, error, info};
 struct Container {
     label: String,
     weight: usize,
+    backup: bool,
     port: u16,
     addrs: Vec<String>,
 }
@@ -53,13 +53,9 @@ impl Docker {
 
         let mut containers = vec![];
         for addr in addrs.iter() {
-            // get the weight of address
+            // get the weight and backup flag of address
             let arr: Vec<_> = addr.split(' ').collect();
-            let weight = if arr.len() == 2 {
-                arr[1].parse::<usize>().unwrap_or(1)
-            } else {
-                1
-            };
+            let (weight, backup) = parse_addr_options(&arr[1..]);
             let mut label = arr[0].to_string();
             let mut container_port = 0;
             if let Some((value, port)) = label.clone().split_once(":") {
@@ -71,6 +67,7 @@ impl Docker {
             containers.push(Container {
                 label,
                 weight,
+                backup,
                 port: container_port,
                 addrs: vec![],
             });
@@ -171,7 +168,7 @@ impl Docker {
                     backends.push(Backend {
                         addr: SocketAddr::Inet(socket_addr),
                         weight: container.weight,
-                        ext: Extensions::new(),
+                        ext: new_backend_ext(container.backup),
                     });
                 }
             }
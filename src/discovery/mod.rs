@@ -13,6 +13,8 @@
 // limitations under the License.
 
 use hickory_resolver::error::ResolveError;
+use http::Extensions;
+use pingora::lb::Backend;
 use snafu::Snafu;
 
 #[derive(Debug, Snafu)]
@@ -37,29 +39,65 @@ impl From<Error> for pingora::BError {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-pub(crate) type Addr = (String, String, usize);
+pub(crate) type Addr = (String, String, usize, bool);
+
+/// The backup flag of backend, backup backends are only used
+/// when all the primary backends are unhealthy.
+#[derive(Debug, Clone)]
+pub struct BackupBackend;
+
+/// Return `true` if the backend is marked as backup.
+#[inline]
+pub fn is_backup_backend(backend: &Backend) -> bool {
+    backend.ext.get::<BackupBackend>().is_some()
+}
+
+/// New the extensions of backend, it will be marked as backup if `backup` is true.
+pub(crate) fn new_backend_ext(backup: bool) -> Extensions {
+    let mut ext = Extensions::new();
+    if backup {
+        ext.insert(BackupBackend);
+    }
+    ext
+}
+
+/// Get the weight and backup flag of address,
+/// e.g. `192.168.1.1:8001 10 backup`
+pub(crate) fn parse_addr_options(values: &[&str]) -> (usize, bool) {
+    let mut weight = 1;
+    let mut backup = false;
+    for value in values.iter() {
+        if *value == "backup" {
+            backup = true;
+        } else if let Ok(v) = value.parse::<usize>() {
+            weight = v;
+        }
+    }
+    (weight, backup)
+}
 
 pub(crate) fn format_addrs(addrs: &[String], tls: bool) -> Vec<Addr> {
     let mut new_addrs = vec![];
     for addr in addrs.iter() {
-        // get the weight of address
+        // get the weight and backup flag of address
         let arr: Vec<_> = addr.split(' ').collect();
-        let weight = if arr.len() == 2 {
-            arr[1].parse::<usize>().unwrap_or(1)
-        } else {
-            1
-        };
+        let (weight, backup) = parse_addr_options(&arr[1..]);
         // split ip and port
         // the port will use default value if none
         if let Some((host, port)) = arr[0].split_once(':') {
-            new_addrs.push((host.to_string(), port.to_string(), weight));
+            new_addrs.push((
+                host.to_string(),
+                port.to_string(),
+                weight,
+                backup,
+            ));
         } else {
             let port = if tls {
                 "443".to_string()
             } else {
                 "80".to_string()
             };
-            new_addrs.push((arr[0].to_string(), port, weight));
+            new_addrs.push((arr[0].to_string(), port, weight, backup));
         }
     }
     new_addrs
@@ -73,3 +111,28 @@ pub use dns::{is_dns_discovery, new_dns_discover_backends};
 pub use docker::{is_docker_discovery, new_docker_discover_backends};
 
 use crate::util;
+
+#[cfg(test)]
+mod tests {
+    use super::{format_addrs, parse_addr_options};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_format_addrs() {
+        assert_eq!((1, false), parse_addr_options(&[]));
+        assert_eq!((10, true), parse_addr_options(&["10", "backup"]));
+        assert_eq!((1, true), parse_addr_options(&["backup"]));
+
+        let addrs = format_addrs(
+            &[
+                "192.168.1.1:8001 10".to_string(),
+                "192.168.1.2 backup".to_string(),
+            ],
+            true,
+        );
+        assert_eq!(
+            r#"[("192.168.1.1", "8001", 10, false), ("192.168.1.2", "443", 1, true)]"#,
+            format!("{addrs:?}")
+        );
+    }
+}
@@ -137,9 +137,13 @@ pub fn convert_header_value(
                         .unwrap_or_default();
                 return session.get_header(key).cloned();
             } else if buf.starts_with(b"$") {
-                if let Ok(value) = std::env::var(
-                    std::str::from_utf8(&buf[1..buf.len()]).unwrap_or_default(),
-                ) {
+                let key =
+                    std::str::from_utf8(&buf[1..buf.len()]).unwrap_or_default();
+                // the variable of location has higher priority than env
+                if let Some(value) = ctx.get_variable(key) {
+                    return HeaderValue::from_str(value).ok();
+                }
+                if let Ok(value) = std::env::var(key) {
                     return HeaderValue::from_str(&value).ok();
                 }
             } else if buf.starts_with(b":") {
@@ -222,6 +226,15 @@ pub static HTTP_HEADER_TRANSFER_CHUNKED: Lazy<HttpHeader> = Lazy::new(|| {
 pub static HTTP_HEADER_NAME_X_REQUEST_ID: Lazy<HeaderName> =
     Lazy::new(|| HeaderName::from_str("X-Request-Id").unwrap());
 
+pub static HTTP_HEADER_NAME_X_CLIENT_CERT_SUBJECT: Lazy<HeaderName> =
+    Lazy::new(|| HeaderName::from_str("X-Client-Cert-Subject").unwrap());
+
+pub static HTTP_HEADER_NAME_X_CLIENT_CERT_SAN: Lazy<HeaderName> =
+    Lazy::new(|| HeaderName::from_str("X-Client-Cert-San").unwrap());
+
+pub static HTTP_HEADER_NAME_X_CLIENT_CERT_FINGERPRINT: Lazy<HeaderName> =
+    Lazy::new(|| HeaderName::from_str("X-Client-Cert-Fingerprint").unwrap());
+
 #[cfg(test)]
 mod tests {
     use crate::state::State;
@@ -366,6 +379,27 @@ mod tests {
         );
         assert_eq!(true, value.is_some());
 
+        let ctx = State {
+            variables: Some(
+                [("tenant".to_string(), "pingap".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let value = convert_header_value(
+            &HeaderValue::from_str("$tenant").unwrap(),
+            &session,
+            &ctx,
+        );
+        assert_eq!(r#"Some("pingap")"#, format!("{value:?}"));
+        let value = convert_header_value(
+            &HeaderValue::from_str(":tenant").unwrap(),
+            &session,
+            &ctx,
+        );
+        assert_eq!(r#"Some("pingap")"#, format!("{value:?}"));
+
         let headers = ["Origin: https://github.com"].join("\r\n");
         let input_header =
             format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
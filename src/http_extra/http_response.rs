@@ -185,7 +185,12 @@ impl HttpResponse {
 
         if let Some(headers) = &self.headers {
             for (name, value) in headers {
-                resp.insert_header(name.to_owned(), value)?;
+                // multiple cookies may be set in one response
+                if name == header::SET_COOKIE {
+                    resp.append_header(name.to_owned(), value)?;
+                } else {
+                    resp.insert_header(name.to_owned(), value)?;
+                }
             }
         }
         Ok(resp)
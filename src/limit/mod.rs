@@ -12,6 +12,26 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use snafu::Snafu;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Io error: {source}"))]
+    Io { source: std::io::Error },
+    #[snafu(display("Invalid error {message}"))]
+    Invalid { message: String },
+    #[snafu(display("Timeout error {message}"))]
+    Timeout { message: String },
+}
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+mod redis;
+mod sliding_window;
+mod token_bucket;
 mod ttl_lru_limit;
 
+#[cfg(test)]
+pub(crate) use redis::tests::start_redis_stand_in;
+pub use sliding_window::SharedRate;
+pub use token_bucket::TokenBucket;
 pub use ttl_lru_limit::TtlLruLimit;
@@ -0,0 +1,371 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, Result};
+use bytes::BytesMut;
+use humantime::parse_duration;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+use tokio::net::TcpStream;
+use url::Url;
+
+type Connection = BufStream<TcpStream>;
+
+#[derive(Debug, PartialEq)]
+pub enum RespValue {
+    Nil,
+    Int(i64),
+    Str(String),
+    Array(Vec<RespValue>),
+}
+
+impl RespValue {
+    /// Get the integer value, the string value will be parsed as integer.
+    pub fn as_int(&self) -> i64 {
+        match self {
+            RespValue::Int(value) => *value,
+            RespValue::Str(value) => value.parse().unwrap_or_default(),
+            _ => 0,
+        }
+    }
+}
+
+/// A minimal client for redis protocol(RESP2) compatible store,
+/// it only supports the commands which are used by shared limit.
+pub struct RedisClient {
+    addr: String,
+    password: Option<String>,
+    db: u32,
+    timeout: Duration,
+    pool_size: usize,
+    connections: Mutex<Vec<Connection>>,
+}
+
+impl RedisClient {
+    /// Create a client from url, e.g.
+    /// `redis://:password@127.0.0.1:6379/0?timeout=100ms&pool_size=10`
+    pub fn new(value: &str) -> Result<Self> {
+        let url = Url::parse(value).map_err(|e| Error::Invalid {
+            message: format!("{value} is invalid, {e}"),
+        })?;
+        if url.scheme() != "redis" {
+            return Err(Error::Invalid {
+                message: format!("{value} should be redis://"),
+            });
+        }
+        let host = url.host_str().unwrap_or_default();
+        if host.is_empty() {
+            return Err(Error::Invalid {
+                message: format!("host of {value} is empty"),
+            });
+        }
+        let addr = format!("{host}:{}", url.port().unwrap_or(6379));
+        let password = url.password().map(|value| value.to_string());
+        let db = url.path().trim_matches('/').parse::<u32>().unwrap_or(0);
+        let mut timeout = Duration::from_millis(200);
+        let mut pool_size = 10;
+        for (key, value) in url.query_pairs() {
+            match key.as_ref() {
+                "timeout" => {
+                    if let Ok(value) = parse_duration(&value) {
+                        timeout = value;
+                    }
+                },
+                "pool_size" => {
+                    if let Ok(value) = value.parse::<usize>() {
+                        pool_size = value;
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(Self {
+            addr,
+            password,
+            db,
+            timeout,
+            pool_size,
+            connections: Mutex::new(vec![]),
+        })
+    }
+    async fn connect(&self) -> Result<Connection> {
+        let stream = TcpStream::connect(&self.addr)
+            .await
+            .map_err(|e| Error::Io { source: e })?;
+        let _ = stream.set_nodelay(true);
+        let mut conn = BufStream::new(stream);
+        let mut cmds = vec![];
+        if let Some(password) = &self.password {
+            cmds.push(vec!["AUTH".to_string(), password.clone()]);
+        }
+        if self.db != 0 {
+            cmds.push(vec!["SELECT".to_string(), self.db.to_string()]);
+        }
+        if !cmds.is_empty() {
+            do_pipeline(&mut conn, &cmds).await?;
+        }
+        Ok(conn)
+    }
+    async fn get_connection(&self) -> Result<Connection> {
+        let conn = self
+            .connections
+            .lock()
+            .ok()
+            .and_then(|mut connections| connections.pop());
+        if let Some(conn) = conn {
+            return Ok(conn);
+        }
+        self.connect().await
+    }
+    fn release_connection(&self, conn: Connection) {
+        if let Ok(mut connections) = self.connections.lock() {
+            if connections.len() < self.pool_size {
+                connections.push(conn);
+            }
+        }
+    }
+    /// Send the commands in pipeline and returns the replies,
+    /// the connection is dropped if any error occurs.
+    pub async fn pipeline(
+        &self,
+        cmds: &[Vec<String>],
+    ) -> Result<Vec<RespValue>> {
+        let result = tokio::time::timeout(self.timeout, async {
+            let mut conn = self.get_connection().await?;
+            let values = do_pipeline(&mut conn, cmds).await?;
+            Ok((conn, values))
+        })
+        .await
+        .map_err(|_| Error::Timeout {
+            message: format!("{} request timeout", self.addr),
+        })?;
+        let (conn, values) = result?;
+        self.release_connection(conn);
+        Ok(values)
+    }
+}
+
+fn encode_command(buf: &mut BytesMut, cmd: &[String]) {
+    buf.extend_from_slice(format!("*{}\r\n", cmd.len()).as_bytes());
+    for arg in cmd.iter() {
+        buf.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        buf.extend_from_slice(arg.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+}
+
+async fn read_line(conn: &mut Connection) -> Result<String> {
+    let mut line = String::new();
+    let size = conn
+        .read_line(&mut line)
+        .await
+        .map_err(|e| Error::Io { source: e })?;
+    if size == 0 {
+        return Err(Error::Invalid {
+            message: "connection is closed".to_string(),
+        });
+    }
+    Ok(line.trim_end_matches("\r\n").to_string())
+}
+
+async fn read_value(conn: &mut Connection) -> Result<RespValue> {
+    let line = read_line(conn).await?;
+    let invalid = || Error::Invalid {
+        message: format!("invalid reply: {line}"),
+    };
+    let Some(prefix) = line.chars().next() else {
+        return Err(invalid());
+    };
+    let data = &line[1..];
+    match prefix {
+        '+' => Ok(RespValue::Str(data.to_string())),
+        '-' => Err(Error::Invalid {
+            message: data.to_string(),
+        }),
+        ':' => Ok(RespValue::Int(data.parse().map_err(|_| invalid())?)),
+        '$' => {
+            let size: i64 = data.parse().map_err(|_| invalid())?;
+            if size < 0 {
+                return Ok(RespValue::Nil);
+            }
+            // the data and \r\n
+            let mut buf = vec![0; size as usize + 2];
+            conn.read_exact(&mut buf)
+                .await
+                .map_err(|e| Error::Io { source: e })?;
+            buf.truncate(size as usize);
+            Ok(RespValue::Str(String::from_utf8_lossy(&buf).to_string()))
+        },
+        '*' => {
+            let size: i64 = data.parse().map_err(|_| invalid())?;
+            if size < 0 {
+                return Ok(RespValue::Nil);
+            }
+            let mut values = Vec::with_capacity(size as usize);
+            for _ in 0..size {
+                values.push(Box::pin(read_value(conn)).await?);
+            }
+            Ok(RespValue::Array(values))
+        },
+        _ => Err(invalid()),
+    }
+}
+
+async fn do_pipeline(
+    conn: &mut Connection,
+    cmds: &[Vec<String>],
+) -> Result<Vec<RespValue>> {
+    let mut buf = BytesMut::new();
+    for cmd in cmds.iter() {
+        encode_command(&mut buf, cmd);
+    }
+    conn.write_all(&buf)
+        .await
+        .map_err(|e| Error::Io { source: e })?;
+    conn.flush().await.map_err(|e| Error::Io { source: e })?;
+    let mut values = Vec::with_capacity(cmds.len());
+    for _ in cmds.iter() {
+        values.push(read_value(conn).await?);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{RedisClient, RespValue};
+    use ahash::AHashMap;
+    use pretty_assertions::assert_eq;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream};
+    use tokio::net::TcpListener;
+
+    async fn read_command(
+        conn: &mut BufStream<tokio::net::TcpStream>,
+    ) -> Option<Vec<String>> {
+        let mut line = String::new();
+        if conn.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let size: usize = line.trim()[1..].parse().ok()?;
+        let mut args = vec![];
+        for _ in 0..size {
+            let mut line = String::new();
+            conn.read_line(&mut line).await.ok()?;
+            let len: usize = line.trim()[1..].parse().ok()?;
+            let mut buf = vec![0; len + 2];
+            conn.read_exact(&mut buf).await.ok()?;
+            buf.truncate(len);
+            args.push(String::from_utf8(buf).ok()?);
+        }
+        Some(args)
+    }
+
+    /// Start a local stand-in of redis, it only supports
+    /// the commands which are used by shared limit.
+    pub(crate) async fn start_redis_stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let data: Arc<Mutex<AHashMap<String, i64>>> = Default::default();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let data = data.clone();
+                tokio::spawn(async move {
+                    let mut conn = BufStream::new(stream);
+                    while let Some(args) = read_command(&mut conn).await {
+                        let reply = {
+                            let mut data = data.lock().unwrap();
+                            match args[0].to_uppercase().as_str() {
+                                "INCR" => {
+                                    let value = data
+                                        .entry(args[1].clone())
+                                        .or_default();
+                                    *value += 1;
+                                    format!(":{value}\r\n")
+                                },
+                                "GET" => match data.get(&args[1]) {
+                                    Some(value) => {
+                                        let value = value.to_string();
+                                        format!(
+                                            "${}\r\n{value}\r\n",
+                                            value.len()
+                                        )
+                                    },
+                                    None => "$-1\r\n".to_string(),
+                                },
+                                "PEXPIRE" => ":1\r\n".to_string(),
+                                "AUTH" | "SELECT" => "+OK\r\n".to_string(),
+                                _ => "-ERR unknown command\r\n".to_string(),
+                            }
+                        };
+                        if conn.write_all(reply.as_bytes()).await.is_err()
+                            || conn.flush().await.is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        format!("redis://{addr}")
+    }
+
+    #[test]
+    fn test_new_redis_client() {
+        let client = RedisClient::new(
+            "redis://:pass@127.0.0.1:6380/2?timeout=1s&pool_size=2",
+        )
+        .unwrap();
+        assert_eq!("127.0.0.1:6380", client.addr);
+        assert_eq!(Some("pass".to_string()), client.password);
+        assert_eq!(2, client.db);
+        assert_eq!(std::time::Duration::from_secs(1), client.timeout);
+        assert_eq!(2, client.pool_size);
+
+        assert_eq!(
+            "Invalid error http://127.0.0.1 should be redis://",
+            RedisClient::new("http://127.0.0.1")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redis_pipeline() {
+        let url = start_redis_stand_in().await;
+        let client = RedisClient::new(&format!("{url}/1")).unwrap();
+        let values = client
+            .pipeline(&[
+                vec!["INCR".to_string(), "a".to_string()],
+                vec!["INCR".to_string(), "a".to_string()],
+                vec!["GET".to_string(), "a".to_string()],
+                vec!["GET".to_string(), "b".to_string()],
+            ])
+            .await
+            .unwrap();
+        assert_eq!(
+            vec![
+                RespValue::Int(1),
+                RespValue::Int(2),
+                RespValue::Str("2".to_string()),
+                RespValue::Nil,
+            ],
+            values
+        );
+        assert_eq!(2, values[2].as_int());
+        // the connection is reused
+        assert_eq!(1, client.connections.lock().unwrap().len());
+    }
+}
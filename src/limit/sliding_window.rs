@@ -0,0 +1,157 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::redis::RedisClient;
+use super::Result;
+use crate::util;
+use pingora_limits::rate::Rate;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::error;
+
+// the shared store is skipped for a while after it fails
+const STORE_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Sliding window rate which counts in a shared store,
+/// so the limit is applied to all instances.
+/// It falls back to the local rate when the store is unreachable.
+pub struct SharedRate {
+    client: RedisClient,
+    prefix: String,
+    interval: Duration,
+    local: Rate,
+    // unix timestamp(ms) until which the store is considered unavailable
+    unavailable_until: AtomicU64,
+}
+
+/// Estimate the count of the sliding window, the previous window
+/// is weighted by the part that still overlaps the sliding window.
+fn estimate(prev: i64, current: i64, elapsed: u64, interval: u64) -> isize {
+    if interval == 0 {
+        return current as isize;
+    }
+    let weight = 1.0 - (elapsed.min(interval) as f64 / interval as f64);
+    (prev as f64 * weight) as isize + current as isize
+}
+
+impl SharedRate {
+    /// Create a shared rate, the url is the address of redis
+    /// protocol compatible store, e.g. `redis://127.0.0.1:6379/0`
+    pub fn new(url: &str, prefix: &str, interval: Duration) -> Result<Self> {
+        let client = RedisClient::new(url)?;
+        Ok(Self {
+            client,
+            prefix: prefix.to_string(),
+            interval,
+            local: Rate::new(interval),
+            unavailable_until: AtomicU64::new(0),
+        })
+    }
+    async fn observe_shared(&self, key: &str, now: u64) -> Result<isize> {
+        let interval = self.interval.as_millis().max(1) as u64;
+        let window = now / interval;
+        let current_key = format!("{}:{key}:{window}", self.prefix);
+        let prev_key =
+            format!("{}:{key}:{}", self.prefix, window.saturating_sub(1));
+        let values = self
+            .client
+            .pipeline(&[
+                vec!["INCR".to_string(), current_key.clone()],
+                vec![
+                    "PEXPIRE".to_string(),
+                    current_key,
+                    (interval * 2).to_string(),
+                ],
+                vec!["GET".to_string(), prev_key],
+            ])
+            .await?;
+        let current = values.first().map(|v| v.as_int()).unwrap_or_default();
+        let prev = values.get(2).map(|v| v.as_int()).unwrap_or_default();
+        Ok(estimate(prev, current, now % interval, interval))
+    }
+    /// Increment the count of key and returns the estimated count of
+    /// the sliding window, the local rate is used if the store fails.
+    pub async fn observe(&self, key: &str) -> isize {
+        // the local rate is always observed,
+        // so it can take over when the store fails
+        self.local.observe(&key, 1);
+        let now = util::now().as_millis() as u64;
+        if now >= self.unavailable_until.load(Ordering::Relaxed) {
+            match self.observe_shared(key, now).await {
+                Ok(value) => return value,
+                Err(e) => {
+                    error!(
+                        error = e.to_string(),
+                        "shared limit store fail, fallback to local"
+                    );
+                    self.unavailable_until.store(
+                        now + STORE_RETRY_INTERVAL.as_millis() as u64,
+                        Ordering::Relaxed,
+                    );
+                },
+            }
+        }
+        self.local.rate(&key) as isize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{estimate, SharedRate};
+    use crate::limit::redis::tests::start_redis_stand_in;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+    use std::time::Duration;
+
+    #[test]
+    fn test_estimate() {
+        assert_eq!(3, estimate(0, 3, 500, 1000));
+        assert_eq!(8, estimate(10, 3, 500, 1000));
+        assert_eq!(13, estimate(10, 3, 0, 1000));
+        assert_eq!(3, estimate(10, 3, 1000, 1000));
+    }
+
+    #[tokio::test]
+    async fn test_shared_rate() {
+        let url = start_redis_stand_in().await;
+        let interval = Duration::from_secs(60);
+        let rate = SharedRate::new(&url, "pingap", interval).unwrap();
+        // another instance shares the same store
+        let other = SharedRate::new(&url, "pingap", interval).unwrap();
+
+        let mut values = vec![];
+        values.push(rate.observe("1.1.1.1").await);
+        values.push(other.observe("1.1.1.1").await);
+        values.push(rate.observe("1.1.1.1").await);
+        values.push(rate.observe("2.2.2.2").await);
+        // the window may be changed during test, the count of
+        // previous window is weighted, so it's not less than
+        assert_eq!(true, values[1] >= 2);
+        assert_eq!(true, values[2] >= 3);
+        assert_eq!(1, values[3]);
+    }
+
+    #[tokio::test]
+    async fn test_shared_rate_fallback() {
+        let rate = SharedRate::new(
+            "redis://127.0.0.1:1?timeout=100ms",
+            "pingap",
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert_eq!(0, rate.observe("1.1.1.1").await);
+        assert_eq!(true, rate.unavailable_until.load(Ordering::Relaxed) > 0);
+        assert_eq!(0, rate.observe("1.1.1.1").await);
+    }
+}
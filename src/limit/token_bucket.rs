@@ -0,0 +1,213 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::util;
+use lru::LruCache;
+use std::hash::{BuildHasher, RandomState};
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// the buckets are split into shards to reduce lock contention
+const SHARDS: usize = 64;
+
+#[derive(Debug)]
+struct Bucket {
+    // the tokens may be negative when requests are delayed
+    tokens: f64,
+    updated_at: u64,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct TokenResult {
+    // the request is allowed
+    pub allowed: bool,
+    // the request should be delayed before proceeding if allowed,
+    // otherwise it's the time after which the client can retry
+    pub wait: Duration,
+    // the remaining tokens
+    pub remaining: usize,
+    // the time until the bucket is full
+    pub reset: Duration,
+}
+
+/// Token bucket limiter, the bucket is refilled `max` tokens
+/// per interval and holds `burst` tokens at most.
+/// The max and burst are passed by each call, so the keys can
+/// have different quotas.
+pub struct TokenBucket {
+    // interval in milliseconds
+    interval: f64,
+    max_delay: Duration,
+    // the bucket is updated with the lock of its shard,
+    // so the concurrent requests of same key can't get the same token
+    shards: Vec<Mutex<LruCache<String, Bucket>>>,
+    hasher: RandomState,
+}
+
+impl TokenBucket {
+    /// Create a token bucket limiter, the request is delayed
+    /// if it can get the token within `max_delay`.
+    pub fn new(interval: Duration, max_delay: Duration) -> Self {
+        let size = NonZeroUsize::new(100_000 / SHARDS).unwrap();
+        Self {
+            interval: interval.as_millis().max(1) as f64,
+            max_delay,
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(LruCache::new(size)))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+    // the time to refill tokens
+    fn refill_time(&self, tokens: f64, max: f64) -> Duration {
+        if max <= 0.0 {
+            return Duration::MAX;
+        }
+        Duration::from_millis((tokens * self.interval / max).ceil() as u64)
+    }
+    /// Take a token of key.
+    pub fn take(&self, key: &str, max: usize, burst: usize) -> TokenResult {
+        self.take_at(key, max, burst, util::now().as_millis() as u64)
+    }
+    fn take_at(
+        &self,
+        key: &str,
+        max: usize,
+        burst: usize,
+        now: u64,
+    ) -> TokenResult {
+        let max = max as f64;
+        let burst = burst as f64;
+        let index = self.hasher.hash_one(key) as usize % SHARDS;
+        let Ok(mut buckets) = self.shards[index].lock() else {
+            return TokenResult {
+                allowed: true,
+                ..Default::default()
+            };
+        };
+        let bucket = buckets.get_or_insert_mut(key.to_string(), || Bucket {
+            tokens: burst,
+            updated_at: now,
+        });
+        let elapsed = now.saturating_sub(bucket.updated_at) as f64;
+        bucket.tokens =
+            (bucket.tokens + elapsed * max / self.interval).min(burst);
+        bucket.updated_at = now;
+
+        let mut allowed = true;
+        let mut wait = Duration::ZERO;
+        if bucket.tokens < 1.0 {
+            wait = self.refill_time(1.0 - bucket.tokens, max);
+            // reject if it needs to wait too long
+            allowed = wait <= self.max_delay;
+        }
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        TokenResult {
+            allowed,
+            wait,
+            remaining: bucket.tokens.max(0.0) as usize,
+            reset: self.refill_time(burst - bucket.tokens, max),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenBucket, TokenResult};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_token_bucket() {
+        // 10 tokens per second, burst 2
+        let bucket = TokenBucket::new(Duration::from_secs(1), Duration::ZERO);
+        assert_eq!(
+            TokenResult {
+                allowed: true,
+                wait: Duration::ZERO,
+                remaining: 1,
+                reset: Duration::from_millis(100),
+            },
+            bucket.take_at("a", 10, 2, 1000)
+        );
+        assert_eq!(true, bucket.take_at("a", 10, 2, 1000).allowed);
+        assert_eq!(
+            TokenResult {
+                allowed: false,
+                wait: Duration::from_millis(100),
+                remaining: 0,
+                reset: Duration::from_millis(200),
+            },
+            bucket.take_at("a", 10, 2, 1000)
+        );
+        // other key
+        assert_eq!(true, bucket.take_at("b", 10, 2, 1000).allowed);
+        // refilled one token
+        assert_eq!(true, bucket.take_at("a", 10, 2, 1100).allowed);
+        assert_eq!(false, bucket.take_at("a", 10, 2, 1100).allowed);
+        // the bucket holds burst tokens at most
+        assert_eq!(1, bucket.take_at("a", 10, 2, 9000).remaining);
+    }
+
+    #[test]
+    fn test_token_bucket_delay() {
+        let bucket = TokenBucket::new(
+            Duration::from_secs(1),
+            Duration::from_millis(250),
+        );
+        assert_eq!(Duration::ZERO, bucket.take_at("a", 10, 1, 1000).wait);
+        let result = bucket.take_at("a", 10, 1, 1000);
+        assert_eq!(true, result.allowed);
+        assert_eq!(Duration::from_millis(100), result.wait);
+        let result = bucket.take_at("a", 10, 1, 1000);
+        assert_eq!(true, result.allowed);
+        assert_eq!(Duration::from_millis(200), result.wait);
+        // wait too long
+        let result = bucket.take_at("a", 10, 1, 1000);
+        assert_eq!(false, result.allowed);
+        assert_eq!(Duration::from_millis(300), result.wait);
+    }
+
+    #[test]
+    fn test_token_bucket_quota() {
+        let bucket = TokenBucket::new(Duration::from_secs(1), Duration::ZERO);
+        assert_eq!(0, bucket.take_at("a", 1, 1, 1000).remaining);
+        assert_eq!(false, bucket.take_at("a", 1, 1, 1000).allowed);
+        // the key with higher quota
+        assert_eq!(4, bucket.take_at("b", 5, 5, 1000).remaining);
+    }
+
+    #[test]
+    fn test_token_bucket_concurrency() {
+        let bucket = TokenBucket::new(Duration::from_secs(1), Duration::ZERO);
+        let allowed = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        if bucket.take_at("a", 10, 100, 1000).allowed {
+                            allowed.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        // every token is taken only once
+        assert_eq!(100, allowed.load(Ordering::Relaxed));
+    }
+}
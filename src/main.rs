@@ -12,7 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::acme::{new_lets_encrypt_service, new_tls_validity_service};
+use crate::acme::{
+    new_certificate_watcher_service, new_lets_encrypt_service,
+    new_ocsp_stapling_service, new_tls_validity_service, AcmeAccountParams,
+    AcmeChallenge,
+};
 use crate::cache::new_file_storage_clear_service;
 use crate::config::ETCD_PROTOCOL;
 use crate::service::{new_auto_restart_service, new_observer_service};
@@ -451,13 +455,29 @@ fn run() -> Result<(), Box<dyn Error>> {
         }
         let file =
             Path::new(&util::resolve_path(&certificate_file)).to_path_buf();
-        // now supports lets encrypt only
-        enabled_lets_encrypt = true;
+        let challenge = if let Some(dns_provider) = &certificate.dns_provider {
+            AcmeChallenge::Dns01(dns_provider.clone())
+        } else if certificate.acme_challenge.as_deref() == Some("tls-alpn-01") {
+            AcmeChallenge::TlsAlpn01
+        } else {
+            AcmeChallenge::Http01
+        };
+        // the http-01 challenge needs the server of port 80
+        if challenge == AcmeChallenge::Http01 {
+            enabled_lets_encrypt = true;
+        }
         my_server.add_service(background_service(
             &format!("LetsEncrypt: {name}"),
             new_lets_encrypt_service(
                 file,
                 domains.split(',').map(|item| item.to_string()).collect(),
+                challenge,
+                AcmeAccountParams {
+                    provider: acme,
+                    email: certificate.acme_email.clone(),
+                    eab_kid: certificate.acme_eab_kid.clone(),
+                    eab_hmac_key: certificate.acme_eab_hmac_key.clone(),
+                },
             ),
         ));
     }
@@ -519,6 +539,14 @@ fn run() -> Result<(), Box<dyn Error>> {
         "TlsValidity",
         new_tls_validity_service(),
     ));
+    my_server.add_service(background_service(
+        "CertificateWatcher",
+        new_certificate_watcher_service(),
+    ));
+    my_server.add_service(background_service(
+        "OcspStapling",
+        new_ocsp_stapling_service(),
+    ));
     my_server.add_service(background_service(
         "UpstreamHc",
         new_upstream_health_check_task(Duration::from_secs(10)),
@@ -19,20 +19,33 @@ use super::{
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
 use crate::state::State;
-use crate::util::base64_decode;
+use crate::util::{self, base64_decode, Password};
 use async_trait::async_trait;
 use bytes::Bytes;
 use http::HeaderValue;
 use http::StatusCode;
 use humantime::parse_duration;
 use pingora::proxy::Session;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::Semaphore;
 use tokio::time::sleep;
 use tracing::debug;
 
+// the max count of bcrypt or argon2 verifications running in blocking pool
+const MAX_EXPENSIVE_VERIFICATIONS: usize = 16;
+
 pub struct BasicAuth {
     plugin_step: PluginStep,
     authorizations: Vec<Vec<u8>>,
+    // the user names of authorizations
+    consumers: Vec<String>,
+    // the user names and hashed passwords
+    credentials: Vec<(String, Password)>,
+    // the hash is verified if user is not found, so the time of
+    // unknown user is the same as known user
+    dummy_password: Option<Password>,
+    verify_permits: Arc<Semaphore>,
     hide_credentials: bool,
     miss_authorization_resp: HttpResponse,
     unauthorized_resp: HttpResponse,
@@ -57,25 +70,59 @@ impl TryFrom<&PluginConf> for BasicAuth {
             None
         };
         let mut authorizations = vec![];
+        let mut consumers = vec![];
         for item in get_str_slice_conf(value, "authorizations").iter() {
-            let _ = base64_decode(item).map_err(|e| Error::Base64Decode {
+            let data =
+                base64_decode(item).map_err(|e| Error::Base64Decode {
+                    category: PluginCategory::BasicAuth.to_string(),
+                    source: e,
+                })?;
+            let data = String::from_utf8_lossy(&data);
+            let user = data.split_once(':').map(|(user, _)| user);
+            consumers.push(user.unwrap_or(&data).to_string());
+            authorizations.push(format!("Basic {item}").as_bytes().to_vec());
+        }
+        let mut credentials = vec![];
+        let map_err = |e: util::Error| Error::Invalid {
+            category: PluginCategory::BasicAuth.to_string(),
+            message: e.to_string(),
+        };
+        // the htpasswd style lines, e.g. "admin:$2y$10$..."
+        let users = get_str_slice_conf(value, "users").join("\n");
+        credentials.extend(util::parse_htpasswd(&users).map_err(map_err)?);
+        let htpasswd = get_str_conf(value, "htpasswd");
+        if !htpasswd.is_empty() {
+            let data = std::fs::read_to_string(util::resolve_path(&htpasswd))
+                .map_err(|e| Error::Invalid {
                 category: PluginCategory::BasicAuth.to_string(),
-                source: e,
+                message: format!("read htpasswd fail, {e}"),
             })?;
-            authorizations.push(format!("Basic {item}").as_bytes().to_vec());
+            credentials.extend(util::parse_htpasswd(&data).map_err(map_err)?);
         }
-        if authorizations.is_empty() {
+        if authorizations.is_empty() && credentials.is_empty() {
             return Err(Error::Invalid {
                 category: PluginCategory::BasicAuth.to_string(),
                 message: "basic authorizations can't be empty".to_string(),
             });
         }
+        // use the hash of the same kind and cost as the credentials
+        let dummy_password = credentials
+            .iter()
+            .find(|(_, hash)| hash.is_expensive())
+            .or(credentials.first())
+            .map(|(_, hash)| hash.clone());
         let params = Self {
             hash_value,
             plugin_step: step,
             delay,
             hide_credentials: get_bool_conf(value, "hide_credentials"),
             authorizations,
+            consumers,
+            credentials,
+            dummy_password,
+            verify_permits: Arc::new(Semaphore::new(
+                MAX_EXPENSIVE_VERIFICATIONS,
+            )),
             miss_authorization_resp: HttpResponse {
                 status: StatusCode::UNAUTHORIZED,
                 headers: Some(vec![(
@@ -117,6 +164,38 @@ impl BasicAuth {
         debug!(params = params.to_string(), "new basic auth plugin");
         Self::try_from(params)
     }
+    /// Verify the user and password of authorization with
+    /// the credentials, returns the user if it's valid.
+    async fn verify_credentials(&self, value: &[u8]) -> Option<String> {
+        let value = std::str::from_utf8(value).ok()?.strip_prefix("Basic ")?;
+        let data = base64_decode(value.trim()).ok()?;
+        let data = String::from_utf8(data).ok()?;
+        let (user, password) = data.split_once(':')?;
+        let found = self.credentials.iter().find(|(name, _)| name == user);
+        let hash = match found {
+            Some((_, hash)) => hash,
+            None => self.dummy_password.as_ref()?,
+        };
+        let valid = if hash.is_expensive() {
+            // the permit is moved into the task, so it's released
+            // after the verification even if the request is dropped
+            let permit =
+                self.verify_permits.clone().acquire_owned().await.ok()?;
+            let hash = hash.clone();
+            let password = password.to_string();
+            tokio::task::spawn_blocking(move || {
+                let valid = hash.verify(password.as_bytes());
+                drop(permit);
+                valid
+            })
+            .await
+            .unwrap_or_default()
+        } else {
+            hash.verify(password.as_bytes())
+        };
+        // the result of dummy password is always ignored
+        (valid && found.is_some()).then(|| user.to_string())
+    }
 }
 
 #[async_trait]
@@ -130,7 +209,7 @@ impl Plugin for BasicAuth {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
@@ -139,12 +218,21 @@ impl Plugin for BasicAuth {
         if value.is_empty() {
             return Ok(Some(self.miss_authorization_resp.clone()));
         }
-        if !self.authorizations.contains(&value.to_vec()) {
+        let consumer = match self
+            .authorizations
+            .iter()
+            .position(|item| util::constant_time_eq(item, value))
+        {
+            Some(index) => self.consumers.get(index).cloned(),
+            None => self.verify_credentials(value).await,
+        };
+        let Some(consumer) = consumer else {
             if let Some(d) = self.delay {
                 sleep(d).await;
             }
             return Ok(Some(self.unauthorized_resp.clone()));
-        }
+        };
+        ctx.consumer = Some(consumer);
         if self.hide_credentials {
             session
                 .req_header_mut()
@@ -242,15 +330,13 @@ authorizations = [
         let mock_io = Builder::new().read(input_header.as_bytes()).build();
         let mut session = Session::new_h1(Box::new(mock_io));
         session.read_request().await.unwrap();
+        let mut ctx = State::default();
         let result = auth
-            .handle_request(
-                PluginStep::Request,
-                &mut session,
-                &mut State::default(),
-            )
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
             .await
             .unwrap();
         assert_eq!(true, result.is_none());
+        assert_eq!(Some("admin".to_string()), ctx.consumer);
 
         // auth fail
         let headers = ["Authorization: Basic YWRtaW46MTIzMTIa"].join("\r\n");
@@ -270,4 +356,66 @@ authorizations = [
         assert_eq!(true, result.is_some());
         assert_eq!(StatusCode::UNAUTHORIZED, result.unwrap().status);
     }
+
+    #[tokio::test]
+    async fn test_basic_auth_credentials() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join(".htpasswd");
+        std::fs::write(&file, "tree:$apr1$abcdefgh$DKG7DKqXLFT1328cV5wDe0\n")
+            .unwrap();
+        let auth = BasicAuth::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+users = [
+    "pingap:$2y$04$abcdefghijklmnopqrstuuh.pLFdOiKKF4SAufq8Znnqk23MEwr3i"
+]
+htpasswd = "{}"
+"###,
+                file.to_string_lossy()
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(2, auth.credentials.len());
+        assert_eq!(true, auth.dummy_password.as_ref().unwrap().is_expensive());
+
+        let check = |authorization: &str| {
+            let auth = &auth;
+            let input_header = format!(
+                "GET / HTTP/1.1\r\nAuthorization: Basic {authorization}\r\n\r\n"
+            );
+            async move {
+                let mock_io =
+                    Builder::new().read(input_header.as_bytes()).build();
+                let mut session = Session::new_h1(Box::new(mock_io));
+                session.read_request().await.unwrap();
+                let mut ctx = State::default();
+                let result = auth
+                    .handle_request(PluginStep::Request, &mut session, &mut ctx)
+                    .await
+                    .unwrap();
+                (result.map(|resp| resp.status), ctx.consumer)
+            }
+        };
+        // pingap:pingap
+        assert_eq!(
+            (None, Some("pingap".to_string())),
+            check("cGluZ2FwOnBpbmdhcA==").await
+        );
+        // tree:pingap
+        assert_eq!(
+            (None, Some("tree".to_string())),
+            check("dHJlZTpwaW5nYXA=").await
+        );
+        // tree:123123
+        assert_eq!(
+            (Some(StatusCode::UNAUTHORIZED), None),
+            check("dHJlZToxMjMxMjM=").await
+        );
+        // admin:pingap, the password of dummy hash is not accepted
+        assert_eq!(
+            (Some(StatusCode::UNAUTHORIZED), None),
+            check("YWRtaW46cGluZ2Fw").await
+        );
+    }
 }
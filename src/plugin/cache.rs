@@ -54,6 +54,8 @@ pub struct Cache {
     http_cache: &'static HttpCache,
     max_file_size: usize,
     max_ttl: Option<Duration>,
+    stale_while_revalidate: Option<Duration>,
+    stale_if_error: Option<Duration>,
     namespace: Option<String>,
     headers: Option<Vec<String>>,
     check_cache_control: bool,
@@ -143,15 +145,22 @@ impl TryFrom<&PluginConf> for Cache {
             Duration::from_secs(1)
         };
 
-        let max_ttl = get_str_conf(value, "max_ttl");
-        let max_ttl = if !max_ttl.is_empty() {
-            Some(parse_duration(&max_ttl).map_err(|e| Error::Invalid {
+        let get_duration_conf = |key: &str| -> Result<Option<Duration>> {
+            let value = get_str_conf(value, key);
+            if value.is_empty() {
+                return Ok(None);
+            }
+            let d = parse_duration(&value).map_err(|e| Error::Invalid {
                 category: PluginCategory::Cache.to_string(),
                 message: e.to_string(),
-            })?)
-        } else {
-            None
+            })?;
+            Ok(Some(d))
         };
+        let max_ttl = get_duration_conf("max_ttl")?;
+        // override the stale directives of cache-control from upstream
+        let stale_while_revalidate =
+            get_duration_conf("stale_while_revalidate")?;
+        let stale_if_error = get_duration_conf("stale_if_error")?;
 
         let max_file_size = get_str_conf(value, "max_file_size");
         let max_file_size = if !max_file_size.is_empty() {
@@ -192,6 +201,8 @@ impl TryFrom<&PluginConf> for Cache {
             predictor,
             lock: get_cache_lock(lock),
             max_ttl,
+            stale_while_revalidate,
+            stale_if_error,
             max_file_size: max_file_size.as_u64() as usize,
             namespace,
             headers,
@@ -294,6 +305,8 @@ impl Plugin for Cache {
 
         // max age of cache control
         ctx.cache_max_ttl = self.max_ttl;
+        ctx.cache_stale_while_revalidate = self.stale_while_revalidate;
+        ctx.cache_stale_if_error = self.stale_if_error;
         ctx.check_cache_control = self.check_cache_control;
 
         session.cache.enable(
@@ -336,6 +349,8 @@ lock = "2s"
 max_file_size = "100kb"
 predictor = true
 max_ttl = "1m"
+stale_while_revalidate = "10s"
+stale_if_error = "1h"
 "###,
             )
             .unwrap(),
@@ -349,7 +364,22 @@ max_ttl = "1m"
         assert_eq!(true, params.lock.is_some());
         assert_eq!(100 * 1000, params.max_file_size);
         assert_eq!(60, params.max_ttl.unwrap().as_secs());
+        assert_eq!(10, params.stale_while_revalidate.unwrap().as_secs());
+        assert_eq!(3600, params.stale_if_error.unwrap().as_secs());
         assert_eq!(true, params.predictor.is_some());
+
+        let result = Cache::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+stale_if_error = "1x"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin cache invalid, message: unknown time unit \"x\", supported units: ns, us, ms, sec, min, hours, days, weeks, months, years (and few variations)",
+            result.err().unwrap().to_string()
+        );
     }
     #[tokio::test]
     async fn test_cache() {
@@ -0,0 +1,454 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::HttpResponse;
+use crate::proxy::new_subrequest_peer;
+use crate::state::State;
+use crate::util;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use http::{header, HeaderName};
+use humantime::parse_duration;
+use once_cell::sync::Lazy;
+use pingora::connectors::http::Connector;
+use pingora::http::{RequestHeader, ResponseHeader};
+use pingora::proxy::Session;
+use pingora::upstreams::peer::{HttpPeer, Peer};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{debug, error};
+
+// the body of deny response is truncated if it's larger than this size
+const MAX_DENY_BODY_SIZE: usize = 64 * 1024;
+
+static EXT_AUTH_CONNECTOR: Lazy<Connector> = Lazy::new(|| Connector::new(None));
+
+/// External authorization, the request is sent to the auth upstream
+/// before proxying, it's allowed only if the auth service responds 2xx.
+/// The backend of auth upstream is selected as the mirror request,
+/// so the health check and tls settings of upstream are used.
+pub struct ExtAuth {
+    plugin_step: PluginStep,
+    // the name of auth upstream
+    upstream: String,
+    // the path of auth request
+    path: String,
+    // the headers of request which are sent to the auth service
+    request_headers: Vec<HeaderName>,
+    // the headers of auth response which are set to upstream request
+    response_headers: Vec<HeaderName>,
+    timeout: Duration,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for ExtAuth {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_error = |message: String| Error::Invalid {
+            category: PluginCategory::ExtAuth.to_string(),
+            message,
+        };
+        let upstream = get_str_conf(value, "upstream");
+        if upstream.is_empty() {
+            return Err(new_error(
+                "Ext auth upstream is not allowed empty".to_string(),
+            ));
+        }
+        let mut path = get_str_conf(value, "path");
+        if path.is_empty() {
+            path = "/".to_string();
+        }
+        if !path.starts_with('/') {
+            return Err(new_error(format!(
+                "Ext auth path({path}) should start with /"
+            )));
+        }
+        let parse_headers = |key: &str| -> Result<Vec<HeaderName>> {
+            get_str_slice_conf(value, key)
+                .iter()
+                .map(|item| {
+                    HeaderName::from_str(item.trim()).map_err(|e| {
+                        new_error(format!("invalid header name, {e}"))
+                    })
+                })
+                .collect()
+        };
+        let mut request_headers = parse_headers("request_headers")?;
+        if request_headers.is_empty() {
+            request_headers = vec![header::AUTHORIZATION, header::COOKIE];
+        }
+        let timeout = get_str_conf(value, "timeout");
+        let timeout = if timeout.is_empty() {
+            Duration::from_secs(5)
+        } else {
+            parse_duration(&timeout).map_err(|e| new_error(e.to_string()))?
+        };
+        let params = Self {
+            hash_value,
+            plugin_step: get_step_conf(value),
+            upstream,
+            path,
+            request_headers,
+            response_headers: parse_headers("response_headers")?,
+            timeout,
+        };
+        if ![PluginStep::Request, PluginStep::ProxyUpstream]
+            .contains(&params.plugin_step)
+        {
+            return Err(new_error(
+                "Ext auth plugin should be executed at request or proxy upstream step".to_string(),
+            ));
+        }
+        Ok(params)
+    }
+}
+
+impl ExtAuth {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new ext auth plugin");
+        Self::try_from(params)
+    }
+    fn new_auth_request(
+        &self,
+        peer: &HttpPeer,
+        session: &Session,
+    ) -> pingora::Result<RequestHeader> {
+        let req_header = session.req_header();
+        let uri = req_header
+            .uri
+            .path_and_query()
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+        let host = if peer.sni().is_empty() {
+            peer.address().to_string()
+        } else {
+            peer.sni().to_string()
+        };
+        let mut req = RequestHeader::build("GET", self.path.as_bytes(), None)?;
+        req.insert_header(header::HOST, host)?;
+        req.insert_header("X-Forwarded-Method", req_header.method.as_str())?;
+        req.insert_header("X-Forwarded-Uri", uri)?;
+        req.insert_header(
+            "X-Forwarded-Host",
+            util::get_host(req_header).unwrap_or_default(),
+        )?;
+        req.insert_header("X-Forwarded-For", util::get_client_ip(session))?;
+        for name in self.request_headers.iter() {
+            for value in req_header.headers.get_all(name) {
+                req.append_header(name, value)?;
+            }
+        }
+        Ok(req)
+    }
+    /// Send the auth request to the peer, returns the response header
+    /// and the body which is truncated to the max size.
+    async fn do_auth_request(
+        &self,
+        peer: &HttpPeer,
+        req: RequestHeader,
+    ) -> pingora::Result<(ResponseHeader, Bytes)> {
+        let (mut session, _) =
+            EXT_AUTH_CONNECTOR.get_http_session(peer).await?;
+        session.write_request_header(Box::new(req)).await?;
+        session.finish_request_body().await?;
+        session.read_response_header().await?;
+        let resp = session.response_header().cloned().ok_or_else(|| {
+            util::new_internal_error(
+                500,
+                "Ext auth response header is empty".to_string(),
+            )
+        })?;
+        let mut body = BytesMut::new();
+        while let Some(data) = session.read_response_body().await? {
+            if body.len() + data.len() > MAX_DENY_BODY_SIZE {
+                let size = MAX_DENY_BODY_SIZE - body.len();
+                body.extend_from_slice(&data[..size]);
+                // the connection is not reused as the body isn't read done
+                return Ok((resp, body.freeze()));
+            }
+            body.extend_from_slice(&data);
+        }
+        EXT_AUTH_CONNECTOR
+            .release_http_session(session, peer, peer.options.idle_timeout)
+            .await;
+        Ok((resp, body.freeze()))
+    }
+    async fn authorize(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        let req = self.new_auth_request(peer, session)?;
+        let result =
+            tokio::time::timeout(self.timeout, self.do_auth_request(peer, req))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(util::new_internal_error(
+                        500,
+                        "Ext auth request timeout".to_string(),
+                    ))
+                });
+        let (resp, body) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                error!(
+                    error = e.to_string(),
+                    upstream = self.upstream,
+                    addr = peer.address().to_string(),
+                    "request ext auth fail"
+                );
+                return Err(util::new_internal_error(
+                    500,
+                    "Ext auth service is unavailable".to_string(),
+                ));
+            },
+        };
+        let status = resp.status;
+        if status.is_success() {
+            let req_header = session.req_header_mut();
+            for name in self.response_headers.iter() {
+                // the header from client should not be trusted
+                req_header.remove_header(name);
+                for value in resp.headers.get_all(name) {
+                    let _ = req_header.append_header(name, value);
+                }
+            }
+            return Ok(None);
+        }
+        // the response of auth service is returned to client
+        let mut headers = vec![];
+        for (name, value) in resp.headers.iter() {
+            if [
+                header::CONTENT_LENGTH,
+                header::TRANSFER_ENCODING,
+                header::CONNECTION,
+            ]
+            .contains(name)
+            {
+                continue;
+            }
+            headers.push((name.to_owned(), value.to_owned()));
+        }
+        Ok(Some(HttpResponse {
+            status,
+            body,
+            headers: Some(headers),
+            ..Default::default()
+        }))
+    }
+}
+
+#[async_trait]
+impl Plugin for ExtAuth {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let Some(peer) = new_subrequest_peer(&self.upstream, session) else {
+            error!(
+                upstream = self.upstream,
+                "ext auth upstream is unavailable"
+            );
+            return Err(util::new_internal_error(
+                500,
+                "Ext auth service is unavailable".to_string(),
+            ));
+        };
+        self.authorize(&peer, session).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtAuth, MAX_DENY_BODY_SIZE};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use http::StatusCode;
+    use pingora::proxy::Session;
+    use pingora::upstreams::peer::HttpPeer;
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio_test::io::Builder;
+
+    /// Start a local auth service, the request with token
+    /// is allowed, the request of large path is denied with a large body
+    /// and others are redirected to login.
+    async fn start_auth_service() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut reader = BufReader::new(stream);
+                let mut headers = vec![];
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim().to_lowercase());
+                }
+                let allowed = headers.contains(&"authorization: token".into())
+                    && headers.contains(&"x-forwarded-method: delete".into())
+                    && headers.contains(&"x-forwarded-uri: /users?id=1".into());
+                let large = headers.contains(&"x-forwarded-uri: /large".into());
+                let resp = if allowed {
+                    "HTTP/1.1 200 OK\r\nX-User-Id: 123\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+                } else if large {
+                    let body = "a".repeat(1024 * 1024);
+                    format!("HTTP/1.1 401 Unauthorized\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+                } else {
+                    "HTTP/1.1 302 Found\r\nLocation: /login\r\nContent-Length: 5\r\nConnection: close\r\n\r\nlogin".to_string()
+                };
+                let mut stream = reader.into_inner();
+                let _ = stream.write_all(resp.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_ext_auth_params() {
+        let auth = ExtAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+upstream = "auth"
+path = "/auth"
+response_headers = ["X-User-Id"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            r#"["authorization", "cookie"]"#,
+            format!("{:?}", auth.request_headers)
+        );
+        assert_eq!(r#"["x-user-id"]"#, format!("{:?}", auth.response_headers));
+        assert_eq!("/auth", auth.path);
+
+        let result = ExtAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+path = "/auth"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin ext_auth invalid, message: Ext auth upstream is not allowed empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = ExtAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+upstream = "auth"
+path = "auth"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin ext_auth invalid, message: Ext auth path(auth) should start with /",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ext_auth() {
+        let addr = start_auth_service().await;
+        let peer = HttpPeer::new(addr, false, "".to_string());
+        let auth = ExtAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+upstream = "ext-auth-not-found"
+path = "/auth"
+response_headers = ["X-User-Id"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let headers =
+            ["Authorization: token", "X-User-Id: 1", "X-Uuid: 138q71"]
+                .join("\r\n");
+        let input_header =
+            format!("DELETE /users?id=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = auth.authorize(&peer, &mut session).await.unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(
+            "123",
+            session.req_header().headers.get("X-User-Id").unwrap()
+        );
+
+        let input_header = "GET /users HTTP/1.1\r\nX-Uuid: 138q71\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = auth.authorize(&peer, &mut session).await.unwrap().unwrap();
+        assert_eq!(StatusCode::FOUND, resp.status);
+        assert_eq!("login", std::str::from_utf8(&resp.body).unwrap());
+        assert_eq!(
+            r#"Some([("location", "/login")])"#,
+            format!("{:?}", resp.headers)
+        );
+
+        // the body of deny response is truncated
+        let input_header = "GET /large HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let resp = auth.authorize(&peer, &mut session).await.unwrap().unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status);
+        assert_eq!(MAX_DENY_BODY_SIZE, resp.body.len());
+
+        // the upstream is not found
+        let input_header = "GET /users HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = auth
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await;
+        assert_eq!(true, result.is_err());
+    }
+}
@@ -19,11 +19,13 @@ use super::{
 use crate::config::{PluginCategory, PluginConf, PluginStep};
 use crate::http_extra::HttpResponse;
 use crate::state::State;
-use crate::util;
+use crate::util::{self, Password};
+use ahash::AHashMap;
 use async_trait::async_trait;
 use bytes::Bytes;
 use http::{HeaderName, StatusCode};
 use humantime::parse_duration;
+use openssl::hash::{hash, MessageDigest};
 use pingora::proxy::Session;
 use std::str::FromStr;
 use std::time::Duration;
@@ -35,6 +37,12 @@ pub struct KeyAuth {
     header: Option<HeaderName>,
     query: Option<String>,
     keys: Vec<Vec<u8>>,
+    // the consumer names of keys
+    consumers: Vec<Option<String>>,
+    // the sha256 and sha1 digests of hashed keys and consumer names,
+    // the digest of request key is looked up, so only one hash is computed
+    sha256_keys: AHashMap<Vec<u8>, Option<String>>,
+    sha1_keys: AHashMap<Vec<u8>, Option<String>>,
     delay: Option<Duration>,
     miss_authorization_resp: HttpResponse,
     unauthorized_resp: HttpResponse,
@@ -79,11 +87,58 @@ impl TryFrom<&PluginConf> for KeyAuth {
                 }
             })?);
         }
-        let keys: Vec<Vec<u8>> = get_str_slice_conf(value, "keys")
-            .iter()
-            .map(|item| item.as_bytes().to_vec())
-            .collect();
-        if keys.is_empty() {
+        let mut keys = vec![];
+        let mut consumers = vec![];
+        let mut sha256_keys = AHashMap::new();
+        let mut sha1_keys = AHashMap::new();
+        // the whole value is the key, the consumer of key is set by
+        // consumer_keys, e.g. "vip:123123" or "vip:{SHA256}base64"
+        let mut items: Vec<(String, Option<String>)> =
+            get_str_slice_conf(value, "keys")
+                .into_iter()
+                .filter(|key| !key.is_empty())
+                .map(|key| (key, None))
+                .collect();
+        for item in get_str_slice_conf(value, "consumer_keys").iter() {
+            let Some((consumer, key)) = item.split_once(':') else {
+                return Err(Error::Invalid {
+                    category: PluginCategory::KeyAuth.to_string(),
+                    message: format!(
+                        "consumer key {item} is invalid, it should be consumer:key"
+                    ),
+                });
+            };
+            items.push((key.to_string(), Some(consumer.trim().to_string())));
+        }
+        for (key, consumer) in items {
+            let password = Password::new(&key).map_err(|e| Error::Invalid {
+                category: PluginCategory::KeyAuth.to_string(),
+                message: e.to_string(),
+            })?;
+            match password {
+                Password::Plain(_) => {
+                    keys.push(key.into_bytes());
+                    consumers.push(consumer);
+                },
+                Password::Sha256(digest) => {
+                    sha256_keys.insert(digest, consumer);
+                },
+                Password::Sha1(digest) => {
+                    sha1_keys.insert(digest, consumer);
+                },
+                // the slow hashes can't be indexed,
+                // every unknown key would be verified with all of them
+                _ => {
+                    return Err(Error::Invalid {
+                        category: PluginCategory::KeyAuth.to_string(),
+                        message: format!(
+                            "hashed key {key} is not supported, use {{SHA256}} instead"
+                        ),
+                    });
+                },
+            }
+        }
+        if keys.is_empty() && sha256_keys.is_empty() && sha1_keys.is_empty() {
             return Err(Error::Invalid {
                 category: PluginCategory::KeyAuth.to_string(),
                 message: "auth keys can't be empty".to_string(),
@@ -92,6 +147,9 @@ impl TryFrom<&PluginConf> for KeyAuth {
         let params = Self {
             hash_value,
             keys,
+            consumers,
+            sha256_keys,
+            sha1_keys,
             hide_credentials: get_bool_conf(value, "hide_credentials"),
             plugin_step: step,
             query,
@@ -125,6 +183,24 @@ impl KeyAuth {
         debug!(params = params.to_string(), "new key auth plugin");
         Self::try_from(params)
     }
+    /// Get the consumer of hashed key by the digest of request key.
+    fn get_hashed_key_consumer(&self, value: &[u8]) -> Option<Option<String>> {
+        for (keys, md) in [
+            (&self.sha256_keys, MessageDigest::sha256()),
+            (&self.sha1_keys, MessageDigest::sha1()),
+        ] {
+            if keys.is_empty() {
+                continue;
+            }
+            let Ok(digest) = hash(md, value) else {
+                continue;
+            };
+            if let Some(consumer) = keys.get(digest.as_ref()) {
+                return Some(consumer.clone());
+            }
+        }
+        None
+    }
 }
 
 #[async_trait]
@@ -138,7 +214,7 @@ impl Plugin for KeyAuth {
         &self,
         step: PluginStep,
         session: &mut Session,
-        _ctx: &mut State,
+        ctx: &mut State,
     ) -> pingora::Result<Option<HttpResponse>> {
         if step != self.plugin_step {
             return Ok(None);
@@ -156,12 +232,21 @@ impl Plugin for KeyAuth {
         if value.is_empty() {
             return Ok(Some(self.miss_authorization_resp.clone()));
         }
-        if !self.keys.contains(&value.to_vec()) {
+        let consumer = match self
+            .keys
+            .iter()
+            .position(|item| util::constant_time_eq(item, value))
+        {
+            Some(index) => self.consumers.get(index).cloned(),
+            None => self.get_hashed_key_consumer(value),
+        };
+        let Some(consumer) = consumer else {
             if let Some(d) = self.delay {
                 sleep(d).await;
             }
             return Ok(Some(self.unauthorized_resp.clone()));
-        }
+        };
+        ctx.consumer = consumer;
         if self.hide_credentials {
             if let Some(name) = &self.header {
                 session.req_header_mut().remove_header(name);
@@ -250,6 +335,22 @@ keys = [
             "Plugin key_auth invalid, message: Key auth plugin should be executed at request or proxy upstream step",
             result.err().unwrap().to_string()
         );
+
+        let result = KeyAuth::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-User"
+keys = [
+    "$2y$04$abcdefghijklmnopqrstuuh.pLFdOiKKF4SAufq8Znnqk23MEwr3i",
+]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin key_auth invalid, message: hashed key $2y$04$abcdefghijklmnopqrstuuh.pLFdOiKKF4SAufq8Znnqk23MEwr3i is not supported, use {SHA256} instead",
+            result.err().unwrap().to_string()
+        );
     }
 
     #[tokio::test]
@@ -258,10 +359,8 @@ keys = [
             &toml::from_str::<PluginConf>(
                 r###"
 header = "X-User"
-keys = [
-    "123",
-    "456",
-]
+keys = ["456", "key with space"]
+consumer_keys = ["vip:123"]
 hide_credentials = true
 "###,
             )
@@ -276,16 +375,42 @@ hide_credentials = true
         let mut session = Session::new_h1(Box::new(mock_io));
         session.read_request().await.unwrap();
         assert_eq!(false, session.get_header_bytes("X-User").is_empty());
+        let mut ctx = State::default();
         let result = auth
-            .handle_request(
-                PluginStep::Request,
-                &mut session,
-                &mut State::default(),
-            )
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
             .await
             .unwrap();
         assert_eq!(true, result.is_none());
         assert_eq!(true, session.get_header_bytes("X-User").is_empty());
+        assert_eq!(Some("vip".to_string()), ctx.consumer);
+        assert_eq!(b"key with space".to_vec(), auth.keys[1]);
+
+        // hashed key
+        let auth = KeyAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-User"
+consumer_keys = [
+    "svip:{SHA256}1/HMZ1tMri9mMw+y2//nN5lz3+lAxAClZjlwCVYeu6g=",
+]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(0, auth.keys.len());
+        assert_eq!(1, auth.sha256_keys.len());
+        let input_header = "GET / HTTP/1.1\r\nX-User: pingap\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = State::default();
+        let result = auth
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        assert_eq!(Some("svip".to_string()), ctx.consumer);
 
         let headers = ["X-User: 12"].join("\r\n");
         let input_header =
@@ -13,36 +13,71 @@
 // limitations under the License.
 
 use super::{
-    get_hash_key, get_int_conf, get_step_conf, get_str_conf, Error, Plugin,
-    Result,
+    get_hash_key, get_int_conf, get_step_conf, get_str_conf,
+    get_str_slice_conf, Error, Plugin, Result,
 };
 use crate::config::{PluginCategory, PluginConf, PluginStep};
-use crate::http_extra::HttpResponse;
+use crate::http_extra::{HttpHeader, HttpResponse};
+use crate::limit::{SharedRate, TokenBucket};
 use crate::state::State;
 use crate::util;
+use ahash::AHashMap;
 use async_trait::async_trait;
-use http::StatusCode;
+use http::{HeaderName, HeaderValue, StatusCode};
 use humantime::parse_duration;
+use pingora::http::ResponseHeader;
 use pingora::proxy::Session;
 use pingora_limits::inflight::Inflight;
 use pingora_limits::rate::Rate;
 use std::time::Duration;
 use tracing::debug;
 
+static RATE_LIMIT_LIMIT: HeaderName =
+    HeaderName::from_static("ratelimit-limit");
+static RATE_LIMIT_REMAINING: HeaderName =
+    HeaderName::from_static("ratelimit-remaining");
+static RATE_LIMIT_RESET: HeaderName =
+    HeaderName::from_static("ratelimit-reset");
+
 #[derive(PartialEq, Debug)]
 pub enum LimitTag {
     Ip,
     RequestHeader,
     Cookie,
     Query,
+    Path,
+    Method,
+    // the consumer identity set by auth plugins, fallback to client ip
+    Consumer,
+}
+
+impl LimitTag {
+    fn new(value: &str) -> Self {
+        match value {
+            "cookie" => LimitTag::Cookie,
+            "header" => LimitTag::RequestHeader,
+            "query" => LimitTag::Query,
+            "path" => LimitTag::Path,
+            "method" => LimitTag::Method,
+            "consumer" => LimitTag::Consumer,
+            _ => LimitTag::Ip,
+        }
+    }
 }
 
 pub struct Limiter {
-    tag: LimitTag,
+    // the limit key is composed of the values of tags
+    tags: Vec<(LimitTag, String)>,
     max: isize,
-    key: String,
+    // the max value of consumers
+    consumer_max: AHashMap<String, isize>,
+    burst: isize,
+    interval: Duration,
     inflight: Option<Inflight>,
     rate: Option<Rate>,
+    // sliding window rate which counts in the shared store
+    shared_rate: Option<SharedRate>,
+    token_bucket: Option<TokenBucket>,
     plugin_step: PluginStep,
     hash_value: String,
 }
@@ -53,12 +88,34 @@ impl TryFrom<&PluginConf> for Limiter {
         let hash_value = get_hash_key(value);
         let step = get_step_conf(value);
 
-        let tag = match get_str_conf(value, "tag").as_str() {
-            "cookie" => LimitTag::Cookie,
-            "header" => LimitTag::RequestHeader,
-            "query" => LimitTag::Query,
-            _ => LimitTag::Ip,
-        };
+        // composite tags, e.g. ["ip", "path"] or ["header:X-Uuid", "method"]
+        let mut tags: Vec<(LimitTag, String)> =
+            get_str_slice_conf(value, "tags")
+                .iter()
+                .map(|item| {
+                    let (tag, key) = item.split_once(':').unwrap_or((item, ""));
+                    (LimitTag::new(tag.trim()), key.trim().to_string())
+                })
+                .collect();
+        if tags.is_empty() {
+            tags.push((
+                LimitTag::new(&get_str_conf(value, "tag")),
+                get_str_conf(value, "key"),
+            ));
+        }
+        let mut consumer_max = AHashMap::new();
+        // the max value of consumer, e.g. "vip 100"
+        for item in get_str_slice_conf(value, "consumer_max").iter() {
+            let Some((consumer, max)) = item.split_once(' ') else {
+                continue;
+            };
+            let max =
+                max.trim().parse::<isize>().map_err(|e| Error::Invalid {
+                    category: PluginCategory::Limit.to_string(),
+                    message: format!("invalid consumer max {item}, {e}"),
+                })?;
+            consumer_max.insert(consumer.to_string(), max);
+        }
         let interval = get_str_conf(value, "interval");
         let interval = if !interval.is_empty() {
             parse_duration(&interval).map_err(|e| Error::Invalid {
@@ -68,21 +125,64 @@ impl TryFrom<&PluginConf> for Limiter {
         } else {
             Duration::from_secs(10)
         };
+        let max = get_int_conf(value, "max") as isize;
         let mut inflight = None;
         let mut rate = None;
-        if get_str_conf(value, "type") == "inflight" {
+        let mut shared_rate = None;
+        let mut token_bucket = None;
+        let store = get_str_conf(value, "store");
+        let category = get_str_conf(value, "type");
+        if ["inflight", "token_bucket"].contains(&category.as_str())
+            && !store.is_empty()
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::Limit.to_string(),
+                message: "Shared store only supports rate limit".to_string(),
+            });
+        }
+        if category == "inflight" {
             inflight = Some(Inflight::new());
+        } else if category == "token_bucket" {
+            if max <= 0 {
+                return Err(Error::Invalid {
+                    category: PluginCategory::Limit.to_string(),
+                    message: "Max of token bucket should be gt 0".to_string(),
+                });
+            }
+            let delay = get_str_conf(value, "delay");
+            let delay = if !delay.is_empty() {
+                parse_duration(&delay).map_err(|e| Error::Invalid {
+                    category: PluginCategory::Limit.to_string(),
+                    message: e.to_string(),
+                })?
+            } else {
+                Duration::ZERO
+            };
+            token_bucket = Some(TokenBucket::new(interval, delay));
+        } else if !store.is_empty() {
+            let prefix = format!("pingap:limit:{hash_value}");
+            shared_rate =
+                Some(SharedRate::new(&store, &prefix, interval).map_err(
+                    |e| Error::Invalid {
+                        category: PluginCategory::Limit.to_string(),
+                        message: e.to_string(),
+                    },
+                )?);
         } else {
             rate = Some(Rate::new(interval));
         }
 
         let params = Self {
             hash_value,
-            tag,
-            key: get_str_conf(value, "key"),
-            max: get_int_conf(value, "max") as isize,
+            tags,
+            max,
+            consumer_max,
+            burst: get_int_conf(value, "burst") as isize,
+            interval,
             inflight,
             rate,
+            shared_rate,
+            token_bucket,
             plugin_step: step,
         };
         if ![PluginStep::Request, PluginStep::ProxyUpstream]
@@ -102,54 +202,177 @@ impl Limiter {
         debug!(params = params.to_string(), "new limit plugin");
         Self::try_from(params)
     }
-    /// Increment `key` by 1. If value gt max, an error will be return.
-    /// Otherwise returns a Guard. It may set the client ip to context.
-    pub fn incr(&self, session: &Session, ctx: &mut State) -> Result<()> {
-        let key = match self.tag {
-            LimitTag::Query => {
-                util::get_query_value(session.req_header(), &self.key)
-                    .unwrap_or_default()
-                    .to_string()
-            },
-            LimitTag::RequestHeader => {
-                util::get_req_header_value(session.req_header(), &self.key)
+    fn get_client_ip(session: &Session, ctx: &mut State) -> String {
+        if let Some(client_ip) = &ctx.client_ip {
+            return client_ip.clone();
+        }
+        let client_ip = util::get_client_ip(session);
+        ctx.client_ip = Some(client_ip.clone());
+        client_ip
+    }
+    /// Get the limit key of request, the values of tags are joined by `:`.
+    /// It may set the client ip to context.
+    fn get_key(&self, session: &Session, ctx: &mut State) -> String {
+        let req_header = session.req_header();
+        let mut values = Vec::with_capacity(self.tags.len());
+        for (tag, key) in self.tags.iter() {
+            let value = match tag {
+                LimitTag::Query => util::get_query_value(req_header, key)
                     .unwrap_or_default()
-                    .to_string()
-            },
-            LimitTag::Cookie => {
-                util::get_cookie_value(session.req_header(), &self.key)
+                    .to_string(),
+                LimitTag::RequestHeader => {
+                    util::get_req_header_value(req_header, key)
+                        .unwrap_or_default()
+                        .to_string()
+                },
+                LimitTag::Cookie => util::get_cookie_value(req_header, key)
                     .unwrap_or_default()
-                    .to_string()
-            },
-            _ => {
-                let client_ip = util::get_client_ip(session);
-                ctx.client_ip = Some(client_ip.clone());
-                client_ip
-            },
-        };
-        if key.is_empty() {
-            return Ok(());
+                    .to_string(),
+                LimitTag::Path => req_header.uri.path().to_string(),
+                LimitTag::Method => req_header.method.to_string(),
+                // the values are prefixed,
+                // so the consumer can't be the same as client ip
+                LimitTag::Consumer => {
+                    if let Some(consumer) = &ctx.consumer {
+                        format!("consumer:{consumer}")
+                    } else {
+                        format!("ip:{}", Self::get_client_ip(session, ctx))
+                    }
+                },
+                LimitTag::Ip => Self::get_client_ip(session, ctx),
+            };
+            values.push(value);
         }
-        let value = if let Some(rate) = &self.rate {
-            rate.observe(&key, 1);
-            let value = rate.rate(&key);
-            value as isize
-        } else if let Some(inflight) = &self.inflight {
-            let (guard, value) = inflight.incr(&key, 1);
-            ctx.guard = Some(guard);
-            value
-        } else {
-            0
-        };
-        if value > self.max {
+        if values.iter().all(|value| value.is_empty()) {
+            return "".to_string();
+        }
+        values.join(":")
+    }
+    /// Get the max value, the consumer may have its own max value.
+    fn get_max(&self, ctx: &State) -> isize {
+        ctx.consumer
+            .as_ref()
+            .and_then(|consumer| self.consumer_max.get(consumer))
+            .cloned()
+            .unwrap_or(self.max)
+    }
+    /// Check the count of rate window, the rate limit headers
+    /// are set to context.
+    fn check_rate(&self, value: isize, ctx: &mut State) -> Result<()> {
+        let max = self.get_max(ctx);
+        let interval = self.interval.as_millis().max(1) as u64;
+        let now = util::now().as_millis() as u64;
+        // the time until current window ends
+        let reset = Duration::from_millis(interval - now % interval);
+        let exceed = value > max;
+        ctx.rate_limit_headers = Some(new_rate_limit_headers(
+            max,
+            max - value,
+            reset,
+            exceed.then_some(reset),
+        ));
+        if exceed {
             return Err(Error::Exceed {
                 category: PluginCategory::Limit.to_string(),
-                max: self.max,
+                max,
                 value,
             });
         }
         Ok(())
     }
+    /// Increment `key` by 1. If value gt max, an error will be return.
+    /// Otherwise returns the delay before the request proceeds.
+    /// It may set a Guard or the client ip to context.
+    pub fn incr(&self, session: &Session, ctx: &mut State) -> Result<Duration> {
+        let key = self.get_key(session, ctx);
+        if key.is_empty() {
+            return Ok(Duration::ZERO);
+        }
+        let max = self.get_max(ctx);
+        if let Some(token_bucket) = &self.token_bucket {
+            // the burst is scaled by the max value of consumer
+            let burst = if self.burst > 0 {
+                (self.burst * max / self.max).max(1)
+            } else {
+                max
+            };
+            let result = token_bucket.take(&key, max as usize, burst as usize);
+            ctx.rate_limit_headers = Some(new_rate_limit_headers(
+                burst,
+                result.remaining as isize,
+                result.reset,
+                (!result.allowed).then_some(result.wait),
+            ));
+            if !result.allowed {
+                return Err(Error::Exceed {
+                    category: PluginCategory::Limit.to_string(),
+                    max: burst,
+                    value: burst + 1,
+                });
+            }
+            return Ok(result.wait);
+        }
+        if let Some(rate) = &self.rate {
+            rate.observe(&key, 1);
+            let value = rate.rate(&key) as isize;
+            self.check_rate(value, ctx)?;
+        } else if let Some(inflight) = &self.inflight {
+            let (guard, value) = inflight.incr(&key, 1);
+            ctx.guard = Some(guard);
+            if value > max {
+                return Err(Error::Exceed {
+                    category: PluginCategory::Limit.to_string(),
+                    max,
+                    value,
+                });
+            }
+        }
+        Ok(Duration::ZERO)
+    }
+    /// Increment `key` by 1 in the shared store,
+    /// if value gt max, an error will be return.
+    pub async fn incr_shared(
+        &self,
+        shared_rate: &SharedRate,
+        session: &Session,
+        ctx: &mut State,
+    ) -> Result<()> {
+        let key = self.get_key(session, ctx);
+        if key.is_empty() {
+            return Ok(());
+        }
+        let value = shared_rate.observe(&key).await;
+        self.check_rate(value, ctx)
+    }
+}
+
+/// Create the `RateLimit-*` headers, and `Retry-After` if it's rejected.
+fn new_rate_limit_headers(
+    limit: isize,
+    remaining: isize,
+    reset: Duration,
+    retry_after: Option<Duration>,
+) -> Vec<HttpHeader> {
+    // the values are seconds, rounded up
+    let to_secs = |value: Duration| value.as_millis().div_ceil(1000);
+    let mut headers = vec![
+        (RATE_LIMIT_LIMIT.clone(), HeaderValue::from(limit.max(0))),
+        (
+            RATE_LIMIT_REMAINING.clone(),
+            HeaderValue::from(remaining.max(0)),
+        ),
+        (
+            RATE_LIMIT_RESET.clone(),
+            HeaderValue::from(to_secs(reset) as u64),
+        ),
+    ];
+    if let Some(value) = retry_after {
+        headers.push((
+            http::header::RETRY_AFTER,
+            HeaderValue::from(to_secs(value).max(1) as u64),
+        ));
+    }
+    headers
 }
 #[async_trait]
 impl Plugin for Limiter {
@@ -167,15 +390,49 @@ impl Plugin for Limiter {
         if step != self.plugin_step {
             return Ok(None);
         }
-        if let Err(e) = self.incr(session, ctx) {
-            return Ok(Some(HttpResponse {
-                status: StatusCode::TOO_MANY_REQUESTS,
-                body: e.to_string().into(),
-                ..Default::default()
-            }));
+        let result = if let Some(shared_rate) = &self.shared_rate {
+            self.incr_shared(shared_rate, session, ctx)
+                .await
+                .map(|_| Duration::ZERO)
+        } else {
+            self.incr(session, ctx)
+        };
+        match result {
+            Ok(delay) => {
+                // delay the request instead of rejecting it
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+            },
+            Err(e) => {
+                return Ok(Some(HttpResponse {
+                    status: StatusCode::TOO_MANY_REQUESTS,
+                    body: e.to_string().into(),
+                    headers: ctx.rate_limit_headers.take(),
+                    ..Default::default()
+                }));
+            },
         }
         Ok(None)
     }
+    #[inline]
+    async fn handle_response(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut State,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<()> {
+        if step != PluginStep::Response {
+            return Ok(());
+        }
+        if let Some(headers) = ctx.rate_limit_headers.take() {
+            for (name, value) in headers {
+                let _ = upstream_response.insert_header(name, value);
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -185,6 +442,7 @@ mod tests {
         config::PluginConf, config::PluginStep, plugin::Plugin, state::State,
     };
     use http::StatusCode;
+    use pingora::http::ResponseHeader;
     use pingora::proxy::Session;
     use pretty_assertions::assert_eq;
     use std::time::Duration;
@@ -225,8 +483,10 @@ max = 10
         .unwrap();
         assert_eq!("request", params.plugin_step.to_string());
         assert_eq!(true, params.inflight.is_some());
-        assert_eq!(LimitTag::Cookie, params.tag);
-        assert_eq!("deviceId", params.key);
+        assert_eq!(
+            vec![(LimitTag::Cookie, "deviceId".to_string())],
+            params.tags
+        );
 
         let result = Limiter::try_from(
             &toml::from_str::<PluginConf>(
@@ -261,7 +521,7 @@ max = 10
         )
         .unwrap();
 
-        assert_eq!(LimitTag::Cookie, limiter.tag);
+        assert_eq!(LimitTag::Cookie, limiter.tags[0].0);
         let mut ctx = State {
             ..Default::default()
         };
@@ -284,7 +544,7 @@ max = 10
             .unwrap(),
         )
         .unwrap();
-        assert_eq!(LimitTag::RequestHeader, limiter.tag);
+        assert_eq!(LimitTag::RequestHeader, limiter.tags[0].0);
         let mut ctx = State {
             ..Default::default()
         };
@@ -307,7 +567,7 @@ max = 10
             .unwrap(),
         )
         .unwrap();
-        assert_eq!(LimitTag::Query, limiter.tag);
+        assert_eq!(LimitTag::Query, limiter.tags[0].0);
         let mut ctx = State {
             ..Default::default()
         };
@@ -328,7 +588,7 @@ max = 10
             .unwrap(),
         )
         .unwrap();
-        assert_eq!(LimitTag::Ip, limiter.tag);
+        assert_eq!(LimitTag::Ip, limiter.tags[0].0);
         let mut ctx = State {
             ..Default::default()
         };
@@ -455,4 +715,188 @@ interval = "1s"
             .unwrap();
         assert_eq!(true, result.is_none());
     }
+
+    #[tokio::test]
+    async fn test_shared_rate_limit() {
+        let url = crate::limit::start_redis_stand_in().await;
+        let conf = toml::from_str::<PluginConf>(&format!(
+            r###"
+type = "rate"
+max = 1
+interval = "60s"
+store = "{url}"
+"###
+        ))
+        .unwrap();
+        let limiter = Limiter::new(&conf).unwrap();
+        assert_eq!(true, limiter.shared_rate.is_some());
+        assert_eq!(true, limiter.rate.is_none());
+        // another instance with the same config
+        let other = Limiter::new(&conf).unwrap();
+
+        let headers = ["X-Forwarded-For: 1.1.1.1"].join("\r\n");
+        let input_header =
+            format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = limiter
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+
+        let result = other
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut State::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_some());
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, result.unwrap().status);
+
+        let result = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "inflight"
+max = 1
+store = "redis://127.0.0.1:6379"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin limit invalid, message: Shared store only supports rate limit",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket_limit() {
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "token_bucket"
+max = 10
+interval = "1s"
+burst = 2
+delay = "150ms"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(true, limiter.token_bucket.is_some());
+
+        let headers = ["X-Forwarded-For: 1.1.1.1"].join("\r\n");
+        let input_header =
+            format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let mut ctx = State::default();
+        let result = limiter
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result.is_none());
+        let mut upstream_response =
+            ResponseHeader::build(StatusCode::OK, None).unwrap();
+        limiter
+            .handle_response(
+                PluginStep::Response,
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            r#"{"ratelimit-limit": "2", "ratelimit-remaining": "1", "ratelimit-reset": "1"}"#,
+            format!("{:?}", upstream_response.headers)
+        );
+
+        let _ = limiter
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        // the request should be delayed
+        let delay = limiter.incr(&session, &mut ctx).unwrap();
+        assert_eq!(true, delay > Duration::ZERO);
+        assert_eq!(true, delay <= Duration::from_millis(100));
+
+        let result = limiter
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, result.status);
+        let headers = result.headers.unwrap();
+        assert_eq!("ratelimit-remaining", headers[1].0.as_str());
+        assert_eq!("0", headers[1].1.to_str().unwrap());
+        assert_eq!("retry-after", headers[3].0.as_str());
+        assert_eq!("1", headers[3].1.to_str().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_composite_limit() {
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "inflight"
+tags = ["consumer", "method", "path", "header:X-Uuid"]
+max = 1
+consumer_max = ["vip 2"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                (LimitTag::Consumer, "".to_string()),
+                (LimitTag::Method, "".to_string()),
+                (LimitTag::Path, "".to_string()),
+                (LimitTag::RequestHeader, "X-Uuid".to_string()),
+            ],
+            limiter.tags
+        );
+        let session = new_session().await;
+
+        // anonymous consumer uses client ip
+        let mut ctx = State::default();
+        assert_eq!(
+            "ip:1.1.1.1:GET:/vicanso/pingap:138q71",
+            limiter.get_key(&session, &mut ctx)
+        );
+        assert_eq!(1, limiter.get_max(&ctx));
+
+        let mut ctx = State {
+            consumer: Some("vip".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            "consumer:vip:GET:/vicanso/pingap:138q71",
+            limiter.get_key(&session, &mut ctx)
+        );
+        assert_eq!(2, limiter.get_max(&ctx));
+
+        // the vip consumer has higher quota
+        let mut guards = vec![];
+        for _ in 0..2 {
+            limiter.incr(&session, &mut ctx).unwrap();
+            guards.push(ctx.guard.take());
+        }
+        assert_eq!(
+            "Plugin limit, exceed limit 3/2",
+            limiter.incr(&session, &mut ctx).err().unwrap().to_string()
+        );
+    }
 }
@@ -37,11 +37,13 @@ mod compression;
 mod cors;
 mod csrf;
 mod directory;
+mod ext_auth;
 mod ip_restriction;
 mod jwt;
 mod key_auth;
 mod limit;
 mod mock;
+mod oidc;
 mod ping;
 mod redirect;
 mod referer_restriction;
@@ -299,6 +301,14 @@ pub fn parse_plugins(confs: Vec<(String, PluginConf)>) -> Result<Plugins> {
                     accept_encoding::AcceptEncoding::new(conf)?;
                 plguins.insert(name.clone(), Arc::new(accept_encoding));
             },
+            PluginCategory::Oidc => {
+                let oidc = oidc::Oidc::new(conf)?;
+                plguins.insert(name.clone(), Arc::new(oidc));
+            },
+            PluginCategory::ExtAuth => {
+                let auth = ext_auth::ExtAuth::new(conf)?;
+                plguins.insert(name.clone(), Arc::new(auth));
+            },
         };
     }
 
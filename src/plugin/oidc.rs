@@ -0,0 +1,945 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::jwt::{get_claim_value, Jwks};
+use super::{
+    get_hash_key, get_step_conf, get_str_conf, get_str_slice_conf, Error,
+    Plugin, Result,
+};
+use crate::config::{PluginCategory, PluginConf, PluginStep};
+use crate::http_extra::{HttpHeader, HttpResponse, HTTP_HEADER_NO_STORE};
+use crate::state::State;
+use crate::util;
+use arc_swap::ArcSwapOption;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bytes::Bytes;
+use cookie::{Cookie, SameSite};
+use http::{header, HeaderName, HeaderValue, Method, StatusCode};
+use humantime::parse_duration;
+use nanoid::nanoid;
+use pingora::http::RequestHeader;
+use pingora::proxy::Session;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, error};
+use url::Url;
+
+// the login should be completed in this time
+const LOGIN_STATE_TTL: Duration = Duration::from_secs(10 * 60);
+
+// the timeout of requests to identity provider
+const PROVIDER_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// the interval of reloading the jwks of identity provider
+const JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Default, Clone, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    #[serde(default)]
+    jwks_uri: String,
+}
+
+/// The state of login flow, it's saved in the state cookie
+/// before redirecting to the identity provider.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LoginState {
+    state: String,
+    nonce: String,
+    verifier: String,
+    // the original url of request
+    redirect: String,
+    expired_at: u64,
+}
+
+/// The session of user, it's saved in the session cookie,
+/// only the sub and claims used by plugin are kept.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct LoginSession {
+    claims: HashMap<String, String>,
+    expired_at: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct IdTokenHeader {
+    alg: String,
+    kid: Option<String>,
+}
+
+pub struct Oidc {
+    plugin_step: PluginStep,
+    issuer: String,
+    client_id: String,
+    client_secret: String,
+    redirect_uri: String,
+    // the path of redirect uri, the authorization code is handled on it
+    callback_path: String,
+    scopes: String,
+    // the key for encrypting cookies
+    secret: String,
+    cookie: String,
+    // set the secure attribute of cookies
+    secure: bool,
+    session_ttl: Duration,
+    consumer_claim: String,
+    claim_headers: Vec<(String, HeaderName)>,
+    metadata: ArcSwapOption<ProviderMetadata>,
+    // the public keys of jwks uri, it's set with the metadata
+    jwks: ArcSwapOption<Jwks>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Oidc {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_error = |message: String| Error::Invalid {
+            category: PluginCategory::Oidc.to_string(),
+            message,
+        };
+        let issuer = get_str_conf(value, "issuer")
+            .trim_end_matches('/')
+            .to_string();
+        let authorization_endpoint =
+            get_str_conf(value, "authorization_endpoint");
+        let token_endpoint = get_str_conf(value, "token_endpoint");
+        // the endpoints are discovered from issuer if they are not set
+        let metadata = if !authorization_endpoint.is_empty()
+            && !token_endpoint.is_empty()
+        {
+            Some(Arc::new(ProviderMetadata {
+                issuer: issuer.clone(),
+                authorization_endpoint,
+                token_endpoint,
+                jwks_uri: get_str_conf(value, "jwks_uri"),
+            }))
+        } else {
+            None
+        };
+        // the issuer of id token is always checked
+        if issuer.is_empty() {
+            return Err(new_error(
+                "Oidc issuer is not allowed empty".to_string(),
+            ));
+        }
+        let client_id = get_str_conf(value, "client_id");
+        if client_id.is_empty() {
+            return Err(new_error(
+                "Oidc client id is not allowed empty".to_string(),
+            ));
+        }
+        let secret = get_str_conf(value, "secret");
+        if secret.is_empty() {
+            return Err(new_error(
+                "Oidc secret is not allowed empty".to_string(),
+            ));
+        }
+        let redirect_uri = get_str_conf(value, "redirect_uri");
+        let url = Url::parse(&redirect_uri).map_err(|e| {
+            new_error(format!("redirect uri {redirect_uri} is invalid, {e}"))
+        })?;
+
+        let mut scopes = get_str_slice_conf(value, "scopes");
+        if scopes.is_empty() {
+            scopes = vec![
+                "openid".to_string(),
+                "profile".to_string(),
+                "email".to_string(),
+            ];
+        }
+        let mut cookie = get_str_conf(value, "cookie");
+        if cookie.is_empty() {
+            cookie = "pingap_session".to_string();
+        }
+        let session_ttl = get_str_conf(value, "session_ttl");
+        let session_ttl = if session_ttl.is_empty() {
+            Duration::from_secs(24 * 3600)
+        } else {
+            parse_duration(&session_ttl)
+                .map_err(|e| new_error(e.to_string()))?
+        };
+
+        let mut claim_headers = vec![];
+        // the claim path and header name, e.g. "email X-User-Email"
+        for item in get_str_slice_conf(value, "claim_headers").iter() {
+            let Some((claim, name)) = item.split_once(' ') else {
+                continue;
+            };
+            let name = HeaderName::from_str(name.trim())
+                .map_err(|e| new_error(format!("invalid header name, {e}")))?;
+            claim_headers.push((claim.to_string(), name));
+        }
+        let mut consumer_claim = get_str_conf(value, "consumer_claim");
+        if consumer_claim.is_empty() {
+            consumer_claim = "sub".to_string();
+        }
+
+        let params = Self {
+            hash_value,
+            plugin_step: get_step_conf(value),
+            issuer,
+            client_id,
+            client_secret: get_str_conf(value, "client_secret"),
+            callback_path: url.path().to_string(),
+            secure: url.scheme() == "https",
+            redirect_uri,
+            scopes: scopes.join(" "),
+            secret,
+            cookie,
+            session_ttl,
+            consumer_claim,
+            claim_headers,
+            jwks: ArcSwapOption::new(metadata.as_ref().and_then(new_jwks)),
+            metadata: ArcSwapOption::new(metadata),
+        };
+        if PluginStep::Request != params.plugin_step {
+            return Err(new_error(
+                "Oidc plugin should be executed at request step".to_string(),
+            ));
+        }
+        Ok(params)
+    }
+}
+
+fn unauthorized(message: &str) -> HttpResponse {
+    HttpResponse {
+        status: StatusCode::UNAUTHORIZED,
+        body: Bytes::from(message.to_string()),
+        headers: Some(vec![HTTP_HEADER_NO_STORE.clone()]),
+        ..Default::default()
+    }
+}
+
+/// Get the redirect path after login, only the path of this site is allowed,
+/// `//evil.com` and `/\evil.com` are treated as other sites by browsers.
+fn get_local_redirect(redirect: String) -> String {
+    let is_local =
+        redirect.starts_with('/') && !redirect[1..].starts_with(['/', '\\']);
+    if is_local {
+        redirect
+    } else {
+        "/".to_string()
+    }
+}
+
+fn new_jwks(metadata: &Arc<ProviderMetadata>) -> Option<Arc<Jwks>> {
+    if metadata.jwks_uri.is_empty() {
+        return None;
+    }
+    Some(Arc::new(Jwks::new(
+        metadata.jwks_uri.clone(),
+        JWKS_REFRESH_INTERVAL,
+    )))
+}
+
+fn new_code_challenge(verifier: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(verifier.as_bytes());
+    URL_SAFE_NO_PAD.encode(hasher.finalize())
+}
+
+impl Oidc {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new oidc plugin");
+        Self::try_from(params)
+    }
+    fn state_cookie(&self) -> String {
+        format!("{}_state", self.cookie)
+    }
+    fn new_cookie(
+        &self,
+        name: &str,
+        value: &str,
+        max_age: Duration,
+    ) -> pingora::Result<HttpHeader> {
+        let cookie = Cookie::build((name, value))
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .max_age(cookie::time::Duration::seconds(max_age.as_secs() as i64))
+            .build();
+        let value = HeaderValue::from_str(&cookie.to_string())
+            .map_err(|e| util::new_internal_error(400, e.to_string()))?;
+        Ok((header::SET_COOKIE, value))
+    }
+    /// Get the metadata of identity provider, it's fetched from
+    /// the discovery endpoint of issuer at the first time.
+    async fn get_metadata(&self) -> pingora::Result<Arc<ProviderMetadata>> {
+        if let Some(metadata) = self.metadata.load_full() {
+            return Ok(metadata);
+        }
+        let url = format!("{}/.well-known/openid-configuration", self.issuer);
+        let metadata = reqwest::Client::new()
+            .get(&url)
+            .timeout(PROVIDER_REQUEST_TIMEOUT)
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status())
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?
+            .json::<ProviderMetadata>()
+            .await
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        // the issuer of metadata should be the same as configured
+        if metadata.issuer.trim_end_matches('/') != self.issuer {
+            return Err(util::new_internal_error(
+                500,
+                format!("issuer of metadata {} is invalid", metadata.issuer),
+            ));
+        }
+        let metadata = Arc::new(metadata);
+        self.jwks.store(new_jwks(&metadata));
+        self.metadata.store(Some(metadata.clone()));
+        Ok(metadata)
+    }
+    /// Redirect to the authorization endpoint, the state, nonce and
+    /// code verifier are saved in the encrypted state cookie.
+    async fn login(
+        &self,
+        req_header: &RequestHeader,
+    ) -> pingora::Result<HttpResponse> {
+        let metadata = self.get_metadata().await?;
+        let login_state = LoginState {
+            state: nanoid!(32),
+            nonce: nanoid!(32),
+            verifier: nanoid!(64),
+            redirect: req_header
+                .uri
+                .path_and_query()
+                .map(|value| value.to_string())
+                .unwrap_or_else(|| "/".to_string()),
+            expired_at: util::now().as_secs() + LOGIN_STATE_TTL.as_secs(),
+        };
+        let mut url = Url::parse(&metadata.authorization_endpoint)
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.client_id)
+            .append_pair("redirect_uri", &self.redirect_uri)
+            .append_pair("scope", &self.scopes)
+            .append_pair("state", &login_state.state)
+            .append_pair("nonce", &login_state.nonce)
+            .append_pair(
+                "code_challenge",
+                &new_code_challenge(&login_state.verifier),
+            )
+            .append_pair("code_challenge_method", "S256");
+
+        let data = serde_json::to_string(&login_state)
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        let value = util::aes_encrypt(&self.secret, &data)
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        let location = HeaderValue::from_str(url.as_str())
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        Ok(HttpResponse {
+            status: StatusCode::FOUND,
+            headers: Some(vec![
+                (header::LOCATION, location),
+                HTTP_HEADER_NO_STORE.clone(),
+                self.new_cookie(&self.state_cookie(), &value, LOGIN_STATE_TTL)?,
+            ]),
+            ..Default::default()
+        })
+    }
+    /// Verify the id token and returns its claims.
+    async fn verify_id_token(
+        &self,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> std::result::Result<serde_json::Value, String> {
+        let arr: Vec<&str> = id_token.split('.').collect();
+        if arr.len() != 3 {
+            return Err("id token format is invalid".to_string());
+        }
+        let header = serde_json::from_slice::<IdTokenHeader>(
+            &URL_SAFE_NO_PAD.decode(arr[0]).unwrap_or_default(),
+        )
+        .unwrap_or_default();
+        let content = format!("{}.{}", arr[0], arr[1]);
+        let signature = URL_SAFE_NO_PAD.decode(arr[2]).unwrap_or_default();
+        let alg = header.alg.as_str();
+        let valid = if util::is_asymmetric_jwt_alg(alg) {
+            let Some(jwks) = self.jwks.load_full() else {
+                return Err("jwks uri is empty".to_string());
+            };
+            jwks.get_keys(header.kid.as_deref())
+                .await
+                .iter()
+                .any(|item| {
+                    util::verify_jwt_signature(
+                        alg,
+                        &item.key,
+                        content.as_bytes(),
+                        &signature,
+                    )
+                    .unwrap_or_default()
+                })
+        } else if alg == "HS256" && !self.client_secret.is_empty() {
+            // the client secret is used as the key of hmac
+            let hash = hmac_sha256::HMAC::mac(
+                content.as_bytes(),
+                self.client_secret.as_bytes(),
+            );
+            util::constant_time_eq(hash.as_slice(), signature.as_slice())
+        } else {
+            false
+        };
+        if !valid {
+            return Err("id token signature is invalid".to_string());
+        }
+        let claims: serde_json::Value = serde_json::from_slice(
+            &URL_SAFE_NO_PAD.decode(arr[1]).unwrap_or_default(),
+        )
+        .map_err(|e| e.to_string())?;
+        if claims.get("iss").and_then(|v| v.as_str())
+            != Some(metadata.issuer.as_str())
+        {
+            return Err("id token issuer is invalid".to_string());
+        }
+        let valid_audience = match claims.get("aud") {
+            Some(serde_json::Value::String(aud)) => aud == &self.client_id,
+            Some(serde_json::Value::Array(arr)) => arr
+                .iter()
+                .any(|aud| aud.as_str() == Some(self.client_id.as_str())),
+            _ => false,
+        };
+        if !valid_audience {
+            return Err("id token audience is invalid".to_string());
+        }
+        let exp = claims
+            .get("exp")
+            .and_then(|v| v.as_u64())
+            .unwrap_or_default();
+        if exp < util::now().as_secs() {
+            return Err("id token is expired".to_string());
+        }
+        if claims.get("nonce").and_then(|v| v.as_str()) != Some(nonce) {
+            return Err("id token nonce is invalid".to_string());
+        }
+        Ok(claims)
+    }
+    /// Handle the redirection from identity provider, the code is
+    /// exchanged for id token, then the session cookie is set.
+    async fn callback(
+        &self,
+        req_header: &RequestHeader,
+    ) -> pingora::Result<HttpResponse> {
+        let query: HashMap<String, String> = url::form_urlencoded::parse(
+            req_header.uri.query().unwrap_or_default().as_bytes(),
+        )
+        .into_owned()
+        .collect();
+        if let Some(err) = query.get("error") {
+            return Ok(unauthorized(&format!("Oidc login fail, {err}")));
+        }
+        let login_state =
+            util::get_cookie_value(req_header, &self.state_cookie())
+                .and_then(|value| util::aes_decrypt(&self.secret, value).ok())
+                .and_then(|value| {
+                    serde_json::from_str::<LoginState>(&value).ok()
+                });
+        let Some(login_state) = login_state else {
+            return Ok(unauthorized("Oidc login state is missing"));
+        };
+        if login_state.expired_at < util::now().as_secs()
+            || query.get("state") != Some(&login_state.state)
+        {
+            return Ok(unauthorized("Oidc login state is invalid"));
+        }
+        let code = query.get("code").cloned().unwrap_or_default();
+        if code.is_empty() {
+            return Ok(unauthorized("Oidc authorization code is missing"));
+        }
+
+        let metadata = self.get_metadata().await?;
+        let resp = reqwest::Client::new()
+            .post(&metadata.token_endpoint)
+            .timeout(PROVIDER_REQUEST_TIMEOUT)
+            .form(&[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", &self.redirect_uri),
+                ("client_id", &self.client_id),
+                ("client_secret", &self.client_secret),
+                ("code_verifier", &login_state.verifier),
+            ])
+            .send()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        let token = match resp {
+            Ok(resp) => resp.json::<TokenResponse>().await,
+            Err(e) => Err(e),
+        };
+        let token = match token {
+            Ok(token) => token,
+            Err(e) => {
+                error!(error = e.to_string(), "oidc exchange code fail");
+                return Ok(unauthorized("Oidc exchange code fail"));
+            },
+        };
+        let claims = match self
+            .verify_id_token(&metadata, &token.id_token, &login_state.nonce)
+            .await
+        {
+            Ok(claims) => claims,
+            Err(message) => {
+                error!(error = message, "oidc verify id token fail");
+                return Ok(unauthorized("Oidc id token is invalid"));
+            },
+        };
+
+        let mut session_claims = HashMap::new();
+        let paths = ["sub", &self.consumer_claim]
+            .into_iter()
+            .chain(self.claim_headers.iter().map(|(claim, _)| claim.as_str()));
+        for path in paths {
+            if let Some(value) = get_claim_value(&claims, path) {
+                session_claims.insert(path.to_string(), value);
+            }
+        }
+        let login_session = LoginSession {
+            claims: session_claims,
+            expired_at: util::now().as_secs() + self.session_ttl.as_secs(),
+        };
+        let data = serde_json::to_string(&login_session)
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        let value = util::aes_encrypt(&self.secret, &data)
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        let redirect = get_local_redirect(login_state.redirect);
+        let location = HeaderValue::from_str(&redirect)
+            .map_err(|e| util::new_internal_error(500, e.to_string()))?;
+        Ok(HttpResponse {
+            status: StatusCode::FOUND,
+            headers: Some(vec![
+                (header::LOCATION, location),
+                HTTP_HEADER_NO_STORE.clone(),
+                self.new_cookie(&self.cookie, &value, self.session_ttl)?,
+                self.new_cookie(&self.state_cookie(), "", Duration::ZERO)?,
+            ]),
+            ..Default::default()
+        })
+    }
+    fn get_session(&self, req_header: &RequestHeader) -> Option<LoginSession> {
+        let value = util::get_cookie_value(req_header, &self.cookie)?;
+        let data = util::aes_decrypt(&self.secret, value).ok()?;
+        let login_session = serde_json::from_str::<LoginSession>(&data).ok()?;
+        if login_session.expired_at < util::now().as_secs() {
+            return None;
+        }
+        Some(login_session)
+    }
+}
+
+#[async_trait]
+impl Plugin for Oidc {
+    #[inline]
+    fn hash_key(&self) -> String {
+        self.hash_value.clone()
+    }
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut State,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        if step != self.plugin_step {
+            return Ok(None);
+        }
+        let req_header = session.req_header();
+        if req_header.uri.path() == self.callback_path {
+            return self.callback(req_header).await.map(Some);
+        }
+        let Some(login_session) = self.get_session(req_header) else {
+            // only the page request can be redirected to login
+            if req_header.method != Method::GET {
+                return Ok(Some(unauthorized("Oidc session is missing")));
+            }
+            return self.login(req_header).await.map(Some);
+        };
+        let claims = &login_session.claims;
+        ctx.consumer = claims.get(&self.consumer_claim).cloned();
+        let req_header = session.req_header_mut();
+        for (claim, name) in self.claim_headers.iter() {
+            // the header from client should not be trusted
+            req_header.remove_header(name);
+            if let Some(value) = claims.get(claim) {
+                let _ = req_header.insert_header(name, value);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_local_redirect, new_code_challenge, LoginSession, Oidc};
+    use crate::config::{PluginConf, PluginStep};
+    use crate::plugin::Plugin;
+    use crate::state::State;
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use cookie::Cookie;
+    use http::StatusCode;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio_test::io::Builder;
+
+    /// Start a local identity provider, it supports the discovery,
+    /// token and jwks endpoints.
+    async fn start_identity_provider(
+        nonce: Arc<Mutex<String>>,
+        challenge: Arc<Mutex<String>>,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+        let (keys, jwks) = crate::util::new_test_keys();
+        let addr = issuer.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut reader = BufReader::new(stream);
+                let mut line = String::new();
+                reader.read_line(&mut line).await.unwrap();
+                let path =
+                    line.split(' ').nth(1).unwrap_or_default().to_string();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).await.unwrap();
+                let form: HashMap<String, String> =
+                    url::form_urlencoded::parse(&body).into_owned().collect();
+
+                let (status, data) = match path.as_str() {
+                    "/.well-known/openid-configuration" => (
+                        200,
+                        format!(
+                            r#"{{"issuer":"{addr}","authorization_endpoint":"{addr}/authorize","token_endpoint":"{addr}/token","jwks_uri":"{addr}/jwks"}}"#
+                        ),
+                    ),
+                    "/jwks" => (200, jwks.clone()),
+                    "/token" => {
+                        let verifier = form
+                            .get("code_verifier")
+                            .cloned()
+                            .unwrap_or_default();
+                        if form.get("code").map(|v| v.as_str())
+                            != Some("abc+123")
+                            || new_code_challenge(&verifier)
+                                != *challenge.lock().unwrap()
+                        {
+                            (400, r#"{"error":"invalid_grant"}"#.to_string())
+                        } else {
+                            let header = URL_SAFE_NO_PAD
+                                .encode(r#"{"alg":"EdDSA","kid":"ed"}"#);
+                            let claims = URL_SAFE_NO_PAD.encode(format!(
+                                r#"{{"sub":"tree","email":"tree@pingap.io","iss":"{addr}","aud":"pingap","exp":{},"nonce":"{}"}}"#,
+                                crate::util::now().as_secs() + 60,
+                                nonce.lock().unwrap()
+                            ));
+                            let content = format!("{header}.{claims}");
+                            let signature = crate::util::sign_jwt(
+                                "EdDSA", &keys[2], &content,
+                            );
+                            (
+                                200,
+                                format!(
+                                    r#"{{"id_token":"{content}.{signature}","token_type":"Bearer"}}"#
+                                ),
+                            )
+                        }
+                    },
+                    _ => (404, "{}".to_string()),
+                };
+                let resp = format!(
+                    "HTTP/1.1 {status} OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{data}",
+                    data.len()
+                );
+                let mut stream = reader.into_inner();
+                let _ = stream.write_all(resp.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            }
+        });
+        issuer
+    }
+
+    #[test]
+    fn test_oidc_params() {
+        let oidc = Oidc::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+issuer = "https://accounts.pingap.io/"
+client_id = "pingap"
+client_secret = "123123"
+redirect_uri = "https://pingap.io/oauth2/callback"
+secret = "abcd"
+session_ttl = "1h"
+claim_headers = ["email X-User-Email"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("https://accounts.pingap.io", oidc.issuer);
+        assert_eq!("/oauth2/callback", oidc.callback_path);
+        assert_eq!(true, oidc.secure);
+        assert_eq!("openid profile email", oidc.scopes);
+        assert_eq!("pingap_session", oidc.cookie);
+        assert_eq!(3600, oidc.session_ttl.as_secs());
+        assert_eq!("x-user-email", oidc.claim_headers[0].1.as_str());
+        assert_eq!(true, oidc.metadata.load().is_none());
+
+        let result = Oidc::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+issuer = "https://accounts.pingap.io"
+client_id = "pingap"
+redirect_uri = "https://pingap.io/oauth2/callback"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin oidc invalid, message: Oidc secret is not allowed empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Oidc::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+authorization_endpoint = "https://accounts.pingap.io/authorize"
+token_endpoint = "https://accounts.pingap.io/token"
+client_id = "pingap"
+redirect_uri = "https://pingap.io/oauth2/callback"
+secret = "abcd"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin oidc invalid, message: Oidc issuer is not allowed empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_local_redirect() {
+        assert_eq!(
+            "/users?page=1",
+            get_local_redirect("/users?page=1".to_string())
+        );
+        assert_eq!("/", get_local_redirect("//evil.com".to_string()));
+        assert_eq!("/", get_local_redirect("/\\evil.com".to_string()));
+        assert_eq!("/", get_local_redirect("https://evil.com".to_string()));
+        assert_eq!("/", get_local_redirect("".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_oidc_metadata_issuer() {
+        let issuer = start_identity_provider(
+            Arc::new(Mutex::new(String::new())),
+            Arc::new(Mutex::new(String::new())),
+        )
+        .await;
+        // the same provider, but the issuer of metadata is different
+        let oidc = Oidc::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+issuer = "{}"
+client_id = "pingap"
+redirect_uri = "http://pingap.io/oauth2/callback"
+secret = "abcd"
+"###,
+                issuer.replace("127.0.0.1", "localhost")
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let result = oidc.get_metadata().await;
+        assert_eq!(
+            true,
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .contains(&format!("issuer of metadata {issuer} is invalid"))
+        );
+        assert_eq!(true, oidc.metadata.load().is_none());
+        assert_eq!(true, oidc.jwks.load().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_oidc_login() {
+        let nonce = Arc::new(Mutex::new(String::new()));
+        let challenge = Arc::new(Mutex::new(String::new()));
+        let issuer =
+            start_identity_provider(nonce.clone(), challenge.clone()).await;
+        let oidc = Oidc::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+issuer = "{issuer}"
+client_id = "pingap"
+client_secret = "123123"
+redirect_uri = "http://pingap.io/oauth2/callback"
+secret = "abcd"
+claim_headers = ["email X-User-Email"]
+"###
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let run = |path: &str, cookies: Vec<String>| {
+            let oidc = &oidc;
+            let path = path.to_string();
+            async move {
+                let mut headers = vec!["Host: pingap.io".to_string()];
+                if !cookies.is_empty() {
+                    headers.push(format!("Cookie: {}", cookies.join("; ")));
+                }
+                headers.push("X-User-Email: admin@pingap.io".to_string());
+                let input_header = format!(
+                    "GET {path} HTTP/1.1\r\n{}\r\n\r\n",
+                    headers.join("\r\n")
+                );
+                let mock_io =
+                    Builder::new().read(input_header.as_bytes()).build();
+                let mut session = Session::new_h1(Box::new(mock_io));
+                session.read_request().await.unwrap();
+                let mut state = State::default();
+                let resp = oidc
+                    .handle_request(
+                        PluginStep::Request,
+                        &mut session,
+                        &mut state,
+                    )
+                    .await
+                    .unwrap();
+                (resp, session, state)
+            }
+        };
+
+        // redirect to the authorization endpoint
+        let (resp, _, _) = run("/users?page=1", vec![]).await;
+        let resp = resp.unwrap();
+        assert_eq!(StatusCode::FOUND, resp.status);
+        let headers = resp.headers.unwrap();
+        let location = url::Url::parse(headers[0].1.to_str().unwrap()).unwrap();
+        assert_eq!(
+            format!("{issuer}/authorize"),
+            location.as_str().split('?').next().unwrap()
+        );
+        let query: HashMap<String, String> =
+            location.query_pairs().into_owned().collect();
+        assert_eq!("code", query["response_type"]);
+        assert_eq!("pingap", query["client_id"]);
+        assert_eq!("openid profile email", query["scope"]);
+        assert_eq!("S256", query["code_challenge_method"]);
+        *nonce.lock().unwrap() = query["nonce"].clone();
+        *challenge.lock().unwrap() = query["code_challenge"].clone();
+        let state_cookie =
+            Cookie::parse(headers[2].1.to_str().unwrap().to_string()).unwrap();
+        assert_eq!("pingap_session_state", state_cookie.name());
+        assert_eq!(Some(true), state_cookie.http_only());
+        let state_cookie =
+            format!("{}={}", state_cookie.name(), state_cookie.value());
+
+        // invalid state
+        let (resp, _, _) = run(
+            "/oauth2/callback?code=abc%2B123&state=abc",
+            vec![state_cookie.clone()],
+        )
+        .await;
+        assert_eq!(
+            "Oidc login state is invalid",
+            std::string::String::from_utf8_lossy(resp.unwrap().body.as_ref())
+        );
+
+        // exchange code and set session
+        let (resp, _, _) = run(
+            &format!(
+                "/oauth2/callback?code=abc%2B123&state={}",
+                query["state"]
+            ),
+            vec![state_cookie],
+        )
+        .await;
+        let resp = resp.unwrap();
+        assert_eq!(StatusCode::FOUND, resp.status);
+        let headers = resp.headers.unwrap();
+        assert_eq!("/users?page=1", headers[0].1.to_str().unwrap());
+        let session_cookie =
+            Cookie::parse(headers[2].1.to_str().unwrap().to_string()).unwrap();
+        assert_eq!("pingap_session", session_cookie.name());
+        // only the sub and claims used by plugin are saved
+        let login_session: LoginSession = serde_json::from_str(
+            &crate::util::aes_decrypt("abcd", session_cookie.value()).unwrap(),
+        )
+        .unwrap();
+        let mut keys: Vec<_> = login_session.claims.keys().collect();
+        keys.sort();
+        assert_eq!(vec!["email", "sub"], keys);
+        assert_eq!(
+            "pingap_session_state=; HttpOnly; SameSite=Lax; Path=/; Max-Age=0",
+            headers[3].1.to_str().unwrap()
+        );
+
+        // authenticated request
+        let (resp, session, state) = run(
+            "/users",
+            vec![
+                "lang=en".to_string(),
+                format!("{}={}", session_cookie.name(), session_cookie.value()),
+            ],
+        )
+        .await;
+        assert_eq!(true, resp.is_none());
+        assert_eq!(Some("tree".to_string()), state.consumer);
+        assert_eq!(
+            "tree@pingap.io",
+            session.req_header().headers.get("X-User-Email").unwrap()
+        );
+
+        // tampered session
+        let (resp, _, _) =
+            run("/users", vec!["pingap_session=abcd".to_string()]).await;
+        assert_eq!(StatusCode::FOUND, resp.unwrap().status);
+    }
+}
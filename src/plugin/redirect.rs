@@ -77,9 +77,12 @@ impl Plugin for Redirect {
         if step != self.plugin_step {
             return Ok(None);
         }
+        // the variables of location can be used in prefix, e.g. `/$tenant`
+        let prefix = util::replace_variables(&self.prefix, |name| {
+            ctx.get_variable(name).map(|value| value.to_string())
+        });
         let schema_match = ctx.tls_version.is_some() == self.http_to_https;
-        if schema_match
-            && session.req_header().uri.path().starts_with(&self.prefix)
+        if schema_match && session.req_header().uri.path().starts_with(&prefix)
         {
             return Ok(None);
         }
@@ -88,7 +91,7 @@ impl Plugin for Redirect {
         let location = format!(
             "Location: {}://{host}{}{}",
             schema,
-            self.prefix,
+            prefix,
             session.req_header().uri
         );
         Ok(Some(HttpResponse {
@@ -143,6 +146,36 @@ prefix = "/api"
             format!("{:?}", resp.headers)
         );
 
+        let redirect = Redirect::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+prefix = "/$tenant"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = State {
+            variables: Some(
+                [("tenant".to_string(), "pingap".to_string())]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+        let resp = redirect
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            r###"Some([("location", "http://github.com/pingap/vicanso/pingap?size=1")])"###,
+            format!("{:?}", resp.headers)
+        );
+
         let params = Redirect::new(
             &toml::from_str::<PluginConf>(
                 r###"
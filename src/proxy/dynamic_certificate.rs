@@ -13,23 +13,31 @@
 // limitations under the License.
 
 use crate::acme::{
-    get_certificate_info, get_lets_encrypt_certificate, CertificateInfo,
+    get_certificate_info, get_lets_encrypt_certificate, get_ocsp_staple,
+    get_tls_alpn_certificate, CertificateInfo, ACME_TLS_ALPN_NAME,
 };
 use crate::config::CertificateConf;
 use crate::{util, webhook};
 use ahash::AHashMap;
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use lru::LruCache;
 use once_cell::sync::Lazy;
 use pingora::listeners::tls::TlsSettings;
 use pingora::tls::ext;
+use pingora::tls::hash::MessageDigest;
 use pingora::tls::pkey::{PKey, Private};
-use pingora::tls::ssl::{NameType, SslRef};
-use pingora::tls::x509::X509;
+use pingora::tls::ssl::{
+    select_next_proto, AlpnError, NameType, SslFiletype, SslRef, SslVerifyMode,
+};
+use pingora::tls::x509::store::X509Lookup;
+use pingora::tls::x509::verify::X509VerifyFlags;
+use pingora::tls::x509::{X509Ref, X509VerifyResult, X509};
 use snafu::Snafu;
 use std::collections::HashMap;
+use std::num::NonZeroUsize;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use substring::Substring;
 use tracing::{debug, error, info};
 
@@ -224,6 +232,24 @@ pub fn get_certificate_info_list() -> Vec<(String, CertificateInfo)> {
     infos
 }
 
+/// Get the certificate and its issuer list, which are used for ocsp stapling.
+pub fn get_certificate_issuer_list() -> Vec<(String, X509, X509)> {
+    let mut hash_keys = vec![];
+    let mut list = vec![];
+    for (name, cert) in DYNAMIC_CERTIFICATE_MAP.load().iter() {
+        if hash_keys.contains(&cert.hash_key) {
+            continue;
+        }
+        if let (Some((x509, _)), Some(issuer)) =
+            (&cert.certificate, &cert.chain_certificate)
+        {
+            hash_keys.push(cert.hash_key.clone());
+            list.push((name.to_string(), x509.clone(), issuer.clone()));
+        }
+    }
+    list
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct DynamicCertificate {
     chain_certificate: Option<X509>,
@@ -240,6 +266,110 @@ pub struct TlsSettingParams {
     pub ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
+    pub client_ca: Option<String>,
+    pub client_auth: Option<String>,
+    pub client_crl: Option<String>,
+}
+
+/// The identity of verified client certificate
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertificate {
+    pub subject: String,
+    pub san: String,
+    pub fingerprint: String,
+}
+
+// the client certificates which are verified in tls handshake,
+// the key is the sha256 fingerprint of certificate,
+// lru cache is used because every verified certificate should be admitted,
+// the evicted one is cached again from the tls connection of http/1,
+// but it can't be got for http/2 because its stream is not exposed
+static CLIENT_CERTIFICATES: Lazy<
+    Mutex<LruCache<String, Arc<ClientCertificate>>>,
+> = Lazy::new(|| Mutex::new(LruCache::new(NonZeroUsize::new(10_000).unwrap())));
+
+static CLIENT_AUTH_OPTIONAL: &str = "optional";
+
+// the wire format of alpn protocols
+static H2H1_ALPN: &[u8] = b"\x02h2\x08http/1.1";
+static ACME_TLS_ALPN: &[u8] = b"\x0aacme-tls/1";
+
+/// Get the verified client certificate by its sha256 digest,
+/// which is the cert digest of tls connection.
+pub fn get_client_certificate(digest: &[u8]) -> Option<Arc<ClientCertificate>> {
+    if digest.is_empty() {
+        return None;
+    }
+    CLIENT_CERTIFICATES
+        .lock()
+        .ok()?
+        .get(&hex::encode(digest))
+        .cloned()
+}
+
+/// Cache the identity of verified peer certificate of tls connection,
+/// it's used if the identity is evicted from cache or the verify callback
+/// is not called for the resumed session.
+pub fn cache_client_certificate(
+    ssl: &SslRef,
+) -> Option<Arc<ClientCertificate>> {
+    if ssl.verify_result() != X509VerifyResult::OK {
+        return None;
+    }
+    let cert = ssl.peer_certificate()?;
+    let info = Arc::new(new_client_certificate(&cert)?);
+    CLIENT_CERTIFICATES
+        .lock()
+        .ok()?
+        .put(info.fingerprint.clone(), info.clone());
+    Some(info)
+}
+
+fn new_client_certificate(cert: &X509Ref) -> Option<ClientCertificate> {
+    let fingerprint = hex::encode(cert.digest(MessageDigest::sha256()).ok()?);
+    let subject: Vec<String> = cert
+        .subject_name()
+        .entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or_default();
+            let value = entry
+                .data()
+                .as_utf8()
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+            format!("{key}={value}")
+        })
+        .collect();
+    let mut san = vec![];
+    if let Some(names) = cert.subject_alt_names() {
+        for name in names.iter() {
+            if let Some(value) = name.dnsname() {
+                san.push(format!("DNS:{value}"));
+            } else if let Some(value) = name.email() {
+                san.push(format!("email:{value}"));
+            } else if let Some(value) = name.uri() {
+                san.push(format!("URI:{value}"));
+            } else if let Some(value) = name.ipaddress() {
+                let ip = match value.len() {
+                    4 => <[u8; 4]>::try_from(value)
+                        .map(|value| std::net::IpAddr::from(value).to_string())
+                        .ok(),
+                    16 => <[u8; 16]>::try_from(value)
+                        .map(|value| std::net::IpAddr::from(value).to_string())
+                        .ok(),
+                    _ => None,
+                };
+                if let Some(ip) = ip {
+                    san.push(format!("IP:{ip}"));
+                }
+            }
+        }
+    }
+    Some(ClientCertificate {
+        subject: subject.join(", "),
+        san: san.join(","),
+        fingerprint,
+    })
 }
 
 impl DynamicCertificate {
@@ -264,8 +394,38 @@ impl DynamicCertificate {
             category: "new_tls_settings".to_string(),
             message: e.to_string(),
         })?;
-        if params.enabled_h2 {
-            tls_settings.enable_h2();
+        // the alpn of acme-tls/1 is selected for tls-alpn-01 challenge,
+        // otherwise prefer h2 if it's enabled
+        let enabled_h2 = params.enabled_h2;
+        tls_settings.set_alpn_select_callback(move |ssl, alpn_in| {
+            let acme_tls_alpn = ssl
+                .servername(NameType::HOST_NAME)
+                .and_then(get_tls_alpn_certificate)
+                .and_then(|_| select_next_proto(ACME_TLS_ALPN, alpn_in));
+            if let Some(value) = acme_tls_alpn {
+                return Ok(value);
+            }
+            if !enabled_h2 || alpn_in.is_empty() {
+                return Err(AlpnError::NOACK);
+            }
+            select_next_proto(H2H1_ALPN, alpn_in).ok_or(AlpnError::NOACK)
+        });
+        // staple the ocsp response of selected certificate
+        if let Err(e) = tls_settings.set_status_callback(|ssl| {
+            let Some(staple) = ssl
+                .certificate()
+                .and_then(|cert| cert.digest(MessageDigest::sha256()).ok())
+                .and_then(|digest| get_ocsp_staple(&hex::encode(digest)))
+            else {
+                return Ok(false);
+            };
+            ssl.set_ocsp_status(&staple.response)?;
+            Ok(true)
+        }) {
+            error!(
+                error = e.to_string(),
+                name, "set ocsp status callback fail"
+            );
         }
         if let Some(cipher_list) = &params.cipher_list {
             if let Err(e) = tls_settings.set_cipher_list(cipher_list) {
@@ -301,6 +461,74 @@ impl DynamicCertificate {
             );
         }
 
+        if let Some(client_ca) = &params.client_ca {
+            let new_error =
+                |e: pingora::tls::error::ErrorStack| Error::Invalid {
+                    category: "client_ca".to_string(),
+                    message: e.to_string(),
+                };
+            let data =
+                util::convert_certificate_bytes(&Some(client_ca.to_string()))
+                    .unwrap_or_default();
+            let certs = X509::stack_from_pem(&data).map_err(new_error)?;
+            if certs.is_empty() {
+                return Err(Error::Invalid {
+                    category: "client_ca".to_string(),
+                    message: "client ca is empty".to_string(),
+                });
+            }
+            for cert in certs {
+                tls_settings.add_client_ca(&cert).map_err(new_error)?;
+                tls_settings
+                    .cert_store_mut()
+                    .add_cert(cert)
+                    .map_err(new_error)?;
+            }
+            if let Some(client_crl) = &params.client_crl {
+                let store = tls_settings.cert_store_mut();
+                store
+                    .add_lookup(X509Lookup::file())
+                    .map_err(new_error)?
+                    .load_crl_file(
+                        util::resolve_path(client_crl),
+                        SslFiletype::PEM,
+                    )
+                    .map_err(new_error)?;
+                store
+                    .set_flags(X509VerifyFlags::CRL_CHECK)
+                    .map_err(new_error)?;
+            }
+            // the session can't be resumed if session id context is not set
+            let context = name.as_bytes();
+            tls_settings
+                .set_session_id_context(&context[..context.len().min(32)])
+                .map_err(new_error)?;
+            let mode = if params.client_auth.as_deref()
+                == Some(CLIENT_AUTH_OPTIONAL)
+            {
+                SslVerifyMode::PEER
+            } else {
+                SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT
+            };
+            tls_settings.set_verify_callback(mode, |verified, ctx| {
+                // cache the identity of client certificate,
+                // it's used for the requests of this connection
+                if verified && ctx.error_depth() == 0 {
+                    if let Some(info) =
+                        ctx.current_cert().and_then(new_client_certificate)
+                    {
+                        if let Ok(mut certificates) = CLIENT_CERTIFICATES.lock()
+                        {
+                            certificates
+                                .put(info.fingerprint.clone(), Arc::new(info));
+                        }
+                    }
+                }
+                verified
+            });
+            info!(name, mode = format!("{mode:?}"), "tls client auth");
+        }
+
         // tls_settings.set_min_proto_version(version)
         if let Some(min_version) = tls_settings.min_proto_version() {
             info!(name, min_version = format!("{min_version:?}"), "tls proto");
@@ -341,6 +569,17 @@ impl pingora::listeners::TlsAccept for DynamicCertificate {
     async fn certificate_callback(&self, ssl: &mut SslRef) {
         // TODO add more debug log
         debug!(ssl = format!("{ssl:?}"));
+        // the alpn is selected before certificate callback only for tls 1.3,
+        // so the tls-alpn-01 challenge needs tls 1.3
+        if ssl.selected_alpn_protocol() == Some(ACME_TLS_ALPN_NAME) {
+            if let Some(cert) = ssl
+                .servername(NameType::HOST_NAME)
+                .and_then(get_tls_alpn_certificate)
+            {
+                ssl_certificate(ssl, &cert.0, &cert.1, &None);
+                return;
+            }
+        }
         if let Some((cert, key)) = &self.certificate {
             ssl_certificate(ssl, cert, key, &self.chain_certificate);
             return;
@@ -391,7 +630,11 @@ impl pingora::listeners::TlsAccept for DynamicCertificate {
 
 #[cfg(test)]
 mod tests {
-    use super::{parse_certificate, DynamicCertificate, TlsSettingParams};
+    use super::{
+        cache_client_certificate, get_client_certificate,
+        new_client_certificate, parse_certificate, DynamicCertificate,
+        TlsSettingParams, CLIENT_CERTIFICATES,
+    };
     use crate::{
         config::CertificateConf,
         proxy::{
@@ -399,6 +642,8 @@ mod tests {
             init_certificates,
         },
     };
+    use pingora::tls::hash::MessageDigest;
+    use pingora::tls::x509::X509;
     use pretty_assertions::assert_eq;
     use std::collections::HashMap;
 
@@ -477,12 +722,133 @@ aqcrKJfS+xaKWxXPiNlpBMG5
                 ),
                 tls_min_version: Some("tlsv1.1".to_string()),
                 tls_max_version: Some("tlsv1.3".to_string()),
+                client_ca: None,
+                client_auth: None,
+                client_crl: None,
             })
             .unwrap();
         assert_eq!(true, tls_setings.min_proto_version().is_some());
         assert_eq!(true, tls_setings.max_proto_version().is_some());
     }
 
+    #[test]
+    fn test_client_certificate() {
+        let (tls_cert, _) = get_tls_pem();
+        let dynamic = DynamicCertificate::new_global();
+        let new_params = |client_ca: &str| TlsSettingParams {
+            server_name: "pingap".to_string(),
+            enabled_h2: false,
+            cipher_list: None,
+            ciphersuites: None,
+            tls_min_version: None,
+            tls_max_version: None,
+            client_ca: Some(client_ca.to_string()),
+            client_auth: Some("optional".to_string()),
+            client_crl: None,
+        };
+        assert_eq!(
+            true,
+            dynamic.new_tls_settings(&new_params(&tls_cert)).is_ok()
+        );
+        assert_eq!(
+            "Invalid error, category: client_ca, client ca is empty",
+            dynamic
+                .new_tls_settings(&new_params("-----BEGIN"))
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        let cert = X509::from_pem(tls_cert.as_bytes()).unwrap();
+        let info = new_client_certificate(&cert).unwrap();
+        assert_eq!(
+            "O=mkcert development certificate, OU=vicanso@tree",
+            info.subject
+        );
+        assert_eq!("DNS:pingap.io", info.san);
+        assert_eq!(64, info.fingerprint.len());
+        assert_eq!(true, get_client_certificate(&[]).is_none());
+    }
+
+    #[test]
+    fn test_cache_client_certificate() {
+        use pingora::tls::pkey::PKey;
+        use pingora::tls::ssl::{
+            SslAcceptor, SslConnector, SslMethod, SslVerifyMode,
+        };
+        use std::os::unix::net::UnixStream;
+
+        let server =
+            rcgen::generate_simple_self_signed(vec!["pingap.io".to_string()])
+                .unwrap();
+        let client = rcgen::generate_simple_self_signed(vec![
+            "client.pingap.io".to_string(),
+        ])
+        .unwrap();
+        let client_cert = X509::from_pem(client.cert.pem().as_bytes()).unwrap();
+
+        let mut acceptor =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor
+            .set_certificate(
+                &X509::from_pem(server.cert.pem().as_bytes()).unwrap(),
+            )
+            .unwrap();
+        acceptor
+            .set_private_key(
+                &PKey::private_key_from_pem(
+                    server.key_pair.serialize_pem().as_bytes(),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        acceptor
+            .cert_store_mut()
+            .add_cert(client_cert.clone())
+            .unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER);
+        let acceptor = acceptor.build();
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_certificate(&client_cert).unwrap();
+        connector
+            .set_private_key(
+                &PKey::private_key_from_pem(
+                    client.key_pair.serialize_pem().as_bytes(),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let connector = connector.build();
+
+        let (server_stream, client_stream) = UnixStream::pair().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut stream =
+                connector.connect("pingap.io", client_stream).unwrap();
+            let _ = std::io::Read::read(&mut stream, &mut [0; 1]);
+        });
+        let stream = acceptor.accept(server_stream).unwrap();
+
+        // the identity is evicted from cache
+        let digest = client_cert.digest(MessageDigest::sha256()).unwrap();
+        CLIENT_CERTIFICATES
+            .lock()
+            .unwrap()
+            .pop(&hex::encode(digest));
+        assert_eq!(true, get_client_certificate(&digest).is_none());
+
+        let info = cache_client_certificate(stream.ssl()).unwrap();
+        assert_eq!(hex::encode(digest), info.fingerprint);
+        assert_eq!("DNS:client.pingap.io", info.san);
+        assert_eq!(
+            info.fingerprint,
+            get_client_certificate(&digest).unwrap().fingerprint
+        );
+        drop(stream);
+        handle.join().unwrap();
+    }
+
     #[test]
     fn test_parse_certificate() {
         let (tls_cert, tls_key) = get_tls_pem();
@@ -19,6 +19,7 @@ use crate::state::State;
 use crate::util;
 use ahash::AHashMap;
 use arc_swap::ArcSwap;
+use http::{HeaderValue, Method, StatusCode};
 use once_cell::sync::Lazy;
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::proxy::Session;
@@ -26,10 +27,11 @@ use regex::Regex;
 use snafu::{ResultExt, Snafu};
 use std::collections::HashMap;
 use std::fmt;
-use std::sync::atomic::{AtomicI32, AtomicU64};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use substring::Substring;
 use tracing::{debug, error};
+use urlencoding::encode;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -40,6 +42,17 @@ pub enum Error {
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+impl Error {
+    /// Get the message of error without the prefix of invalid error,
+    /// it's used by the config validation which has its own prefix.
+    pub fn message(&self) -> String {
+        match self {
+            Error::Invalid { message } => message.clone(),
+            _ => self.to_string(),
+        }
+    }
+}
+
 struct RegexPath {
     value: Regex,
 }
@@ -129,19 +142,504 @@ fn new_host_selector(host: &str) -> Result<HostSelector> {
     Ok(se)
 }
 
+#[derive(Debug)]
+struct UpstreamOverride {
+    // header, cookie or query
+    category: String,
+    key: String,
+    // the value is not checked if it's none
+    value: Option<String>,
+    upstream: String,
+}
+
+impl UpstreamOverride {
+    /// Parse the override rule, e.g. `header:X-Canary=1 charts-v2`
+    fn new(value: &str) -> Result<Self> {
+        let invalid = || Error::Invalid {
+            message: format!("upstream override {value} is invalid"),
+        };
+        let (rule, upstream) =
+            value.trim().split_once(' ').ok_or_else(invalid)?;
+        let (category, rule) = rule.split_once(':').ok_or_else(invalid)?;
+        if !["header", "cookie", "query"].contains(&category) {
+            return Err(invalid());
+        }
+        let (key, value) = if let Some((key, value)) = rule.split_once('=') {
+            (key, Some(value.to_string()))
+        } else {
+            (rule, None)
+        };
+        let upstream = upstream.trim();
+        if key.is_empty() || upstream.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            category: category.to_string(),
+            key: key.to_string(),
+            value,
+            upstream: upstream.to_string(),
+        })
+    }
+    #[inline]
+    fn matched(&self, header: &RequestHeader) -> bool {
+        let Some(value) = get_req_value(header, &self.category, &self.key)
+        else {
+            return false;
+        };
+        if let Some(expected) = &self.value {
+            return expected == value;
+        }
+        true
+    }
+}
+
+/// Get the value of header, cookie or query from request.
+#[inline]
+fn get_req_value<'a>(
+    header: &'a RequestHeader,
+    category: &str,
+    key: &str,
+) -> Option<&'a str> {
+    match category {
+        "header" => util::get_req_header_value(header, key),
+        "cookie" => util::get_cookie_value(header, key),
+        "query" => util::get_query_value(header, key),
+        _ => None,
+    }
+}
+
+/// The value like `:client_cert_subject` is got from the context of request.
+#[inline]
+fn is_context_value(value: &HeaderValue) -> bool {
+    value.as_bytes().starts_with(b":")
+}
+
+#[derive(Debug)]
+enum ValueMatcher {
+    Exists,
+    Equal(String),
+    Regex(Regex),
+}
+
+/// The match condition of header, query or cookie.
+#[derive(Debug)]
+pub struct RequestMatcher {
+    category: String,
+    key: String,
+    matcher: ValueMatcher,
+}
+
+impl RequestMatcher {
+    /// Parse the match condition, `X-Beta` checks the value exists,
+    /// `X-Beta=1` checks the value is equal and `User-Agent~curl` uses regex.
+    pub fn new(category: &str, value: &str) -> Result<Self> {
+        let value = value.trim();
+        let (key, matcher) = match value.find(['=', '~']) {
+            Some(index) => {
+                let (key, expected) = value.split_at(index);
+                let expected = &expected[1..];
+                let matcher = if value[index..].starts_with('~') {
+                    ValueMatcher::Regex(Regex::new(expected).context(
+                        RegexSnafu {
+                            value: expected.to_string(),
+                        },
+                    )?)
+                } else {
+                    ValueMatcher::Equal(expected.to_string())
+                };
+                (key.trim(), matcher)
+            },
+            None => (value, ValueMatcher::Exists),
+        };
+        if key.is_empty() {
+            return Err(Error::Invalid {
+                message: format!("{category} match {value} is invalid"),
+            });
+        }
+        Ok(Self {
+            category: category.to_string(),
+            key: key.to_string(),
+            matcher,
+        })
+    }
+    #[inline]
+    fn matched(&self, header: &RequestHeader) -> bool {
+        let Some(value) = get_req_value(header, &self.category, &self.key)
+        else {
+            return false;
+        };
+        match &self.matcher {
+            ValueMatcher::Exists => true,
+            ValueMatcher::Equal(expected) => expected == value,
+            ValueMatcher::Regex(re) => re.is_match(value),
+        }
+    }
+}
+
+/// Split the traffic of location between multiple upstreams by weight,
+/// and the override rules have higher priority.
+#[derive(Debug, Default)]
+struct UpstreamSplitter {
+    // the upstream and its cumulative weight
+    upstreams: Vec<(String, u32)>,
+    total_weight: u32,
+    overrides: Vec<UpstreamOverride>,
+    // the upstream is selected by the hash of value if it's set,
+    // so the same client always uses the same upstream
+    hash: Option<(String, String)>,
+    count: AtomicU32,
+}
+
+impl UpstreamSplitter {
+    fn new(conf: &LocationConf) -> Result<Option<Self>> {
+        let overrides = conf
+            .upstream_overrides
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|item| UpstreamOverride::new(item))
+            .collect::<Result<Vec<_>>>()?;
+        let mut upstreams = vec![];
+        let mut total_weight: u32 = 0;
+        for item in conf.upstreams.clone().unwrap_or_default().iter() {
+            let (name, weight) = parse_upstream_weight(item)?;
+            if weight == 0 {
+                continue;
+            }
+            total_weight =
+                total_weight.checked_add(weight).ok_or_else(|| {
+                    Error::Invalid {
+                        message: format!("upstream weight {item} is invalid"),
+                    }
+                })?;
+            upstreams.push((name, total_weight));
+        }
+        if upstreams.is_empty() && overrides.is_empty() {
+            return Ok(None);
+        }
+        let hash = conf
+            .upstream_hash
+            .as_ref()
+            .map(|value| {
+                value
+                    .split_once(':')
+                    .filter(|(category, key)| {
+                        ["header", "cookie", "query"].contains(category)
+                            && !key.is_empty()
+                    })
+                    .map(|(category, key)| {
+                        (category.to_string(), key.to_string())
+                    })
+                    .ok_or_else(|| Error::Invalid {
+                        message: format!("upstream hash {value} is invalid"),
+                    })
+            })
+            .transpose()?;
+        Ok(Some(Self {
+            upstreams,
+            total_weight,
+            overrides,
+            hash,
+            count: AtomicU32::new(0),
+        }))
+    }
+    /// Select the upstream by override rules and weight,
+    /// returns `None` if no upstream is selected.
+    fn select(&self, header: &RequestHeader) -> Option<&str> {
+        if let Some(item) =
+            self.overrides.iter().find(|item| item.matched(header))
+        {
+            return Some(&item.upstream);
+        }
+        if self.total_weight == 0 {
+            return None;
+        }
+        let value = self
+            .hash
+            .as_ref()
+            .and_then(|(category, key)| get_req_value(header, category, key))
+            .map(|value| crc32fast::hash(value.as_bytes()))
+            .unwrap_or_else(|| self.count.fetch_add(1, Ordering::Relaxed));
+        let index = value % self.total_weight;
+        self.upstreams
+            .iter()
+            .find(|(_, weight)| index < *weight)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// Parse the upstream and weight, e.g. `charts-v2 5`,
+/// the weight is 1 if it's not set.
+fn parse_upstream_weight(value: &str) -> Result<(String, u32)> {
+    let value = value.trim();
+    let Some((name, weight)) = value.split_once(' ') else {
+        return Ok((value.to_string(), 1));
+    };
+    let weight = weight.trim().parse::<u32>().map_err(|_| Error::Invalid {
+        message: format!("upstream weight {value} is invalid"),
+    })?;
+    Ok((name.to_string(), weight))
+}
+
+/// The flag of rewrite rule, which is the same as nginx.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RewriteFlag {
+    // stop processing the rules and search location again
+    Last,
+    // stop processing the rules
+    Break,
+    // return a temporary redirect with the 302 code
+    Redirect,
+    // return a permanent redirect with the 301 code
+    Permanent,
+}
+
+/// The modifier of query string after the path is rewritten.
+#[derive(Debug)]
+enum QueryModifier {
+    // set the value of query, it supports variables
+    Add(String, String),
+    Remove(String),
+    Rename(String, String),
+}
+
+/// The result of rewrite rules.
+#[derive(Debug, PartialEq)]
+pub enum RewriteResult {
+    // no rule is matched
+    Unchanged,
+    // the uri is rewritten
+    Rewritten,
+    // the uri is rewritten and location should be searched again
+    Last,
+    // redirect the request to the uri
+    Redirect(StatusCode, String),
+}
+
+/// The rewrite rule of request uri, the format is
+/// `regex replacement [flag] [if~regex] [if!~regex] [add_query=key:value]
+/// [remove_query=key] [rename_query=key:new_key]`.
+/// The conditions are matched against the original request uri.
+#[derive(Debug)]
+pub struct RewriteRule {
+    regex: Regex,
+    replacement: String,
+    flag: Option<RewriteFlag>,
+    conditions: Vec<(bool, Regex)>,
+    query_modifiers: Vec<QueryModifier>,
+}
+
+impl RewriteRule {
+    /// Parse the rewrite rule, it's also used to validate the config.
+    pub fn new(value: &str) -> Result<Self> {
+        let invalid_err = || Error::Invalid {
+            message: format!("rewrite rule {value} is invalid"),
+        };
+        let new_regex = |value: &str| {
+            Regex::new(value).context(RegexSnafu {
+                value: value.to_string(),
+            })
+        };
+        let arr: Vec<&str> = value.split_whitespace().collect();
+        if arr.len() < 2 {
+            return Err(invalid_err());
+        }
+        let mut rule = RewriteRule {
+            regex: new_regex(arr[0])?,
+            replacement: arr[1].to_string(),
+            flag: None,
+            conditions: vec![],
+            query_modifiers: vec![],
+        };
+        for (index, item) in arr[2..].iter().enumerate() {
+            let flag = match *item {
+                "last" => Some(RewriteFlag::Last),
+                "break" => Some(RewriteFlag::Break),
+                "redirect" => Some(RewriteFlag::Redirect),
+                "permanent" => Some(RewriteFlag::Permanent),
+                _ => None,
+            };
+            if index == 0 && flag.is_some() {
+                rule.flag = flag;
+                continue;
+            }
+            if let Some(value) = item.strip_prefix("if!~") {
+                rule.conditions.push((true, new_regex(value)?));
+                continue;
+            }
+            if let Some(value) = item.strip_prefix("if~") {
+                rule.conditions.push((false, new_regex(value)?));
+                continue;
+            }
+            let modifier = match item.split_once('=') {
+                Some(("add_query", value)) => value
+                    .split_once(':')
+                    .filter(|(key, _)| !key.is_empty())
+                    .map(|(key, value)| {
+                        QueryModifier::Add(key.to_string(), value.to_string())
+                    }),
+                Some(("rename_query", value)) => value
+                    .split_once(':')
+                    .filter(|(key, _)| !key.is_empty())
+                    .map(|(key, value)| {
+                        QueryModifier::Rename(
+                            key.to_string(),
+                            value.to_string(),
+                        )
+                    }),
+                Some(("remove_query", value)) if !value.is_empty() => {
+                    Some(QueryModifier::Remove(value.to_string()))
+                },
+                _ => None,
+            };
+            rule.query_modifiers.push(modifier.ok_or_else(invalid_err)?);
+        }
+        Ok(rule)
+    }
+    /// Return `true` if the path and all conditions of original uri match.
+    #[inline]
+    fn matched(&self, path: &str, original_uri: &str) -> bool {
+        self.regex.is_match(path)
+            && self
+                .conditions
+                .iter()
+                .all(|(negative, re)| re.is_match(original_uri) != *negative)
+    }
+    /// Get the new uri of the rule, the query of replacement is prepended
+    /// to the original query, and the original query is dropped
+    /// if the replacement ends with `?`.
+    fn replace(
+        &self,
+        header: &RequestHeader,
+        variables: Option<&AHashMap<String, String>>,
+    ) -> String {
+        let new_path = replace_path(
+            &self.regex,
+            &self.replacement,
+            header.uri.path(),
+            variables,
+        );
+        let mut queries = vec![];
+        let new_path = match new_path.split_once('?') {
+            Some((path, query)) => {
+                if !query.is_empty() {
+                    queries.extend(split_query(query));
+                    queries.extend(split_query(
+                        header.uri.query().unwrap_or_default(),
+                    ));
+                }
+                path.to_string()
+            },
+            None => {
+                queries.extend(split_query(
+                    header.uri.query().unwrap_or_default(),
+                ));
+                new_path
+            },
+        };
+        for modifier in self.query_modifiers.iter() {
+            match modifier {
+                QueryModifier::Add(key, value) => {
+                    // the values of variables are from request,
+                    // so they are encoded to keep the query valid
+                    let value = if let Some(variables) = variables {
+                        util::replace_variables(value, |name| {
+                            variables
+                                .get(name)
+                                .map(|value| encode(value).to_string())
+                        })
+                    } else {
+                        value.to_string()
+                    };
+                    queries.retain(|(k, _)| k != key);
+                    queries.push((key.to_string(), value));
+                },
+                QueryModifier::Remove(key) => {
+                    queries.retain(|(k, _)| k != key);
+                },
+                QueryModifier::Rename(key, new_key) => {
+                    for (k, _) in queries.iter_mut().filter(|(k, _)| k == key) {
+                        k.clone_from(new_key);
+                    }
+                },
+            }
+        }
+        if queries.is_empty() {
+            return new_path;
+        }
+        let query = queries
+            .iter()
+            .map(|(key, value)| {
+                if value.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{key}={value}")
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+        format!("{new_path}?{query}")
+    }
+}
+
+/// Split the query string to key value pairs, the values are not decoded.
+fn split_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            let (key, value) = item.split_once('=').unwrap_or((item, ""));
+            (key.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// Replace the path by regex, the variables can be used in replacement,
+/// and the captures of regex have higher priority than them.
+fn replace_path(
+    re: &Regex,
+    value: &str,
+    path: &str,
+    variables: Option<&AHashMap<String, String>>,
+) -> String {
+    if let Some(variables) = variables {
+        let value = util::replace_variables(value, |name| {
+            if re.capture_names().flatten().any(|item| item == name) {
+                return None;
+            }
+            variables.get(name).map(|value| value.replace('$', "$$"))
+        });
+        re.replace(path, value).to_string()
+    } else {
+        re.replace(path, value).to_string()
+    }
+}
+
 pub struct Location {
     pub name: String,
     pub key: String,
     path: String,
     path_selector: PathSelector,
     hosts: Vec<HostSelector>,
+    // whether the regex of host or path has named captures,
+    // the captures are skipped if they don't
+    host_captures: bool,
+    path_captures: bool,
+    methods: Vec<Method>,
+    request_matchers: Vec<RequestMatcher>,
+    client_ip_rules: Option<util::IpRules>,
     reg_rewrite: Option<(Regex, String)>,
+    rewrite_rules: Vec<RewriteRule>,
     proxy_add_headers: Option<Vec<HttpHeader>>,
     proxy_set_headers: Option<Vec<HttpHeader>>,
     plugins: Option<Vec<String>>,
     pub accepted: AtomicU64,
     pub processing: AtomicI32,
     pub upstream: String,
+    upstream_splitter: Option<UpstreamSplitter>,
+    mirror_upstream: String,
+    mirror_percentage: u32,
+    mirror_count: AtomicU32,
     client_max_body_size: usize,
 }
 
@@ -181,7 +679,17 @@ impl Location {
             });
         }
         let key = conf.hash_key();
-        let upstream = conf.upstream.clone().unwrap_or_default();
+        let upstream_splitter = UpstreamSplitter::new(conf)?;
+        let mut upstream = conf.upstream.clone().unwrap_or_default();
+        // use the first weighted upstream as default
+        if upstream.is_empty() {
+            if let Some((name, _)) = upstream_splitter
+                .as_ref()
+                .and_then(|splitter| splitter.upstreams.first())
+            {
+                upstream.clone_from(name);
+            }
+        }
         let mut reg_rewrite = None;
         if let Some(value) = &conf.rewrite {
             let arr: Vec<&str> = value.split(' ').collect();
@@ -190,6 +698,13 @@ impl Location {
                 reg_rewrite = Some((re, value.to_string()));
             }
         }
+        let rewrite_rules = conf
+            .rewrites
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|item| RewriteRule::new(item))
+            .collect::<Result<Vec<_>>>()?;
         let mut hosts = vec![];
         for item in conf.host.clone().unwrap_or_default().split(',') {
             let host = item.trim().to_string();
@@ -199,16 +714,66 @@ impl Location {
             hosts.push(new_host_selector(&host)?);
         }
 
+        let methods = conf
+            .methods
+            .clone()
+            .unwrap_or_default()
+            .iter()
+            .map(|item| {
+                Method::from_bytes(item.trim().to_uppercase().as_bytes())
+                    .map_err(|e| Error::Invalid {
+                        message: format!("method {item} is invalid, {e}"),
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut request_matchers = vec![];
+        for (category, values) in [
+            ("header", &conf.match_headers),
+            ("query", &conf.match_queries),
+            ("cookie", &conf.match_cookies),
+        ] {
+            for item in values.clone().unwrap_or_default().iter() {
+                request_matchers.push(RequestMatcher::new(category, item)?);
+            }
+        }
+        let client_ip_rules = conf
+            .client_ips
+            .as_ref()
+            .filter(|values| !values.is_empty())
+            .map(util::IpRules::new);
+
         let path = conf.path.clone().unwrap_or_default();
 
+        let path_selector = new_path_selector(&path)?;
+        let host_captures = hosts.iter().any(|item| match item {
+            HostSelector::RegexHost(RegexHost { value }) => value.has_names(),
+            _ => false,
+        });
+        let path_captures = match &path_selector {
+            PathSelector::RegexPath(RegexPath { value }) => {
+                value.capture_names().flatten().next().is_some()
+            },
+            _ => false,
+        };
         let location = Location {
             name: name.to_string(),
             key,
-            path_selector: new_path_selector(&path)?,
+            path_selector,
             path,
             hosts,
+            host_captures,
+            path_captures,
+            methods,
+            request_matchers,
+            client_ip_rules,
             upstream,
+            upstream_splitter,
+            mirror_upstream: conf.mirror_upstream.clone().unwrap_or_default(),
+            mirror_percentage: conf.mirror_percentage.unwrap_or(100).min(100)
+                as u32,
+            mirror_count: AtomicU32::new(0),
             reg_rewrite,
+            rewrite_rules,
             plugins: conf.plugins.clone(),
             accepted: AtomicU64::new(0),
             processing: AtomicI32::new(0),
@@ -254,6 +819,91 @@ impl Location {
             HostSelector::EqualHost(EqualHost { value }) => value == host,
         })
     }
+    /// Get the named captures of host and path regex,
+    /// they are used as variables of request.
+    pub fn get_captures(
+        &self,
+        host: &str,
+        path: &str,
+    ) -> Option<AHashMap<String, String>> {
+        if !self.host_captures && !self.path_captures {
+            return None;
+        }
+        let mut variables = AHashMap::new();
+        for item in self.hosts.iter().filter(|_| self.host_captures) {
+            if let HostSelector::RegexHost(RegexHost { value }) = item {
+                if let (true, Some(captures)) = value.captures(host) {
+                    variables.extend(captures);
+                    break;
+                }
+            }
+        }
+        if let (true, PathSelector::RegexPath(RegexPath { value })) =
+            (self.path_captures, &self.path_selector)
+        {
+            if let Some(captures) = value.captures(path) {
+                for name in value.capture_names().flatten() {
+                    if let Some(item) = captures.name(name) {
+                        variables.insert(
+                            name.to_string(),
+                            item.as_str().to_string(),
+                        );
+                    }
+                }
+            }
+        }
+        if variables.is_empty() {
+            return None;
+        }
+        Some(variables)
+    }
+    /// Return `true` if the method, headers, queries, cookies
+    /// and peer ip of request match location.
+    #[inline]
+    pub fn matched_request(&self, session: &Session) -> bool {
+        let header = session.req_header();
+        if !self.methods.is_empty() && !self.methods.contains(&header.method) {
+            return false;
+        }
+        if !self
+            .request_matchers
+            .iter()
+            .all(|item| item.matched(header))
+        {
+            return false;
+        }
+        // the forwarded headers can be forged by client,
+        // so only the socket peer address is matched
+        if let Some(rules) = &self.client_ip_rules {
+            let Some((ip, _)) = util::get_remote_addr(session) else {
+                return false;
+            };
+            return rules.matched(&ip).unwrap_or_default();
+        }
+        true
+    }
+    /// Get the upstream of request, it's selected by the override rules
+    /// and weights if multiple upstreams are set.
+    #[inline]
+    pub fn get_upstream_name(&self, header: &RequestHeader) -> &str {
+        self.upstream_splitter
+            .as_ref()
+            .and_then(|splitter| splitter.select(header))
+            .unwrap_or(&self.upstream)
+    }
+    /// Get the mirror upstream if the request should be mirrored,
+    /// the requests are sampled by the mirror percentage.
+    #[inline]
+    pub fn get_mirror_upstream(&self) -> Option<&str> {
+        if self.mirror_upstream.is_empty() || self.mirror_percentage == 0 {
+            return None;
+        }
+        let count = self.mirror_count.fetch_add(1, Ordering::Relaxed);
+        if count % 100 >= self.mirror_percentage {
+            return None;
+        }
+        Some(&self.mirror_upstream)
+    }
     /// Sets the maximum allowed size of the client request body.
     /// If the size in a request exceeds the configured value, the 413 (Request Entity Too Large) error
     /// is returned to the client.
@@ -286,11 +936,16 @@ impl Location {
     }
     /// Rewrite the path by the rule and returns true.
     /// If the rule is not exists, returns false.
+    /// The variables can be used in the rule, e.g. `^/(.*)$ /$tenant/$1`.
     #[inline]
-    pub fn rewrite(&self, header: &mut RequestHeader) -> bool {
+    pub fn rewrite(
+        &self,
+        header: &mut RequestHeader,
+        variables: Option<&AHashMap<String, String>>,
+    ) -> bool {
         if let Some((re, value)) = &self.reg_rewrite {
             let path = header.uri.path();
-            let mut new_path = re.replace(path, value).to_string();
+            let mut new_path = replace_path(re, value, path, variables);
             if path == new_path {
                 return false;
             }
@@ -311,6 +966,63 @@ impl Location {
         }
         false
     }
+    /// Apply the rewrite rules in order, the rule is skipped if its regex
+    /// or conditions of original uri don't match.
+    /// The rules after a matched rule with `last` or `break` flag
+    /// are not processed, and `redirect` or `permanent` flag
+    /// returns the redirect uri.
+    pub fn rewrite_rules(
+        &self,
+        header: &mut RequestHeader,
+        variables: Option<&AHashMap<String, String>>,
+        original_uri: &str,
+    ) -> RewriteResult {
+        let mut result = RewriteResult::Unchanged;
+        for rule in self.rewrite_rules.iter() {
+            if !rule.matched(header.uri.path(), original_uri) {
+                continue;
+            }
+            let new_uri = rule.replace(header, variables);
+            match rule.flag {
+                Some(RewriteFlag::Redirect) => {
+                    return RewriteResult::Redirect(StatusCode::FOUND, new_uri);
+                },
+                Some(RewriteFlag::Permanent) => {
+                    return RewriteResult::Redirect(
+                        StatusCode::MOVED_PERMANENTLY,
+                        new_uri,
+                    );
+                },
+                // redirect if the replacement is an absolute url
+                _ if new_uri.starts_with("http://")
+                    || new_uri.starts_with("https://") =>
+                {
+                    return RewriteResult::Redirect(StatusCode::FOUND, new_uri);
+                },
+                _ => {},
+            }
+            debug!(new_uri, "rewrite uri");
+            match new_uri.parse::<http::Uri>() {
+                Ok(uri) => {
+                    header.set_uri(uri);
+                    result = RewriteResult::Rewritten;
+                },
+                Err(e) => {
+                    error!(
+                        error = e.to_string(),
+                        location = self.name,
+                        "new uri parse fail"
+                    );
+                },
+            }
+            match rule.flag {
+                Some(RewriteFlag::Last) => return RewriteResult::Last,
+                Some(RewriteFlag::Break) => break,
+                _ => {},
+            }
+        }
+        result
+    }
     /// Set or append the headers before proxy the request to upstream.
     #[inline]
     pub fn set_append_proxy_headers(
@@ -324,6 +1036,10 @@ impl Location {
                 if let Some(v) = convert_header_value(v, session, ctx) {
                     // v validate for HeaderValue, so always no error
                     let _ = header.insert_header(k, v);
+                } else if is_context_value(v) {
+                    // the value of context is empty,
+                    // the header from client should not be forwarded
+                    header.remove_header(k);
                 } else {
                     // v validate for HeaderValue, so always no error
                     let _ = header.insert_header(k, v);
@@ -335,7 +1051,7 @@ impl Location {
                 if let Some(v) = convert_header_value(v, session, ctx) {
                     // v validate for HeaderValue, so always no error
                     let _ = header.append_header(k, v);
-                } else {
+                } else if !is_context_value(v) {
                     // v validate for HeaderValue, so always no error
                     let _ = header.append_header(k, v);
                 }
@@ -427,12 +1143,16 @@ pub fn try_init_locations(
 
 #[cfg(test)]
 mod tests {
-    use super::{format_headers, new_path_selector, Location, PathSelector};
+    use super::{
+        format_headers, new_path_selector, parse_upstream_weight, Location,
+        PathSelector, RequestMatcher, RewriteResult, UpstreamOverride,
+    };
     use crate::config::{LocationConf, PluginStep};
     use crate::plugin::initialize_test_plugins;
     use crate::state::State;
+    use ahash::AHashMap;
     use bytesize::ByteSize;
-    use http::Method;
+    use http::{Method, StatusCode};
     use pingora::http::{RequestHeader, ResponseHeader};
     use pingora::proxy::Session;
     use pretty_assertions::assert_eq;
@@ -552,6 +1272,263 @@ mod tests {
         assert_eq!(true, lo.matched("", "/api"));
     }
 
+    #[test]
+    fn test_upstream_override() {
+        assert_eq!(
+            ("charts".to_string(), 1),
+            parse_upstream_weight("charts").unwrap()
+        );
+        assert_eq!(
+            ("charts-v2".to_string(), 5),
+            parse_upstream_weight("charts-v2 5").unwrap()
+        );
+        assert_eq!(
+            "Invalid error upstream weight charts a is invalid",
+            parse_upstream_weight("charts a").err().unwrap().to_string()
+        );
+
+        let item =
+            UpstreamOverride::new("header:X-Canary=1 charts-v2").unwrap();
+        assert_eq!(
+            r#"UpstreamOverride { category: "header", key: "X-Canary", value: Some("1"), upstream: "charts-v2" }"#,
+            format!("{item:?}")
+        );
+        let req_header =
+            RequestHeader::build("GET", b"/users/me?abc=1", None).unwrap();
+        assert_eq!(false, item.matched(&req_header));
+
+        let item = UpstreamOverride::new("query:abc charts-v2").unwrap();
+        assert_eq!(true, item.matched(&req_header));
+
+        assert_eq!(
+            "Invalid error upstream override ip:abc charts-v2 is invalid",
+            UpstreamOverride::new("ip:abc charts-v2")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+        assert_eq!(true, UpstreamOverride::new("header:X-Canary").is_err());
+    }
+
+    #[test]
+    fn test_get_upstream_name() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstreams: Some(vec![
+                    "charts 3".to_string(),
+                    "charts-v2 1".to_string(),
+                ]),
+                upstream_overrides: Some(vec![
+                    "cookie:canary=1 charts-v3".to_string()
+                ]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!("charts", lo.upstream);
+
+        let req_header =
+            RequestHeader::build("GET", b"/users/me", None).unwrap();
+        let upstreams: Vec<_> = (0..8)
+            .map(|_| lo.get_upstream_name(&req_header).to_string())
+            .collect();
+        assert_eq!(
+            "charts,charts,charts,charts-v2,charts,charts,charts,charts-v2",
+            upstreams.join(",")
+        );
+
+        let mut req_header =
+            RequestHeader::build("GET", b"/users/me", None).unwrap();
+        req_header.insert_header("Cookie", "canary=1").unwrap();
+        assert_eq!("charts-v3", lo.get_upstream_name(&req_header));
+
+        // sticky by the hash of query value
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstreams: Some(vec![
+                    "charts 1".to_string(),
+                    "charts-v2 1".to_string(),
+                ]),
+                upstream_hash: Some("query:uid".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let req_header =
+            RequestHeader::build("GET", b"/users/me?uid=123", None).unwrap();
+        let upstream = lo.get_upstream_name(&req_header).to_string();
+        for _ in 0..5 {
+            assert_eq!(upstream, lo.get_upstream_name(&req_header));
+        }
+
+        // invalid hash or weight
+        let result = Location::new(
+            "lo",
+            &LocationConf {
+                upstreams: Some(vec!["charts".to_string()]),
+                upstream_hash: Some("uid".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            "Invalid error upstream hash uid is invalid",
+            result.err().unwrap().to_string()
+        );
+        let result = Location::new(
+            "lo",
+            &LocationConf {
+                upstreams: Some(vec![
+                    "charts 4294967295".to_string(),
+                    "charts-v2 1".to_string(),
+                ]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            "Invalid error upstream weight charts-v2 1 is invalid",
+            result.err().unwrap().to_string()
+        );
+
+        // single upstream
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!("charts", lo.get_upstream_name(&req_header));
+    }
+
+    #[tokio::test]
+    async fn test_match_request() {
+        let item = RequestMatcher::new("header", "User-Agent~^curl").unwrap();
+        assert_eq!(
+            r#"RequestMatcher { category: "header", key: "User-Agent", matcher: Regex(Regex("^curl")) }"#,
+            format!("{item:?}")
+        );
+        let item = RequestMatcher::new("query", "id=1").unwrap();
+        assert_eq!(
+            r#"RequestMatcher { category: "query", key: "id", matcher: Equal("1") }"#,
+            format!("{item:?}")
+        );
+        assert_eq!(
+            "Invalid error cookie match =1 is invalid",
+            RequestMatcher::new("cookie", "=1")
+                .err()
+                .unwrap()
+                .to_string()
+        );
+
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                path: Some("/api/upload".to_string()),
+                methods: Some(vec!["post".to_string(), "PUT".to_string()]),
+                match_headers: Some(vec![
+                    "X-Beta=1".to_string(),
+                    "User-Agent~^curl".to_string(),
+                ]),
+                match_queries: Some(vec!["id".to_string()]),
+                match_cookies: Some(vec!["uid=123".to_string()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let new_session = |method: &str, headers: &[&str]| {
+            let input_header = format!(
+                "{method} /api/upload?id=1 HTTP/1.1\r\n{}\r\n\r\n",
+                headers.join("\r\n")
+            );
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            Session::new_h1(Box::new(mock_io))
+        };
+        let headers = [
+            "X-Beta: 1",
+            "User-Agent: curl/8.7.1",
+            "Cookie: uid=123",
+            "X-Forwarded-For: 192.168.1.10",
+        ];
+
+        let mut session = new_session("POST", &headers);
+        session.read_request().await.unwrap();
+        assert_eq!(true, lo.matched_request(&session));
+
+        let mut session = new_session("GET", &headers);
+        session.read_request().await.unwrap();
+        assert_eq!(false, lo.matched_request(&session));
+
+        let mut session = new_session("PUT", &headers[..2]);
+        session.read_request().await.unwrap();
+        assert_eq!(false, lo.matched_request(&session));
+
+        let mut session = new_session(
+            "PUT",
+            &[
+                "X-Beta: 2",
+                "User-Agent: curl/8.7.1",
+                "Cookie: uid=123",
+                "X-Forwarded-For: 192.168.1.10",
+            ],
+        );
+        session.read_request().await.unwrap();
+        assert_eq!(false, lo.matched_request(&session));
+
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(true, lo.matched_request(&session));
+
+        // the forwarded for header is not trusted
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                client_ips: Some(vec!["192.168.1.0/24".to_string()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(false, lo.matched_request(&session));
+    }
+
+    #[test]
+    fn test_get_mirror_upstream() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(None, lo.get_mirror_upstream());
+
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                mirror_upstream: Some("charts-v2".to_string()),
+                mirror_percentage: Some(10),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let count = (0..100)
+            .filter(|_| lo.get_mirror_upstream() == Some("charts-v2"))
+            .count();
+        assert_eq!(10, count);
+    }
+
     #[test]
     fn test_rewrite_path() {
         let upstream_name = "charts";
@@ -567,15 +1544,187 @@ mod tests {
         .unwrap();
         let mut req_header =
             RequestHeader::build("GET", b"/users/me?abc=1", None).unwrap();
-        assert_eq!(true, lo.rewrite(&mut req_header));
+        assert_eq!(true, lo.rewrite(&mut req_header, None));
         assert_eq!("/me?abc=1", req_header.uri.to_string());
 
         let mut req_header =
             RequestHeader::build("GET", b"/api/me?abc=1", None).unwrap();
-        assert_eq!(false, lo.rewrite(&mut req_header));
+        assert_eq!(false, lo.rewrite(&mut req_header, None));
         assert_eq!("/api/me?abc=1", req_header.uri.to_string());
     }
 
+    #[test]
+    fn test_rewrite_rules() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                rewrites: Some(vec![
+                    "^/old/(.*)$ /new/$1 permanent".to_string(),
+                    "^/go/(.*)$ https://pingap.io/$1? redirect".to_string(),
+                    "^/v1/(.*)$ /api/$1 if!~^/v1/internal".to_string(),
+                    "^/api/(.*)$ /$1 break add_query=tenant:$tenant remove_query=debug rename_query=q:keyword".to_string(),
+                    "^/search$ /search?from=web last".to_string(),
+                    "^/(.*)$ /fallback/$1".to_string(),
+                ]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let mut req_header =
+            RequestHeader::build("GET", b"/old/users?a=1", None).unwrap();
+        assert_eq!(
+            RewriteResult::Redirect(
+                StatusCode::MOVED_PERMANENTLY,
+                "/new/users?a=1".to_string()
+            ),
+            lo.rewrite_rules(&mut req_header, None, "/old/users?a=1")
+        );
+        assert_eq!("/old/users?a=1", req_header.uri.to_string());
+
+        let mut req_header =
+            RequestHeader::build("GET", b"/go/docs?a=1", None).unwrap();
+        assert_eq!(
+            RewriteResult::Redirect(
+                StatusCode::FOUND,
+                "https://pingap.io/docs".to_string()
+            ),
+            lo.rewrite_rules(&mut req_header, None, "/go/docs?a=1")
+        );
+
+        let mut variables = AHashMap::new();
+        variables.insert("tenant".to_string(), "pingap".to_string());
+        let mut req_header = RequestHeader::build(
+            "GET",
+            b"/v1/users?q=abc&debug=1&page=2",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            RewriteResult::Rewritten,
+            lo.rewrite_rules(
+                &mut req_header,
+                Some(&variables),
+                "/v1/users?q=abc&debug=1&page=2"
+            )
+        );
+        assert_eq!(
+            "/users?keyword=abc&page=2&tenant=pingap",
+            req_header.uri.to_string()
+        );
+
+        // the value of variable is encoded
+        variables.insert("tenant".to_string(), "a b&debug=1".to_string());
+        let mut req_header =
+            RequestHeader::build("GET", b"/v1/users", None).unwrap();
+        assert_eq!(
+            RewriteResult::Rewritten,
+            lo.rewrite_rules(&mut req_header, Some(&variables), "/v1/users")
+        );
+        assert_eq!(
+            "/users?tenant=a%20b%26debug%3D1",
+            req_header.uri.to_string()
+        );
+
+        // the condition of original uri doesn't match
+        let mut req_header =
+            RequestHeader::build("GET", b"/v1/internal/stats", None).unwrap();
+        assert_eq!(
+            RewriteResult::Rewritten,
+            lo.rewrite_rules(&mut req_header, None, "/v1/internal/stats")
+        );
+        assert_eq!("/fallback/v1/internal/stats", req_header.uri.to_string());
+
+        let mut req_header =
+            RequestHeader::build("GET", b"/search?q=abc", None).unwrap();
+        assert_eq!(
+            RewriteResult::Last,
+            lo.rewrite_rules(&mut req_header, None, "/search?q=abc")
+        );
+        assert_eq!("/search?from=web&q=abc", req_header.uri.to_string());
+
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                rewrites: Some(vec!["^/api/(.*)$ /$1".to_string()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut req_header =
+            RequestHeader::build("GET", b"/users", None).unwrap();
+        assert_eq!(
+            RewriteResult::Unchanged,
+            lo.rewrite_rules(&mut req_header, None, "/users")
+        );
+
+        let result = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                rewrites: Some(vec!["^/api/(.*)$ /$1 break abc".to_string()]),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            "Invalid error rewrite rule ^/api/(.*)$ /$1 break abc is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_captures() {
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                host: Some(r"~^(?<tenant>.+)\.example\.com$".to_string()),
+                path: Some(r"~^/(?<version>v\d+)/".to_string()),
+                rewrite: Some(
+                    "^/v\\d+/(?<path>.*)$ /$tenant/$path".to_string(),
+                ),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(true, lo.matched("pingap.example.com", "/v1/users"));
+        let variables =
+            lo.get_captures("pingap.example.com", "/v1/users").unwrap();
+        let mut keys: Vec<_> = variables.keys().cloned().collect();
+        keys.sort();
+        assert_eq!(vec!["tenant", "version"], keys);
+        assert_eq!("pingap", variables["tenant"]);
+        assert_eq!("v1", variables["version"]);
+
+        let mut req_header =
+            RequestHeader::build("GET", b"/v1/users?abc=1", None).unwrap();
+        assert_eq!(true, lo.rewrite(&mut req_header, Some(&variables)));
+        assert_eq!("/pingap/users?abc=1", req_header.uri.to_string());
+
+        assert_eq!(true, lo.get_captures("pingap.io", "/api").is_none());
+
+        // the regex without named captures
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some("charts".to_string()),
+                host: Some(r"~^(.+)\.example\.com$".to_string()),
+                path: Some(r"~^/v\d+/".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(false, lo.host_captures);
+        assert_eq!(false, lo.path_captures);
+        assert_eq!(true, lo.matched("pingap.example.com", "/v1/users"));
+        assert_eq!(
+            true,
+            lo.get_captures("pingap.example.com", "/v1/users").is_none()
+        );
+    }
+
     #[tokio::test]
     async fn test_insert_header() {
         let upstream_name = "charts";
@@ -612,6 +1761,39 @@ mod tests {
             r###"RequestHeader { base: Parts { method: GET, uri: , version: HTTP/1.1, headers: {"cache-control": "no-store", "x-user": "pingap"} }, header_name_map: None, raw_path_fallback: [], send_end_stream: true }"###,
             format!("{req_header:?}")
         );
+
+        // the empty value of context is not sent as literal text
+        let lo = Location::new(
+            "lo",
+            &LocationConf {
+                upstream: Some(upstream_name.to_string()),
+                proxy_set_headers: Some(vec![
+                    "X-Subject: :client_cert_subject".to_string(),
+                ]),
+                proxy_add_headers: Some(vec![
+                    "X-San: :client_cert_san".to_string()
+                ]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let mut req_header =
+            RequestHeader::build_no_case(Method::GET, b"", None).unwrap();
+        req_header.insert_header("X-Subject", "CN=forged").unwrap();
+        lo.set_append_proxy_headers(
+            &session,
+            &State::default(),
+            &mut req_header,
+        );
+        assert_eq!(true, req_header.headers.is_empty());
+
+        let ctx = State {
+            client_cert_subject: Some("CN=pingap".to_string()),
+            ..Default::default()
+        };
+        lo.set_append_proxy_headers(&session, &ctx, &mut req_header);
+        assert_eq!("CN=pingap", req_header.headers.get("X-Subject").unwrap());
+        assert_eq!(true, req_header.headers.get("X-San").is_none());
     }
 
     #[test]
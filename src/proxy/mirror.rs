@@ -0,0 +1,223 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::upstream::new_subrequest_peer;
+use bytes::{Bytes, BytesMut};
+use once_cell::sync::Lazy;
+use pingora::connectors::http::Connector;
+use pingora::http::RequestHeader;
+use pingora::upstreams::peer::{HttpPeer, Peer};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, error, warn};
+
+// the request body is not mirrored if it's larger than this size
+const MAX_MIRROR_BODY_SIZE: usize = 1024 * 1024;
+
+// the max time of mirror request, include reading the response body
+const MIRROR_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// the max count of mirror requests which are processing,
+// the new mirror request is dropped if it's reached
+const MAX_MIRROR_REQUESTS: usize = 1000;
+
+static MIRROR_CONNECTOR: Lazy<Connector> = Lazy::new(|| Connector::new(None));
+
+static MIRROR_PERMITS: Lazy<Arc<Semaphore>> =
+    Lazy::new(|| Arc::new(Semaphore::new(MAX_MIRROR_REQUESTS)));
+
+// the count of dropped mirror requests
+static MIRROR_DROPPED_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// The request which is copied to the mirror upstream.
+#[derive(Debug)]
+pub struct MirrorRequest {
+    pub upstream: String,
+    pub header: RequestHeader,
+    pub body: BytesMut,
+    // the body is larger than the max size
+    pub truncated: bool,
+}
+
+impl MirrorRequest {
+    pub fn new(upstream: &str, header: RequestHeader) -> Self {
+        Self {
+            upstream: upstream.to_string(),
+            header,
+            body: BytesMut::new(),
+            truncated: false,
+        }
+    }
+    /// Append the request body, the body is dropped
+    /// if it's larger than the max size.
+    pub fn append_body(&mut self, data: &[u8]) {
+        if self.truncated {
+            return;
+        }
+        if self.body.len() + data.len() > MAX_MIRROR_BODY_SIZE {
+            self.truncated = true;
+            self.body = BytesMut::new();
+            return;
+        }
+        self.body.extend_from_slice(data);
+    }
+}
+
+async fn do_mirror_request(
+    peer: &HttpPeer,
+    req: MirrorRequest,
+) -> pingora::Result<u16> {
+    let (mut session, _) = MIRROR_CONNECTOR.get_http_session(peer).await?;
+    session.write_request_header(Box::new(req.header)).await?;
+    if !req.body.is_empty() {
+        session
+            .write_request_body(Bytes::from(req.body), true)
+            .await?;
+    }
+    session.finish_request_body().await?;
+    session.read_response_header().await?;
+    let status = session
+        .response_header()
+        .map(|header| header.status.as_u16())
+        .unwrap_or_default();
+    // discard the response body
+    while session.read_response_body().await?.is_some() {}
+    MIRROR_CONNECTOR
+        .release_http_session(session, peer, peer.options.idle_timeout)
+        .await;
+    Ok(status)
+}
+
+/// Acquire the permit of mirror request, none means there are too many
+/// mirror requests and this one should be dropped.
+fn acquire_mirror_permit(
+    permits: &Arc<Semaphore>,
+    upstream: &str,
+) -> Option<OwnedSemaphorePermit> {
+    match permits.clone().try_acquire_owned() {
+        Ok(permit) => Some(permit),
+        Err(_) => {
+            let dropped =
+                MIRROR_DROPPED_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+            // avoid too many logs when the mirror upstream is slow
+            if dropped % 100 == 1 {
+                warn!(upstream, dropped, "too many mirror requests, drop it");
+            }
+            None
+        },
+    }
+}
+
+/// Send the request to the mirror upstream in background,
+/// the response is discarded.
+pub fn send_mirror_request(peer: HttpPeer, req: MirrorRequest) {
+    if req.truncated {
+        debug!(
+            upstream = req.upstream,
+            "request body is too large to mirror"
+        );
+        return;
+    }
+    let Some(permit) = acquire_mirror_permit(&MIRROR_PERMITS, &req.upstream)
+    else {
+        return;
+    };
+    let runtime = pingora_runtime::current_handle();
+    runtime.spawn(async move {
+        // the permit is released when the mirror request is done
+        let _permit = permit;
+        let now = SystemTime::now();
+        let upstream = req.upstream.clone();
+        let addr = peer.address().to_string();
+        // the mirror request should not be blocked too long
+        let result = tokio::time::timeout(
+            MIRROR_REQUEST_TIMEOUT,
+            do_mirror_request(&peer, req),
+        )
+        .await;
+        let elapsed =
+            format!("{}ms", now.elapsed().unwrap_or_default().as_millis());
+        match result {
+            Ok(Ok(status)) => {
+                debug!(upstream, addr, status, elapsed, "mirror request done");
+            },
+            Ok(Err(e)) => {
+                error!(
+                    error = e.to_string(),
+                    upstream, addr, elapsed, "mirror request fail"
+                );
+            },
+            Err(_) => {
+                error!(upstream, addr, elapsed, "mirror request timeout");
+            },
+        }
+    });
+}
+
+/// Get the http peer of mirror upstream.
+pub fn new_mirror_peer(
+    req: &MirrorRequest,
+    session: &pingora::proxy::Session,
+) -> Option<HttpPeer> {
+    new_subrequest_peer(&req.upstream, session)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        acquire_mirror_permit, MirrorRequest, MAX_MIRROR_BODY_SIZE,
+        MAX_MIRROR_REQUESTS, MIRROR_DROPPED_COUNT,
+    };
+    use pingora::http::RequestHeader;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use tokio::sync::Semaphore;
+
+    #[test]
+    fn test_mirror_request() {
+        let header = RequestHeader::build("POST", b"/users", None).unwrap();
+        let mut req = MirrorRequest::new("charts", header);
+        req.append_body(b"abc");
+        req.append_body(b"def");
+        assert_eq!(b"abcdef", req.body.as_ref());
+        assert_eq!(false, req.truncated);
+
+        req.append_body(&vec![0; MAX_MIRROR_BODY_SIZE]);
+        assert_eq!(true, req.truncated);
+        assert_eq!(0, req.body.len());
+        req.append_body(b"abc");
+        assert_eq!(0, req.body.len());
+    }
+
+    #[test]
+    fn test_acquire_mirror_permit() {
+        let semaphore = Arc::new(Semaphore::new(MAX_MIRROR_REQUESTS));
+        let dropped = MIRROR_DROPPED_COUNT.load(Ordering::Relaxed);
+        let mut permits = vec![];
+        for _ in 0..MAX_MIRROR_REQUESTS {
+            permits.push(acquire_mirror_permit(&semaphore, "charts").unwrap());
+        }
+        assert_eq!(true, acquire_mirror_permit(&semaphore, "charts").is_none());
+        assert_eq!(
+            true,
+            MIRROR_DROPPED_COUNT.load(Ordering::Relaxed) > dropped
+        );
+
+        permits.pop();
+        assert_eq!(true, acquire_mirror_permit(&semaphore, "charts").is_some());
+    }
+}
@@ -15,6 +15,8 @@
 mod dynamic_certificate;
 mod location;
 mod logger;
+mod mirror;
+mod router;
 mod server;
 mod server_conf;
 mod upstream;
@@ -22,12 +24,18 @@ mod upstream;
 // for bench
 #[allow(unused_imports)]
 pub use location::Location;
+#[allow(unused_imports)]
+pub use router::Router;
 
-pub use dynamic_certificate::{get_certificate_info_list, init_certificates};
-pub use location::try_init_locations;
+pub use dynamic_certificate::{
+    get_certificate_info_list, get_certificate_issuer_list, init_certificates,
+};
+pub use location::{try_init_locations, RequestMatcher, RewriteRule};
 pub use logger::Parser;
+pub use mirror::MirrorRequest;
 pub use server::*;
 pub use server_conf::ServerConf;
 pub use upstream::{
-    new_upstream_health_check_task, try_init_upstreams, try_update_upstreams,
+    new_subrequest_peer, new_upstream_health_check_task, try_init_upstreams,
+    try_update_upstreams,
 };
@@ -0,0 +1,293 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::config::LocationConf;
+use ahash::AHashMap;
+use regex::RegexSet;
+use std::collections::HashMap;
+use substring::Substring;
+
+/// The node of radix tree, the label of edge is stored in node.
+#[derive(Debug, Default)]
+struct RadixNode {
+    label: Vec<u8>,
+    children: Vec<RadixNode>,
+    // the locations whose prefix path ends at this node
+    indexes: Vec<usize>,
+}
+
+impl RadixNode {
+    fn insert(&mut self, path: &[u8], index: usize) {
+        if path.is_empty() {
+            self.indexes.push(index);
+            return;
+        }
+        for child in self.children.iter_mut() {
+            let common = child
+                .label
+                .iter()
+                .zip(path.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            if common == 0 {
+                continue;
+            }
+            // split the edge of child
+            if common < child.label.len() {
+                let node = RadixNode {
+                    label: child.label.split_off(common),
+                    children: std::mem::take(&mut child.children),
+                    indexes: std::mem::take(&mut child.indexes),
+                };
+                child.children.push(node);
+            }
+            child.insert(&path[common..], index);
+            return;
+        }
+        self.children.push(RadixNode {
+            label: path.to_vec(),
+            indexes: vec![index],
+            ..Default::default()
+        });
+    }
+    /// Collect the locations whose prefix path is prefix of the path.
+    fn collect(&self, path: &[u8], indexes: &mut Vec<usize>) {
+        indexes.extend_from_slice(&self.indexes);
+        for child in self.children.iter() {
+            if path.starts_with(&child.label) {
+                child.collect(&path[child.label.len()..], indexes);
+                return;
+            }
+        }
+    }
+}
+
+/// The compiled routing table of server locations, it selects the
+/// candidate locations by path and host, and the candidates are in the
+/// same order as the location weights.
+#[derive(Debug, Default)]
+pub struct Router {
+    // the location names ordered by weight
+    locations: Vec<String>,
+    // the hash keys of location configs,
+    // it's used to check whether the router is modified
+    key: String,
+    equal_paths: AHashMap<String, Vec<usize>>,
+    prefix_paths: RadixNode,
+    regex_paths: Option<(RegexSet, Vec<usize>)>,
+    // the locations without path or invalid path, which match all paths
+    any_paths: Vec<usize>,
+    equal_hosts: AHashMap<String, Vec<usize>>,
+    // the locations without host or with regex host,
+    // which should be checked by location
+    any_hosts: Vec<bool>,
+}
+
+impl Router {
+    /// Create a router from the location names which are ordered by weight.
+    pub fn new(
+        names: &[String],
+        confs: &HashMap<String, LocationConf>,
+    ) -> Self {
+        let key = names
+            .iter()
+            .map(|name| {
+                let hash_key = confs
+                    .get(name)
+                    .map(|conf| conf.hash_key())
+                    .unwrap_or_default();
+                format!("{name}:{hash_key}")
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut router = Router {
+            locations: names.to_vec(),
+            key,
+            any_hosts: vec![false; names.len()],
+            ..Default::default()
+        };
+        let mut regexes = vec![];
+        let mut regex_indexes = vec![];
+        for (index, name) in names.iter().enumerate() {
+            let Some(conf) = confs.get(name) else {
+                router.any_paths.push(index);
+                router.any_hosts[index] = true;
+                continue;
+            };
+
+            // the same rules as path selector of location
+            let path = conf.path.clone().unwrap_or_default();
+            let path = path.trim();
+            let first = path.chars().next().unwrap_or_default();
+            let last = path.substring(1, path.len()).trim();
+            match first {
+                '~' if regex::Regex::new(last).is_ok() => {
+                    regexes.push(last.to_string());
+                    regex_indexes.push(index);
+                },
+                '=' => {
+                    router
+                        .equal_paths
+                        .entry(last.to_string())
+                        .or_default()
+                        .push(index);
+                },
+                '~' => router.any_paths.push(index),
+                _ => router.prefix_paths.insert(path.as_bytes(), index),
+            }
+
+            let hosts: Vec<&str> = conf
+                .host
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .map(|item| item.trim())
+                .filter(|item| !item.is_empty())
+                .collect();
+            if hosts.is_empty()
+                || hosts.iter().any(|item| item.starts_with('~'))
+            {
+                router.any_hosts[index] = true;
+            }
+            for host in hosts.iter().filter(|item| !item.starts_with('~')) {
+                router
+                    .equal_hosts
+                    .entry(host.to_string())
+                    .or_default()
+                    .push(index);
+            }
+        }
+        if let Ok(set) = RegexSet::new(&regexes) {
+            router.regex_paths = Some((set, regex_indexes));
+        } else {
+            router.any_paths.extend(regex_indexes);
+        }
+        router
+    }
+    /// Get the key of router, it changes when the location list
+    /// or any location config is modified.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    /// Get the candidate locations of host and path ordered by weight,
+    /// they should be matched by location again.
+    pub fn candidates(&self, host: &str, path: &str) -> Vec<&str> {
+        let mut indexes = self.any_paths.clone();
+        if let Some(values) = self.equal_paths.get(path) {
+            indexes.extend_from_slice(values);
+        }
+        self.prefix_paths.collect(path.as_bytes(), &mut indexes);
+        if let Some((set, regex_indexes)) = &self.regex_paths {
+            for i in set.matches(path).iter() {
+                indexes.push(regex_indexes[i]);
+            }
+        }
+        indexes.sort_unstable();
+        let equal_hosts = self.equal_hosts.get(host);
+        indexes
+            .into_iter()
+            .filter(|index| {
+                self.any_hosts[*index]
+                    || equal_hosts
+                        .map(|values| values.contains(index))
+                        .unwrap_or_default()
+            })
+            .map(|index| self.locations[index].as_str())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Router;
+    use crate::config::LocationConf;
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_router() {
+        let mut confs = HashMap::new();
+        for (name, path, host) in [
+            ("equal", "=/api", ""),
+            ("api", "/api", ""),
+            ("users", "/api/users", ""),
+            ("upload", "/api/upload", "pingap.io"),
+            ("regex", "~^/api/.+/me$", ""),
+            ("regex_host", "/", "~pingap"),
+            ("root", "", ""),
+            ("invalid", "~(", ""),
+        ] {
+            confs.insert(
+                name.to_string(),
+                LocationConf {
+                    path: Some(path.to_string()),
+                    host: Some(host.to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+        let names: Vec<String> = [
+            "equal",
+            "upload",
+            "users",
+            "api",
+            "regex",
+            "regex_host",
+            "root",
+            "invalid",
+            "not_found",
+        ]
+        .iter()
+        .map(|item| item.to_string())
+        .collect();
+        let router = Router::new(&names, &confs);
+        assert_eq!(names, router.locations);
+
+        assert_eq!(
+            vec!["equal", "api", "regex_host", "root", "invalid", "not_found"],
+            router.candidates("", "/api")
+        );
+        assert_eq!(
+            vec![
+                "users",
+                "api",
+                "regex",
+                "regex_host",
+                "root",
+                "invalid",
+                "not_found"
+            ],
+            router.candidates("", "/api/users/me")
+        );
+        assert_eq!(
+            vec!["api", "regex_host", "root", "invalid", "not_found"],
+            router.candidates("", "/api/upload")
+        );
+        assert_eq!(
+            vec![
+                "upload",
+                "api",
+                "regex_host",
+                "root",
+                "invalid",
+                "not_found"
+            ],
+            router.candidates("pingap.io", "/api/upload")
+        );
+        assert_eq!(
+            vec!["root", "invalid", "not_found"],
+            router.candidates("", "rest")
+        );
+    }
+}
@@ -12,19 +12,28 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::dynamic_certificate::DynamicCertificate;
+use super::dynamic_certificate::{
+    cache_client_certificate, get_client_certificate, ClientCertificate,
+    DynamicCertificate,
+};
 use super::logger::Parser;
+use super::mirror::{new_mirror_peer, send_mirror_request, MirrorRequest};
 use super::upstream::get_upstream;
 use super::ServerConf;
 use crate::acme::handle_lets_encrypt;
 use crate::config;
 use crate::config::PluginStep;
-use crate::http_extra::{HttpResponse, HTTP_HEADER_NAME_X_REQUEST_ID};
+use crate::http_extra::{
+    HttpResponse, HTTP_HEADER_NAME_X_CLIENT_CERT_FINGERPRINT,
+    HTTP_HEADER_NAME_X_CLIENT_CERT_SAN, HTTP_HEADER_NAME_X_CLIENT_CERT_SUBJECT,
+    HTTP_HEADER_NAME_X_REQUEST_ID,
+};
 #[cfg(feature = "full")]
 use crate::otel;
 use crate::plugin::{get_plugin, ADMIN_SERVER_PLUGIN};
 use crate::proxy::dynamic_certificate::TlsSettingParams;
-use crate::proxy::location::get_location;
+use crate::proxy::location::{get_location, Location, RewriteResult};
+use crate::proxy::router::Router;
 use crate::service::CommonServiceTask;
 #[cfg(feature = "full")]
 use crate::state::OtelTracer;
@@ -37,7 +46,7 @@ use ahash::AHashMap;
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
-use http::StatusCode;
+use http::{HeaderValue, StatusCode};
 use once_cell::sync::Lazy;
 #[cfg(feature = "full")]
 use opentelemetry::{
@@ -51,9 +60,9 @@ use pingora::apps::HttpServerOptions;
 use pingora::cache::cache_control::CacheControl;
 use pingora::cache::cache_control::DirectiveValue;
 use pingora::cache::cache_control::InterpretCacheControl;
-use pingora::cache::filters::resp_cacheable;
+use pingora::cache::filters::{calculate_serve_stale_sec, resp_cacheable};
 use pingora::cache::{
-    CacheKey, CacheMetaDefaults, NoCacheReason, RespCacheable,
+    CacheKey, CacheMeta, CacheMetaDefaults, NoCacheReason, RespCacheable,
 };
 use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::listeners::TcpSocketOptions;
@@ -66,6 +75,7 @@ use pingora::proxy::{ProxyHttp, Session};
 use pingora::server::configuration;
 use pingora::services::listening::Service;
 use pingora::upstreams::peer::{HttpPeer, Peer};
+use pingora::ErrorSource;
 use snafu::Snafu;
 use std::collections::HashMap;
 use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
@@ -80,23 +90,27 @@ pub enum Error {
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
-type ServerLocations = AHashMap<String, Arc<Vec<String>>>;
+type ServerLocations = AHashMap<String, Arc<Router>>;
 static LOCATION_MAP: Lazy<ArcSwap<ServerLocations>> =
     Lazy::new(|| ArcSwap::from_pointee(AHashMap::new()));
 
-/// Try to init the locations of server,
-/// the locations are order by weight
-pub fn try_init_server_locations(
+/// Create the routers of servers, the locations are order by weight
+/// and compiled to router.
+fn new_server_routers(
     servers: &HashMap<String, config::ServerConf>,
     locations: &HashMap<String, config::LocationConf>,
-) -> Result<Vec<String>> {
+) -> ServerLocations {
     // get the location weight
     let mut location_weights = HashMap::new();
     for (name, item) in locations.iter() {
-        location_weights.insert(name.to_string(), item.get_weight());
+        // the location with match conditions has priority
+        // if the weights are equal
+        location_weights.insert(
+            name.to_string(),
+            (item.get_weight(), item.has_conditions()),
+        );
     }
     let mut server_locations = AHashMap::new();
-    let mut updated_servers = vec![];
     for (name, server) in servers.iter() {
         if let Some(items) = &server.locations {
             let mut items = items.clone();
@@ -108,17 +122,29 @@ pub fn try_init_server_locations(
                     .unwrap_or_default();
                 std::cmp::Reverse(weight)
             });
-            let mut not_modified = false;
-            if let Some(current_locations) = get_server_locations(name) {
-                if current_locations.join(",") == items.join(",") {
-                    not_modified = true;
-                }
-            }
-            if !not_modified {
-                updated_servers.push(name.to_string());
-            }
+            server_locations.insert(
+                name.to_string(),
+                Arc::new(Router::new(&items, locations)),
+            );
+        }
+    }
+    server_locations
+}
 
-            server_locations.insert(name.to_string(), Arc::new(items));
+/// Try to init the locations of server, the routers are always rebuilt
+/// because the path or host of location may be modified.
+pub fn try_init_server_locations(
+    servers: &HashMap<String, config::ServerConf>,
+    locations: &HashMap<String, config::LocationConf>,
+) -> Result<Vec<String>> {
+    let server_locations = new_server_routers(servers, locations);
+    let mut updated_servers = vec![];
+    for (name, router) in server_locations.iter() {
+        let modified = get_server_locations(name)
+            .map(|current| current.key() != router.key())
+            .unwrap_or(true);
+        if modified {
+            updated_servers.push(name.to_string());
         }
     }
     LOCATION_MAP.store(Arc::new(server_locations));
@@ -126,10 +152,51 @@ pub fn try_init_server_locations(
 }
 
 #[inline]
-fn get_server_locations(name: &str) -> Option<Arc<Vec<String>>> {
+fn get_server_locations(name: &str) -> Option<Arc<Router>> {
     LOCATION_MAP.load().get(name).cloned()
 }
 
+// the matched location and the named captures of host and path
+type MatchedLocation = (Arc<Location>, Option<AHashMap<String, String>>);
+
+/// Switch the request to the location matched after a `last` rewrite,
+/// the early request plugins of it are run as they are skipped before.
+/// Returns true if the request is done by the plugins.
+async fn switch_location(
+    session: &mut Session,
+    ctx: &mut State,
+    current: &Location,
+    next: Arc<Location>,
+    variables: Option<AHashMap<String, String>>,
+) -> pingora::Result<bool> {
+    current.processing.fetch_sub(1, Ordering::Relaxed);
+    ctx.location_accepted = next.accepted.fetch_add(1, Ordering::Relaxed) + 1;
+    ctx.location_processing =
+        next.processing.fetch_add(1, Ordering::Relaxed) + 1;
+    ctx.variables = variables;
+    ctx.location = Some(next.clone());
+    next.handle_request_plugin(PluginStep::EarlyRequest, session, ctx)
+        .await
+}
+
+/// Find the first matched location of server for the request.
+fn find_location(name: &str, session: &Session) -> Option<MatchedLocation> {
+    let locations = get_server_locations(name)?;
+    let header = session.req_header();
+    let host = util::get_host(header).unwrap_or_default();
+    let path = header.uri.path();
+    for name in locations.candidates(host, path) {
+        let Some(location) = get_location(name) else {
+            continue;
+        };
+        if location.matched(host, path) && location.matched_request(session) {
+            let variables = location.get_captures(host, path);
+            return Some((location, variables));
+        }
+    }
+    None
+}
+
 pub struct Server {
     name: String,
     admin: bool,
@@ -143,6 +210,9 @@ pub struct Server {
     tls_ciphersuites: Option<String>,
     tls_min_version: Option<String>,
     tls_max_version: Option<String>,
+    tls_client_ca: Option<String>,
+    tls_client_auth: Option<String>,
+    tls_client_crl: Option<String>,
     enabled_h2: bool,
     lets_encrypt_enabled: bool,
     global_certificates: bool,
@@ -160,8 +230,10 @@ pub struct ServerServices {
     pub lb: Service<HttpProxy<Server>>,
 }
 
+// the stale response is served only if it's allowed by
+// the cache-control of upstream or the cache plugin
 const META_DEFAULTS: CacheMetaDefaults =
-    CacheMetaDefaults::new(|_| Some(1), 1, 1);
+    CacheMetaDefaults::new(|_| Some(1), 0, 0);
 
 static HTTP_500_RESPONSE: Lazy<ResponseHeader> =
     Lazy::new(|| error_resp::gen_error_response(500));
@@ -207,6 +279,9 @@ impl Server {
             tls_ciphersuites: conf.tls_ciphersuites.clone(),
             tls_min_version: conf.tls_min_version.clone(),
             tls_max_version: conf.tls_max_version.clone(),
+            tls_client_ca: conf.tls_client_ca.clone(),
+            tls_client_auth: conf.tls_client_auth.clone(),
+            tls_client_crl: conf.tls_client_crl.clone(),
             threads: conf.threads,
             lets_encrypt_enabled: false,
             global_certificates: conf.global_certificates,
@@ -298,6 +373,9 @@ impl Server {
         let ciphersuites = self.tls_ciphersuites.clone();
         let tls_min_version = self.tls_min_version.clone();
         let tls_max_version = self.tls_max_version.clone();
+        let client_ca = self.tls_client_ca.clone();
+        let client_auth = self.tls_client_auth.clone();
+        let client_crl = self.tls_client_crl.clone();
         let mut lb = http_proxy_service(conf, self);
         // use h2c if not tls and enable http2
         if !is_tls && enabled_h2 {
@@ -320,6 +398,9 @@ impl Server {
                         ciphersuites: ciphersuites.clone(),
                         tls_min_version: tls_min_version.clone(),
                         tls_max_version: tls_max_version.clone(),
+                        client_ca: client_ca.clone(),
+                        client_auth: client_auth.clone(),
+                        client_crl: client_crl.clone(),
                     })
                     .map_err(|e| Error::Common {
                         category: "tls".to_string(),
@@ -369,6 +450,31 @@ struct DigestDeailt {
     tls_established: u64,
     tls_version: Option<String>,
     tls_cipher: Option<String>,
+    client_certificate: Option<Arc<ClientCertificate>>,
+}
+
+/// Forward the identity of verified client certificate to upstream,
+/// the same headers sent by client are always removed.
+#[inline]
+fn set_client_cert_headers(ctx: &State, header: &mut RequestHeader) {
+    for (name, value) in [
+        (
+            &*HTTP_HEADER_NAME_X_CLIENT_CERT_SUBJECT,
+            &ctx.client_cert_subject,
+        ),
+        (&*HTTP_HEADER_NAME_X_CLIENT_CERT_SAN, &ctx.client_cert_san),
+        (
+            &*HTTP_HEADER_NAME_X_CLIENT_CERT_FINGERPRINT,
+            &ctx.client_cert_fingerprint,
+        ),
+    ] {
+        header.remove_header(name);
+        if let Some(value) =
+            value.as_ref().and_then(|v| HeaderValue::from_str(v).ok())
+        {
+            let _ = header.insert_header(name.clone(), value);
+        }
+    }
 }
 
 #[inline]
@@ -411,6 +517,7 @@ fn get_digest_detail(digest: &Digest) -> DigestDeailt {
         tls_established: get_established(digest.timing_digest.get(1)),
         tls_version: Some(ssl_digest.version.to_string()),
         tls_cipher: Some(ssl_digest.cipher.to_string()),
+        client_certificate: get_client_certificate(&ssl_digest.cert_digest),
     }
 }
 
@@ -448,6 +555,27 @@ impl ProxyHttp for Server {
             }
             ctx.tls_cipher = digest_detail.tls_cipher;
             ctx.tls_version = digest_detail.tls_version;
+            // the identity may be evicted from cache or not be set
+            // for the resumed session, so get it from tls connection
+            let has_client_cert = digest
+                .ssl_digest
+                .as_ref()
+                .is_some_and(|item| !item.cert_digest.is_empty());
+            let client_certificate =
+                digest_detail.client_certificate.or_else(|| {
+                    if !has_client_cert {
+                        return None;
+                    }
+                    session
+                        .stream()
+                        .and_then(|stream| stream.get_ssl())
+                        .and_then(cache_client_certificate)
+                });
+            if let Some(cert) = client_certificate {
+                ctx.client_cert_subject = Some(cert.subject.clone());
+                ctx.client_cert_san = Some(cert.san.clone());
+                ctx.client_cert_fingerprint = Some(cert.fingerprint.clone());
+            }
         };
         accept_request();
 
@@ -465,15 +593,13 @@ impl ProxyHttp for Server {
             ctx.server_port = Some(addr.port());
         }
 
-        let header = session.req_header_mut();
-        let host = util::get_host(header).unwrap_or_default();
-        let path = header.uri.path();
-
         // enable open telemtery
 
         #[cfg(feature = "full")]
         if self.enabled_otel {
             if let Some(tracer) = otel::new_tracer(&self.name) {
+                let header = session.req_header();
+                let path = header.uri.path();
                 let cx = global::get_text_map_propagator(|propagator| {
                     propagator.extract(&HeaderExtractor(&header.headers))
                 });
@@ -495,19 +621,10 @@ impl ProxyHttp for Server {
             prom.before();
         }
 
-        // locations not found
-        let Some(locations) = get_server_locations(&self.name) else {
-            return Ok(());
-        };
-
-        for name in locations.iter() {
-            let Some(location) = get_location(name) else {
-                continue;
-            };
-            if location.matched(host, path) {
-                ctx.location = Some(location);
-                break;
-            }
+        if let Some((location, variables)) = find_location(&self.name, session)
+        {
+            ctx.variables = variables;
+            ctx.location = Some(location);
         }
         if let Some(location) = &ctx.location {
             ctx.location_accepted =
@@ -569,7 +686,69 @@ impl ProxyHttp for Server {
         };
 
         debug!(name = location.name, "location is matched");
-        location.rewrite(header);
+        let original_uri = header.uri.to_string();
+        // the location is searched again when the rule with `last` flag
+        // is matched, the max count is ten as nginx
+        let mut last_count = 0;
+        loop {
+            let Some(location) = ctx.location.clone() else {
+                break;
+            };
+            let header = session.req_header_mut();
+            location.rewrite(header, ctx.variables.as_ref());
+            match location.rewrite_rules(
+                header,
+                ctx.variables.as_ref(),
+                &original_uri,
+            ) {
+                RewriteResult::Redirect(status, uri) => {
+                    let value = HeaderValue::from_str(&uri).map_err(|e| {
+                        util::new_internal_error(
+                            500,
+                            format!("redirect location {uri} is invalid, {e}"),
+                        )
+                    })?;
+                    HttpResponse {
+                        status,
+                        headers: Some(vec![(http::header::LOCATION, value)]),
+                        ..Default::default()
+                    }
+                    .send(session)
+                    .await?;
+                    return Ok(true);
+                },
+                RewriteResult::Last => {
+                    last_count += 1;
+                    if last_count >= 10 {
+                        return Err(util::new_internal_error(
+                            500,
+                            format!(
+                                "rewrite or internal redirection cycle while processing {original_uri}"
+                            ),
+                        ));
+                    }
+                    let Some((next, variables)) =
+                        find_location(&self.name, session)
+                    else {
+                        break;
+                    };
+                    debug!(
+                        name = next.name,
+                        "location is matched after rewrite"
+                    );
+                    if switch_location(session, ctx, &location, next, variables)
+                        .await?
+                    {
+                        return Ok(true);
+                    }
+                },
+                _ => break,
+            }
+        }
+        let Some(location) = &ctx.location else {
+            return Ok(false);
+        };
+        let header = session.req_header();
 
         // body limit
         location.client_body_size_limit(Some(header), ctx)?;
@@ -614,11 +793,17 @@ impl ProxyHttp for Server {
         let mut location_name = "unknown".to_string();
         let peer = if let Some(location) = &ctx.location {
             location_name.clone_from(&location.name);
-            if let Some(up) = get_upstream(&location.upstream) {
+            // the upstream is selected only once, retry uses the same one
+            if ctx.upstream_name.is_empty() {
+                ctx.upstream_name = location
+                    .get_upstream_name(session.req_header())
+                    .to_string();
+            }
+            if let Some(up) = get_upstream(&ctx.upstream_name) {
                 ctx.upstream_connected = up.connected();
                 #[cfg(feature = "full")]
                 if let Some(tracer) = &ctx.otel_tracer {
-                    let name = format!("upstream.{}", &location.upstream);
+                    let name = format!("upstream.{}", &ctx.upstream_name);
                     let mut span = tracer.new_upstream_span(&name);
                     span.set_attribute(KeyValue::new(
                         "upstream.connected",
@@ -640,6 +825,7 @@ impl ProxyHttp for Server {
             )
         })?;
 
+        ctx.upstream_backend = Some(peer.address().to_string());
         ctx.upstream_connect_time =
             util::get_latency(&ctx.upstream_connect_time);
 
@@ -697,6 +883,16 @@ impl ProxyHttp for Server {
         if let Some(location) = &ctx.location {
             location.set_append_proxy_headers(session, ctx, upstream_response);
         }
+        set_client_cert_headers(ctx, upstream_response);
+        if let Some(location) = &ctx.location {
+            // the upstream request filter is called again when retry
+            if ctx.upstream_retries == 0 {
+                ctx.mirror_request =
+                    location.get_mirror_upstream().map(|upstream| {
+                        MirrorRequest::new(upstream, upstream_response.clone())
+                    });
+            }
+        }
         Ok(())
     }
     async fn request_body_filter(
@@ -714,6 +910,9 @@ impl ProxyHttp for Server {
             if let Some(location) = &ctx.location {
                 location.client_body_size_limit(None, ctx)?;
             }
+            if let Some(mirror_request) = ctx.mirror_request.as_mut() {
+                mirror_request.append_body(buf);
+            }
         }
         Ok(())
     }
@@ -745,6 +944,16 @@ impl ProxyHttp for Server {
             ));
         }
         let mut cc = CacheControl::from_resp_headers(resp);
+        // calculate before the s-maxage is updated,
+        // because s-maxage disallows serving stale
+        let (mut stale_while_revalidate, mut stale_if_error) =
+            calculate_serve_stale_sec(cc.as_ref(), &META_DEFAULTS);
+        if let Some(d) = ctx.cache_stale_while_revalidate {
+            stale_while_revalidate = d.as_secs() as u32;
+        }
+        if let Some(d) = ctx.cache_stale_if_error {
+            stale_if_error = d.as_secs() as u32;
+        }
         if let Some(ref mut c) = &mut cc {
             if c.no_cache() || c.no_store() || c.private() {
                 return Ok(RespCacheable::Uncacheable(
@@ -776,12 +985,38 @@ impl ProxyHttp for Server {
             }
         }
 
-        Ok(resp_cacheable(
-            cc.as_ref(),
-            resp.clone(),
-            false,
-            &META_DEFAULTS,
-        ))
+        let cacheable =
+            resp_cacheable(cc.as_ref(), resp.clone(), false, &META_DEFAULTS);
+        let RespCacheable::Cacheable(meta) = cacheable else {
+            return Ok(cacheable);
+        };
+        if meta.stale_while_revalidate_sec() == stale_while_revalidate
+            && meta.stale_if_error_sec() == stale_if_error
+        {
+            return Ok(RespCacheable::Cacheable(meta));
+        }
+        Ok(RespCacheable::Cacheable(CacheMeta::new(
+            meta.fresh_until(),
+            meta.created(),
+            stale_while_revalidate,
+            stale_if_error,
+            meta.response_header_copy(),
+        )))
+    }
+
+    fn should_serve_stale(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Self::CTX,
+        error: Option<&pingora::Error>,
+    ) -> bool {
+        // it's called during stale while revalidate,
+        // which has been allowed by the cache meta
+        let Some(e) = error else {
+            return true;
+        };
+        // serve stale if the upstream is down or responds 5xx
+        e.esource() == &ErrorSource::Upstream
     }
 
     async fn response_filter(
@@ -793,6 +1028,29 @@ impl ProxyHttp for Server {
     where
         Self::CTX: Send + Sync,
     {
+        // the response is not sent to downstream,
+        // so the request can be retried with the next backend
+        if let Some(status) = ctx.upstream_retry_status.take() {
+            if let Some(addr) = ctx.upstream_backend.take() {
+                if let Some(up) = get_upstream(&ctx.upstream_name) {
+                    up.release_backend(
+                        &addr,
+                        ctx.get_upstream_processing_time(),
+                    );
+                }
+            }
+            ctx.upstream_retries += 1;
+            ctx.upstream_tried_addrs.push(ctx.upstream_address.clone());
+            // reset the latency for the next attempt
+            ctx.upstream_connect_time = None;
+            ctx.upstream_processing_time = None;
+            let mut e = util::new_internal_error(
+                status,
+                format!("Upstream response status is {status}"),
+            );
+            e.set_retry(true);
+            return Err(e);
+        }
         if session.cache.enabled() {
             // ignore insert header error
             let _ = upstream_response.insert_header(
@@ -830,10 +1088,27 @@ impl ProxyHttp for Server {
 
     fn upstream_response_filter(
         &self,
-        _session: &mut Session,
+        session: &mut Session,
         upstream_response: &mut ResponseHeader,
         ctx: &mut Self::CTX,
     ) {
+        ctx.upstream_processing_time =
+            util::get_latency(&ctx.upstream_processing_time);
+        if let Some(up) = get_upstream(&ctx.upstream_name) {
+            let status = upstream_response.status.as_u16();
+            up.observe_backend(&ctx.upstream_address, status < 500);
+            // the request body should be buffered completely for retry
+            if !session.as_ref().retry_buffer_truncated()
+                && up.should_retry_status(
+                    &session.req_header().method,
+                    status,
+                    ctx,
+                )
+            {
+                ctx.upstream_retry_status = Some(status);
+                return;
+            }
+        }
         if ctx.status.is_none() {
             ctx.status = Some(upstream_response.status);
             ctx.upstream_response_time =
@@ -843,8 +1118,6 @@ impl ProxyHttp for Server {
             let _ = upstream_response
                 .insert_header(HTTP_HEADER_NAME_X_REQUEST_ID.clone(), id);
         }
-        ctx.upstream_processing_time =
-            util::get_latency(&ctx.upstream_processing_time);
     }
 
     fn upstream_response_body_filter(
@@ -929,6 +1202,29 @@ impl ProxyHttp for Server {
         Ok(None)
     }
 
+    fn fail_to_connect(
+        &self,
+        _session: &mut Session,
+        peer: &HttpPeer,
+        ctx: &mut Self::CTX,
+        mut e: Box<pingora::Error>,
+    ) -> Box<pingora::Error> {
+        if let Some(up) = get_upstream(&ctx.upstream_name) {
+            if let Some(addr) = ctx.upstream_backend.take() {
+                up.release_backend(&addr, None);
+            }
+            up.observe_backend(&peer.address().to_string(), false);
+            if up.should_retry_connect(ctx, &e) {
+                ctx.upstream_retries += 1;
+                ctx.upstream_tried_addrs.push(peer.address().to_string());
+                // reset the latency for the next attempt
+                ctx.upstream_connect_time = None;
+                e.set_retry(true);
+            }
+        }
+        e
+    }
+
     async fn fail_to_proxy(
         &self,
         session: &mut Session,
@@ -1013,7 +1309,7 @@ impl ProxyHttp for Server {
     async fn logging(
         &self,
         session: &mut Session,
-        _e: Option<&pingora::Error>,
+        e: Option<&pingora::Error>,
         ctx: &mut Self::CTX,
     ) where
         Self::CTX: Send + Sync,
@@ -1022,8 +1318,23 @@ impl ProxyHttp for Server {
         self.processing.fetch_sub(1, Ordering::Relaxed);
         if let Some(location) = &ctx.location {
             location.processing.fetch_sub(1, Ordering::Relaxed);
-            if let Some(up) = get_upstream(&location.upstream) {
+            if let Some(up) = get_upstream(&ctx.upstream_name) {
                 ctx.upstream_processing = Some(up.completed());
+                if let Some(addr) = ctx.upstream_backend.take() {
+                    let latency =
+                        ctx.get_upstream_processing_time().map(|value| {
+                            value
+                                + ctx
+                                    .get_upstream_response_time()
+                                    .unwrap_or_default()
+                        });
+                    up.release_backend(&addr, latency);
+                    // proxy to upstream fail, e.g. read timeout
+                    if e.is_some_and(|e| e.esource() == &ErrorSource::Upstream)
+                    {
+                        up.observe_backend(&addr, false);
+                    }
+                }
             }
         }
         if ctx.status.is_none() {
@@ -1031,6 +1342,15 @@ impl ProxyHttp for Server {
                 ctx.status = Some(header.status);
             }
         }
+        // mirror the request after it's done,
+        // so the latency of client is not affected
+        if let Some(mirror_request) = ctx.mirror_request.take() {
+            if session.as_mut().is_body_done() {
+                if let Some(peer) = new_mirror_peer(&mirror_request, session) {
+                    send_mirror_request(peer, mirror_request);
+                }
+            }
+        }
         #[cfg(feature = "full")]
         // enable open telemetry and proxy upstream fail
         if let Some(ref mut span) = ctx.upstream_span.as_mut() {
@@ -1088,23 +1408,63 @@ impl ProxyHttp for Server {
 #[cfg(test)]
 mod tests {
     use super::Server;
-    use crate::config::{LocationConf, PingapConf};
-    use crate::proxy::server::get_digest_detail;
+    use crate::config::{self, LocationConf, PingapConf};
+    use crate::plugin::initialize_test_plugins;
+    use crate::proxy::server::{
+        get_digest_detail, new_server_routers, set_client_cert_headers,
+        switch_location,
+    };
     use crate::proxy::{
         try_init_locations, try_init_server_locations, try_init_upstreams,
         Location, ServerConf,
     };
     use crate::state::State;
-    use pingora::http::ResponseHeader;
+    use pingora::cache::RespCacheable;
+    use pingora::http::{RequestHeader, ResponseHeader};
+    use pingora::modules::http::compression::{
+        ResponseCompression, ResponseCompressionBuilder,
+    };
+    use pingora::modules::http::HttpModules;
     use pingora::protocols::{Digest, TimingDigest};
     use pingora::proxy::{ProxyHttp, Session};
     use pingora::server::configuration;
     use pingora::services::Service;
     use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
     use std::sync::Arc;
     use std::time::{Duration, SystemTime};
     use tokio_test::io::Builder;
 
+    #[test]
+    fn test_set_client_cert_headers() {
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        header
+            .insert_header("X-Client-Cert-Subject", "CN=forged")
+            .unwrap();
+        header
+            .insert_header("X-Client-Cert-San", "DNS:forged")
+            .unwrap();
+        set_client_cert_headers(&State::default(), &mut header);
+        assert_eq!(true, header.headers.get("X-Client-Cert-Subject").is_none());
+        assert_eq!(true, header.headers.get("X-Client-Cert-San").is_none());
+
+        let ctx = State {
+            client_cert_subject: Some("CN=pingap".to_string()),
+            client_cert_fingerprint: Some("abcd".to_string()),
+            ..Default::default()
+        };
+        set_client_cert_headers(&ctx, &mut header);
+        assert_eq!(
+            "CN=pingap",
+            header.headers.get("X-Client-Cert-Subject").unwrap()
+        );
+        assert_eq!(true, header.headers.get("X-Client-Cert-San").is_none());
+        assert_eq!(
+            "abcd",
+            header.headers.get("X-Client-Cert-Fingerprint").unwrap()
+        );
+    }
+
     #[test]
     fn test_get_digest_detail() {
         let digest = Digest {
@@ -1120,6 +1480,40 @@ mod tests {
         assert_eq!(10000, result.tcp_established);
     }
 
+    #[test]
+    fn test_new_server_routers() {
+        let mut servers = HashMap::new();
+        servers.insert(
+            "test".to_string(),
+            config::ServerConf {
+                locations: Some(vec!["lo".to_string(), "root".to_string()]),
+                ..Default::default()
+            },
+        );
+        let mut locations = HashMap::new();
+        for (name, path) in [("lo", "/api"), ("root", "/")] {
+            locations.insert(
+                name.to_string(),
+                LocationConf {
+                    path: Some(path.to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+        let routers = new_server_routers(&servers, &locations);
+        let router = routers.get("test").unwrap();
+        assert_eq!(vec!["lo", "root"], router.candidates("", "/api/users"));
+        assert_eq!(vec!["root"], router.candidates("", "/v1/users"));
+
+        // the path of location is modified
+        locations.get_mut("lo").unwrap().path = Some("/v1".to_string());
+        let new_routers = new_server_routers(&servers, &locations);
+        let new_router = new_routers.get("test").unwrap();
+        assert_ne!(router.key(), new_router.key());
+        assert_eq!(vec!["lo", "root"], new_router.candidates("", "/v1/users"));
+        assert_eq!(vec!["root"], new_router.candidates("", "/api/users"));
+    }
+
     fn new_server() -> Server {
         let toml_data = include_bytes!("../../conf/pingap.toml");
         let pingap_conf = PingapConf::new(toml_data.as_ref(), false).unwrap();
@@ -1181,6 +1575,60 @@ mod tests {
         assert_eq!(false, done);
     }
 
+    #[tokio::test]
+    async fn test_switch_location() {
+        initialize_test_plugins();
+        let current = Location::new("lo", &LocationConf::default()).unwrap();
+        current
+            .processing
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let next = Arc::new(
+            Location::new(
+                "compression",
+                &LocationConf {
+                    plugins: Some(vec!["pingap:compression".to_string()]),
+                    ..Default::default()
+                },
+            )
+            .unwrap(),
+        );
+
+        let headers = ["Accept-Encoding: gzip"].join("\r\n");
+        let input_header =
+            format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut modules = HttpModules::new();
+        modules.add_module(ResponseCompressionBuilder::enable(0));
+        let mut session =
+            Session::new_h1_with_modules(Box::new(mock_io), &modules);
+        session.read_request().await.unwrap();
+
+        let mut ctx = State::default();
+        let done =
+            switch_location(&mut session, &mut ctx, &current, next, None)
+                .await
+                .unwrap();
+        assert_eq!(false, done);
+        assert_eq!("compression", ctx.location.unwrap().name);
+        assert_eq!(1, ctx.location_accepted);
+        assert_eq!(1, ctx.location_processing);
+        assert_eq!(
+            0,
+            current
+                .processing
+                .load(std::sync::atomic::Ordering::Relaxed)
+        );
+        // the early request plugin of the new location is run
+        assert_eq!(
+            true,
+            session
+                .downstream_modules_ctx
+                .get::<ResponseCompression>()
+                .unwrap()
+                .is_enabled()
+        );
+    }
+
     #[tokio::test]
     async fn test_cache_key_callback() {
         let server = new_server();
@@ -1283,5 +1731,68 @@ mod tests {
             )
             .unwrap();
         assert_eq!(false, result.is_cacheable());
+
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        upstream_response
+            .append_header(
+                "Cache-Control",
+                "max-age=100, stale-while-revalidate=10, stale-if-error=60",
+            )
+            .unwrap();
+        // the stale directives are kept after max ttl is adjusted
+        let result = server
+            .response_cache_filter(
+                &session,
+                &upstream_response,
+                &mut State {
+                    cache_max_ttl: Some(Duration::from_secs(30)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = result else {
+            panic!("response should be cacheable");
+        };
+        assert_eq!(30, meta.fresh_sec());
+        assert_eq!(10, meta.stale_while_revalidate_sec());
+        assert_eq!(60, meta.stale_if_error_sec());
+
+        // the stale settings of cache plugin override cache-control
+        let result = server
+            .response_cache_filter(
+                &session,
+                &upstream_response,
+                &mut State {
+                    cache_stale_while_revalidate: Some(Duration::from_secs(5)),
+                    cache_stale_if_error: Some(Duration::from_secs(3600)),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = result else {
+            panic!("response should be cacheable");
+        };
+        assert_eq!(100, meta.fresh_sec());
+        assert_eq!(5, meta.stale_while_revalidate_sec());
+        assert_eq!(3600, meta.stale_if_error_sec());
+
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        upstream_response
+            .append_header("Cache-Control", "max-age=100")
+            .unwrap();
+        let result = server
+            .response_cache_filter(
+                &session,
+                &upstream_response,
+                &mut State::default(),
+            )
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = result else {
+            panic!("response should be cacheable");
+        };
+        assert_eq!(0, meta.stale_while_revalidate_sec());
+        assert_eq!(0, meta.stale_if_error_sec());
     }
 }
@@ -29,6 +29,9 @@ pub struct ServerConf {
     pub tls_ciphersuites: Option<String>,
     pub tls_min_version: Option<String>,
     pub tls_max_version: Option<String>,
+    pub tls_client_ca: Option<String>,
+    pub tls_client_auth: Option<String>,
+    pub tls_client_crl: Option<String>,
     pub threads: Option<usize>,
     pub error_template: String,
     pub tcp_keepalive: Option<TcpKeepalive>,
@@ -63,7 +66,9 @@ impl From<PingapConf> for Vec<ServerConf> {
             locations.push((name, item));
         }
         // sort location by weight
-        locations.sort_by_key(|b| std::cmp::Reverse(b.1.get_weight()));
+        locations.sort_by_key(|b| {
+            std::cmp::Reverse((b.1.get_weight(), b.1.has_conditions()))
+        });
         let mut servers = vec![];
         for (name, item) in conf.servers {
             // load config validate base64
@@ -95,6 +100,9 @@ impl From<PingapConf> for Vec<ServerConf> {
                 tls_ciphersuites: item.tls_ciphersuites.clone(),
                 tls_min_version: item.tls_min_version.clone(),
                 tls_max_version: item.tls_max_version.clone(),
+                tls_client_ca: item.tls_client_ca.clone(),
+                tls_client_auth: item.tls_client_auth.clone(),
+                tls_client_crl: item.tls_client_crl.clone(),
                 addr: item.addr,
                 access_log: item.access_log,
                 locations: item.locations.unwrap_or_default(),
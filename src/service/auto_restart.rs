@@ -92,7 +92,12 @@ async fn diff_and_update_config(
 
         for category in updated_category_list {
             match category.as_str() {
-                CATEGORY_LOCATION => should_reload_location = true,
+                CATEGORY_LOCATION => {
+                    should_reload_location = true;
+                    // the router of server is compiled from
+                    // the path and host of locations
+                    should_reload_server_location = true;
+                },
                 CATEGORY_UPSTREAM => should_reload_upstream = true,
                 CATEGORY_PLUGIN => should_reload_plugin = true,
                 CATEGORY_CERTIFICATE => {
@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::http_extra::HttpHeader;
+use crate::proxy::{Location, MirrorRequest};
+use crate::util;
 use crate::util::format_duration;
-use crate::{proxy::Location, util};
+use ahash::AHashMap;
 use bytes::{Bytes, BytesMut};
 use http::StatusCode;
 #[cfg(feature = "full")]
@@ -83,6 +86,12 @@ pub struct State {
     pub tls_cipher: Option<String>,
     // client tls handshake time
     pub tls_handshake_time: Option<u64>,
+    // the subject of verified client certificate
+    pub client_cert_subject: Option<String>,
+    // the subject alt names of verified client certificate
+    pub client_cert_san: Option<String>,
+    // the sha256 fingerprint of verified client certificate
+    pub client_cert_fingerprint: Option<String>,
     // http status code
     pub status: Option<StatusCode>,
     // the connection time,
@@ -92,6 +101,11 @@ pub struct State {
     pub connection_reused: bool,
     // the location to handle request
     pub location: Option<Arc<Location>>,
+    // the named captures of host and path regex of location
+    pub variables: Option<AHashMap<String, String>>,
+    // the upstream name of location,
+    // it's selected by weight if location has multiple upstreams
+    pub upstream_name: String,
     // the upstream address
     pub upstream_address: String,
     pub client_ip: Option<String>,
@@ -100,12 +114,20 @@ pub struct State {
     pub server_port: Option<u16>,
     pub server_addr: Option<String>,
     pub guard: Option<Guard>,
+    // the consumer identity which is set by auth plugins
+    pub consumer: Option<String>,
+    // the rate limit headers which are set to response
+    pub rate_limit_headers: Option<Vec<HttpHeader>>,
     pub request_id: Option<String>,
     pub cache_prefix: Option<String>,
     pub check_cache_control: bool,
     pub cache_lookup_time: Option<u64>,
     pub cache_lock_time: Option<u64>,
     pub cache_max_ttl: Option<Duration>,
+    // the stale-while-revalidate and stale-if-error of cache,
+    // they override the cache-control directives of upstream response
+    pub cache_stale_while_revalidate: Option<Duration>,
+    pub cache_stale_if_error: Option<Duration>,
     pub upstream_reused: bool,
     pub upstream_processing: Option<i32>,
     // upstream connect time,
@@ -122,6 +144,16 @@ pub struct State {
     pub upstream_processing_time: Option<u64>,
     // upstream response time
     pub upstream_response_time: Option<u64>,
+    // the retry count of upstream
+    pub upstream_retries: usize,
+    // the upstream addresses which are failed in this request
+    pub upstream_tried_addrs: Vec<String>,
+    // the upstream response status which should be retried
+    pub upstream_retry_status: Option<u16>,
+    // the backend address which is selected for the current attempt
+    pub upstream_backend: Option<String>,
+    // the request which is copied to the mirror upstream
+    pub mirror_request: Option<MirrorRequest>,
     // client payload size
     pub payload_size: usize,
     // compression stat, in/out bytes and compression duration
@@ -150,6 +182,14 @@ impl State {
 const ONE_HOUR_MS: u64 = 60 * 60 * 1000;
 
 impl State {
+    /// Get the value of variable, which is captured by location.
+    #[inline]
+    pub fn get_variable(&self, key: &str) -> Option<&str> {
+        self.variables
+            .as_ref()
+            .and_then(|variables| variables.get(key))
+            .map(|value| value.as_str())
+    }
     #[inline]
     pub fn get_upstream_response_time(&self) -> Option<u64> {
         if let Some(value) = self.upstream_response_time {
@@ -193,6 +233,7 @@ impl State {
                 }
             },
             "upstream_addr" => buf.extend(self.upstream_address.as_bytes()),
+            "upstream_name" => buf.extend(self.upstream_name.as_bytes()),
             "processing" => buf
                 .extend(itoa::Buffer::new().format(self.processing).as_bytes()),
             "upstream_connect_time" => {
@@ -215,6 +256,13 @@ impl State {
                     buf = format_duration(buf, ms);
                 }
             },
+            "upstream_retries" => {
+                buf.extend(
+                    itoa::Buffer::new()
+                        .format(self.upstream_retries)
+                        .as_bytes(),
+                );
+            },
             "upstream_tcp_connect_time" => {
                 if let Some(ms) = self.upstream_tcp_connect_time {
                     buf = format_duration(buf, ms);
@@ -225,6 +273,11 @@ impl State {
                     buf = format_duration(buf, ms);
                 }
             },
+            "consumer" => {
+                if let Some(consumer) = &self.consumer {
+                    buf.extend(consumer.as_bytes());
+                }
+            },
             "location" => {
                 if let Some(location) = &self.location {
                     buf.extend(location.name.as_bytes())
@@ -255,6 +308,21 @@ impl State {
                     buf = format_duration(buf, value);
                 }
             },
+            "client_cert_subject" => {
+                if let Some(value) = &self.client_cert_subject {
+                    buf.extend(value.as_bytes());
+                }
+            },
+            "client_cert_san" => {
+                if let Some(value) = &self.client_cert_san {
+                    buf.extend(value.as_bytes());
+                }
+            },
+            "client_cert_fingerprint" => {
+                if let Some(value) = &self.client_cert_fingerprint {
+                    buf.extend(value.as_bytes());
+                }
+            },
             "compression_time" => {
                 if let Some(value) = &self.compression_stat {
                     buf =
@@ -282,7 +350,11 @@ impl State {
                     util::now().as_millis() as u64 - self.created_at,
                 )
             },
-            _ => {},
+            _ => {
+                if let Some(value) = self.get_variable(key) {
+                    buf.extend(value.as_bytes());
+                }
+            },
         }
         buf
     }
@@ -329,6 +401,18 @@ mod tests {
             ctx.append_value(BytesMut::new(), "upstream_addr").as_ref()
         );
 
+        ctx.upstream_name = "charts".to_string();
+        assert_eq!(
+            b"charts",
+            ctx.append_value(BytesMut::new(), "upstream_name").as_ref()
+        );
+
+        ctx.consumer = Some("vip".to_string());
+        assert_eq!(
+            b"vip",
+            ctx.append_value(BytesMut::new(), "consumer").as_ref()
+        );
+
         ctx.processing = 10;
         assert_eq!(
             b"10",
@@ -363,6 +447,13 @@ mod tests {
                 .as_ref()
         );
 
+        ctx.upstream_retries = 2;
+        assert_eq!(
+            b"2",
+            ctx.append_value(BytesMut::new(), "upstream_retries")
+                .as_ref()
+        );
+
         ctx.upstream_tcp_connect_time = Some(100);
         assert_eq!(
             b"100ms",
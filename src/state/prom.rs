@@ -54,6 +54,18 @@ pub static CACHE_WRITING_TIME: Lazy<Box<Histogram>> = Lazy::new(|| {
     )
 });
 
+pub static CERTIFICATE_EXPIRY_DAYS: Lazy<Box<IntGaugeVec>> = Lazy::new(|| {
+    Box::new(
+        new_intgauge_vec(
+            "",
+            "pingap_certificate_expiry_days",
+            "pingap certificate days until expiry",
+            &["certificate"],
+        )
+        .unwrap(),
+    )
+});
+
 pub struct Prometheus {
     r: Registry,
     http_request_accepted: Box<IntCounter>,
@@ -135,15 +147,15 @@ impl Prometheus {
         }
 
         // location stats
-        if let Some(lo) = &ctx.location {
+        if ctx.location.is_some() {
             if let Some(count) = ctx.upstream_connected {
                 self.upstream_connected
-                    .with_label_values(&[&lo.upstream])
+                    .with_label_values(&[&ctx.upstream_name])
                     .set(count as i64);
             }
             if let Some(count) = ctx.upstream_processing {
                 self.upstream_processing
-                    .with_label_values(&[&lo.upstream])
+                    .with_label_values(&[&ctx.upstream_name])
                     .set(count as i64);
             }
         }
@@ -347,7 +359,9 @@ fn new_intgauge_vec(
     label_names: &[&str],
 ) -> Result<IntGaugeVec> {
     let mut opts = Opts::new(name, help);
-    opts = opts.const_label("server", server);
+    if !server.is_empty() {
+        opts = opts.const_label("server", server);
+    }
     let guage =
         IntGaugeVec::new(opts, label_names).map_err(|e| Error::Prometheus {
             message: e.to_string(),
@@ -539,6 +553,7 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         cache_writing.clone(),
         CACHE_READING_TIME.clone(),
         CACHE_WRITING_TIME.clone(),
+        CERTIFICATE_EXPIRY_DAYS.clone(),
         compression_ratio.clone(),
         memory.clone(),
         fd_count.clone(),
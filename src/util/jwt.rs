@@ -0,0 +1,374 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Error;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use openssl::bn::BigNum;
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::rsa::{Padding, Rsa};
+use openssl::sign::{RsaPssSaltlen, Verifier};
+use openssl::x509::X509;
+use serde::Deserialize;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The public key for jwt verification, the kid is from jwks.
+#[derive(Clone)]
+pub struct JwtPublicKey {
+    pub kid: Option<String>,
+    pub key: PKey<Public>,
+}
+
+impl std::fmt::Debug for JwtPublicKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "JwtPublicKey {{ kid: {:?}, id: {:?} }}",
+            self.kid,
+            self.key.id()
+        )
+    }
+}
+
+fn invalid(message: String) -> Error {
+    Error::Invalid { message }
+}
+
+/// Parse the public key from pem, it supports public key,
+/// rsa public key and certificate.
+pub fn parse_public_key_pem(data: &[u8]) -> Result<PKey<Public>> {
+    if let Ok(key) = PKey::public_key_from_pem(data) {
+        return Ok(key);
+    }
+    if let Ok(rsa) = Rsa::public_key_from_pem_pkcs1(data) {
+        return PKey::from_rsa(rsa).map_err(|e| invalid(e.to_string()));
+    }
+    let cert = X509::from_pem(data).map_err(|e| invalid(e.to_string()))?;
+    cert.public_key().map_err(|e| invalid(e.to_string()))
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Jwk {
+    kty: String,
+    kid: Option<String>,
+    #[serde(rename = "use")]
+    key_use: Option<String>,
+    crv: Option<String>,
+    n: Option<String>,
+    e: Option<String>,
+    x: Option<String>,
+    y: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Jwks {
+    keys: Vec<Jwk>,
+}
+
+fn decode_jwk_value(value: &Option<String>, name: &str) -> Result<Vec<u8>> {
+    let value = value
+        .as_ref()
+        .ok_or_else(|| invalid(format!("jwk {name} is missing")))?;
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|e| invalid(format!("jwk {name} is invalid, {e}")))
+}
+
+fn decode_jwk_bn(value: &Option<String>, name: &str) -> Result<BigNum> {
+    BigNum::from_slice(&decode_jwk_value(value, name)?)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+fn convert_jwk(jwk: &Jwk) -> Result<PKey<Public>> {
+    let map_err = |e: openssl::error::ErrorStack| invalid(e.to_string());
+    match jwk.kty.as_str() {
+        "RSA" => {
+            let rsa = Rsa::from_public_components(
+                decode_jwk_bn(&jwk.n, "n")?,
+                decode_jwk_bn(&jwk.e, "e")?,
+            )
+            .map_err(map_err)?;
+            PKey::from_rsa(rsa).map_err(map_err)
+        },
+        "EC" => {
+            let nid = match jwk.crv.as_deref().unwrap_or_default() {
+                "P-256" => Nid::X9_62_PRIME256V1,
+                "P-384" => Nid::SECP384R1,
+                crv => {
+                    return Err(invalid(format!(
+                        "ec curve {crv} is unsupported"
+                    )))
+                },
+            };
+            let group = EcGroup::from_curve_name(nid).map_err(map_err)?;
+            let x = decode_jwk_bn(&jwk.x, "x")?;
+            let y = decode_jwk_bn(&jwk.y, "y")?;
+            let key = EcKey::from_public_key_affine_coordinates(&group, &x, &y)
+                .map_err(map_err)?;
+            PKey::from_ec_key(key).map_err(map_err)
+        },
+        "OKP" => {
+            let crv = jwk.crv.as_deref().unwrap_or_default();
+            if crv != "Ed25519" {
+                return Err(invalid(format!("okp curve {crv} is unsupported")));
+            }
+            PKey::public_key_from_raw_bytes(
+                &decode_jwk_value(&jwk.x, "x")?,
+                Id::ED25519,
+            )
+            .map_err(map_err)
+        },
+        kty => Err(invalid(format!("key type {kty} is unsupported"))),
+    }
+}
+
+/// Parse the jwks document, the keys which are not used for
+/// signature or unsupported are ignored.
+pub fn parse_jwks(data: &[u8]) -> Result<Vec<JwtPublicKey>> {
+    let jwks: Jwks = serde_json::from_slice(data)
+        .map_err(|e| invalid(format!("jwks is invalid, {e}")))?;
+    let mut keys = vec![];
+    for jwk in jwks.keys.iter() {
+        if jwk.key_use.as_ref().is_some_and(|value| value != "sig") {
+            continue;
+        }
+        if let Ok(key) = convert_jwk(jwk) {
+            keys.push(JwtPublicKey {
+                kid: jwk.kid.clone(),
+                key,
+            });
+        }
+    }
+    if keys.is_empty() {
+        return Err(invalid("jwks has no valid key".to_string()));
+    }
+    Ok(keys)
+}
+
+/// Returns true if the algorithm is verified by public key.
+pub fn is_asymmetric_jwt_alg(alg: &str) -> bool {
+    matches!(
+        alg,
+        "RS256"
+            | "RS384"
+            | "RS512"
+            | "PS256"
+            | "PS384"
+            | "PS512"
+            | "ES256"
+            | "ES384"
+            | "EdDSA"
+    )
+}
+
+/// Verify the signature of jwt by public key, returns false
+/// if the key does not match the algorithm.
+pub fn verify_jwt_signature(
+    alg: &str,
+    key: &PKey<Public>,
+    content: &[u8],
+    signature: &[u8],
+) -> Result<bool> {
+    let map_err = |e: openssl::error::ErrorStack| invalid(e.to_string());
+    let digest = match &alg[alg.len().saturating_sub(3)..] {
+        "384" => MessageDigest::sha384(),
+        "512" => MessageDigest::sha512(),
+        _ => MessageDigest::sha256(),
+    };
+    match alg {
+        "RS256" | "RS384" | "RS512" | "PS256" | "PS384" | "PS512" => {
+            if key.id() != Id::RSA {
+                return Ok(false);
+            }
+            let mut verifier = Verifier::new(digest, key).map_err(map_err)?;
+            if alg.starts_with("PS") {
+                verifier
+                    .set_rsa_padding(Padding::PKCS1_PSS)
+                    .map_err(map_err)?;
+                verifier
+                    .set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)
+                    .map_err(map_err)?;
+            }
+            verifier.verify_oneshot(signature, content).map_err(map_err)
+        },
+        "ES256" | "ES384" => {
+            let size = if alg == "ES256" { 32 } else { 48 };
+            let Ok(ec_key) = key.ec_key() else {
+                return Ok(false);
+            };
+            let bits = ec_key.group().degree() as usize;
+            // the signature is r and s, but openssl needs der format
+            if bits.div_ceil(8) != size || signature.len() != size * 2 {
+                return Ok(false);
+            }
+            let sig = EcdsaSig::from_private_components(
+                BigNum::from_slice(&signature[..size]).map_err(map_err)?,
+                BigNum::from_slice(&signature[size..]).map_err(map_err)?,
+            )
+            .map_err(map_err)?;
+            let der = sig.to_der().map_err(map_err)?;
+            let mut verifier = Verifier::new(digest, key).map_err(map_err)?;
+            verifier.verify_oneshot(&der, content).map_err(map_err)
+        },
+        "EdDSA" => {
+            if key.id() != Id::ED25519 {
+                return Ok(false);
+            }
+            let mut verifier =
+                Verifier::new_without_digest(key).map_err(map_err)?;
+            verifier.verify_oneshot(signature, content).map_err(map_err)
+        },
+        _ => Err(invalid(format!("algorithm {alg} is unsupported"))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{
+        is_asymmetric_jwt_alg, parse_jwks, parse_public_key_pem,
+        verify_jwt_signature,
+    };
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use openssl::bn::BigNumContext;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::ecdsa::EcdsaSig;
+    use openssl::hash::{hash, MessageDigest};
+    use openssl::nid::Nid;
+    use openssl::pkey::{PKey, Private};
+    use openssl::rsa::Rsa;
+    use openssl::sign::Signer;
+    use pretty_assertions::assert_eq;
+
+    /// Sign the content of jwt, the signature is url safe base64.
+    pub(crate) fn sign_jwt(
+        alg: &str,
+        key: &PKey<Private>,
+        content: &str,
+    ) -> String {
+        let signature = match alg {
+            "ES256" => {
+                let digest =
+                    hash(MessageDigest::sha256(), content.as_bytes()).unwrap();
+                let sig =
+                    EcdsaSig::sign(&digest, &key.ec_key().unwrap()).unwrap();
+                let mut r = sig.r().to_vec_padded(32).unwrap();
+                r.extend(sig.s().to_vec_padded(32).unwrap());
+                r
+            },
+            "EdDSA" => {
+                let mut signer = Signer::new_without_digest(key).unwrap();
+                signer.sign_oneshot_to_vec(content.as_bytes()).unwrap()
+            },
+            _ => {
+                let mut signer =
+                    Signer::new(MessageDigest::sha256(), key).unwrap();
+                signer.update(content.as_bytes()).unwrap();
+                signer.sign_to_vec().unwrap()
+            },
+        };
+        URL_SAFE_NO_PAD.encode(signature)
+    }
+
+    /// Generate the rsa, ec and ed25519 keys, and the jwks of them.
+    pub(crate) fn new_test_keys() -> (Vec<PKey<Private>>, String) {
+        let rsa = Rsa::generate(2048).unwrap();
+        let n = URL_SAFE_NO_PAD.encode(rsa.n().to_vec());
+        let e = URL_SAFE_NO_PAD.encode(rsa.e().to_vec());
+        let rsa = PKey::from_rsa(rsa).unwrap();
+
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let ec = EcKey::generate(&group).unwrap();
+        let mut ctx = BigNumContext::new().unwrap();
+        let mut x = openssl::bn::BigNum::new().unwrap();
+        let mut y = openssl::bn::BigNum::new().unwrap();
+        ec.public_key()
+            .affine_coordinates(&group, &mut x, &mut y, &mut ctx)
+            .unwrap();
+        let x = URL_SAFE_NO_PAD.encode(x.to_vec_padded(32).unwrap());
+        let y = URL_SAFE_NO_PAD.encode(y.to_vec_padded(32).unwrap());
+        let ec = PKey::from_ec_key(ec).unwrap();
+
+        let ed = PKey::generate_ed25519().unwrap();
+        let ed_x = URL_SAFE_NO_PAD.encode(ed.raw_public_key().unwrap());
+
+        let jwks = format!(
+            r#"{{"keys": [
+{{"kty": "RSA", "kid": "rsa", "use": "sig", "n": "{n}", "e": "{e}"}},
+{{"kty": "EC", "kid": "ec", "crv": "P-256", "x": "{x}", "y": "{y}"}},
+{{"kty": "OKP", "kid": "ed", "crv": "Ed25519", "x": "{ed_x}"}},
+{{"kty": "RSA", "kid": "enc", "use": "enc", "n": "{n}", "e": "{e}"}},
+{{"kty": "oct", "kid": "oct", "k": "abc"}}
+]}}"#
+        );
+        (vec![rsa, ec, ed], jwks)
+    }
+
+    #[test]
+    fn test_parse_public_key_pem() {
+        let (keys, _) = new_test_keys();
+        for key in keys.iter() {
+            let pem = key.public_key_to_pem().unwrap();
+            let public_key = parse_public_key_pem(&pem).unwrap();
+            assert_eq!(true, public_key.public_eq(key));
+        }
+        let pem = keys[0].rsa().unwrap().public_key_to_pem_pkcs1().unwrap();
+        assert_eq!(true, parse_public_key_pem(&pem).is_ok());
+        assert_eq!(true, parse_public_key_pem(b"abc").is_err());
+    }
+
+    #[test]
+    fn test_verify_jwt_signature() {
+        let (keys, jwks) = new_test_keys();
+        let public_keys = parse_jwks(jwks.as_bytes()).unwrap();
+        assert_eq!(
+            vec![Some("rsa"), Some("ec"), Some("ed")],
+            public_keys
+                .iter()
+                .map(|item| item.kid.as_deref())
+                .collect::<Vec<_>>()
+        );
+        let content = "eyJhbGciOiJSUzI1NiJ9.eyJzdWIiOiJwaW5nYXAifQ";
+        for (index, alg) in ["RS256", "ES256", "EdDSA"].iter().enumerate() {
+            assert_eq!(true, is_asymmetric_jwt_alg(alg));
+            let signature = URL_SAFE_NO_PAD
+                .decode(sign_jwt(alg, &keys[index], content))
+                .unwrap();
+            for (i, public_key) in public_keys.iter().enumerate() {
+                let valid = verify_jwt_signature(
+                    alg,
+                    &public_key.key,
+                    content.as_bytes(),
+                    &signature,
+                )
+                .unwrap();
+                // only the matched key is valid
+                assert_eq!(i == index, valid);
+            }
+            let valid = verify_jwt_signature(
+                alg,
+                &public_keys[index].key,
+                b"abc",
+                &signature,
+            )
+            .unwrap_or_default();
+            assert_eq!(false, valid);
+        }
+        assert_eq!(false, is_asymmetric_jwt_alg("HS256"));
+        assert_eq!(true, parse_jwks(br#"{"keys": []}"#).is_err());
+    }
+}
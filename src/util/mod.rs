@@ -40,9 +40,18 @@ pub enum Error {
 
 mod crypto;
 mod ip;
+mod jwt;
+mod password;
 
 pub use crypto::{aes_decrypt, aes_encrypt};
 pub use ip::IpRules;
+#[cfg(test)]
+pub(crate) use jwt::tests::{new_test_keys, sign_jwt};
+pub use jwt::{
+    is_asymmetric_jwt_alg, parse_jwks, parse_public_key_pem,
+    verify_jwt_signature, JwtPublicKey,
+};
+pub use password::{constant_time_eq, parse_htpasswd, Password};
 
 const NAME: &str = env!("CARGO_PKG_NAME");
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -136,7 +145,7 @@ pub fn get_cookie_value<'a>(
     if let Some(cookie_value) = get_req_header_value(req_header, "Cookie") {
         for item in cookie_value.split(';') {
             if let Some((k, v)) = item.split_once('=') {
-                if k == cookie_name {
+                if k.trim() == cookie_name {
                     return Some(v.trim());
                 }
             }
@@ -202,6 +211,28 @@ pub fn remove_query_from_header(
     Ok(())
 }
 
+/// Remove cookie from req header, the cookie header is removed
+/// if there is no other cookie.
+pub fn remove_cookie_from_header(req_header: &mut RequestHeader, name: &str) {
+    let Some(cookie_value) = get_req_header_value(req_header, "Cookie") else {
+        return;
+    };
+    let cookies: Vec<&str> = cookie_value
+        .split(';')
+        .map(|item| item.trim())
+        .filter(|item| {
+            !item.is_empty()
+                && item.split_once('=').map_or(*item, |(k, _)| k.trim()) != name
+        })
+        .collect();
+    if cookies.is_empty() {
+        req_header.remove_header(&http::header::COOKIE);
+        return;
+    }
+    let value = cookies.join("; ");
+    let _ = req_header.insert_header(http::header::COOKIE, value);
+}
+
 /// Creates a new internal error
 pub fn new_internal_error(status: u16, message: String) -> pingora::BError {
     pingora::Error::because(
@@ -315,10 +346,15 @@ pub fn convert_tls_version(version: &Option<String>) -> Option<SslVersion> {
     None
 }
 
+/// Convert the certificate to bytes, the value can be pem,
+/// base64 of pem or file path.
 pub fn convert_certificate_bytes(value: &Option<String>) -> Option<Vec<u8>> {
     if let Some(value) = value {
+        let file = resolve_path(value);
         if is_pem(value) {
             return Some(value.as_bytes().to_vec());
+        } else if Path::new(&file).is_file() {
+            return std::fs::read(&file).ok();
         } else {
             let buf = base64_decode(value).unwrap_or_default();
             return Some(buf);
@@ -352,6 +388,10 @@ impl RegexCapture {
         }
         Ok(RegexCapture { re, keys })
     }
+    /// Return `true` if the regex has named capture groups.
+    pub fn has_names(&self) -> bool {
+        self.keys.iter().any(|key| !key.is_empty())
+    }
     pub fn captures(
         &self,
         value: &str,
@@ -382,6 +422,40 @@ impl RegexCapture {
     }
 }
 
+/// Replace the `$name` or `${name}` variables of value,
+/// the variable is kept if it's not found.
+pub fn replace_variables(
+    value: &str,
+    get_variable: impl Fn(&str) -> Option<String>,
+) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(index) = rest.find('$') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index..];
+        let (name, size) = if rest[1..].starts_with('{') {
+            match rest.find('}') {
+                Some(end) => (&rest[2..end], end + 1),
+                None => ("", 1),
+            }
+        } else {
+            let end = rest[1..]
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .map(|end| end + 1)
+                .unwrap_or(rest.len());
+            (&rest[1..end], end)
+        };
+        let size = size.max(1);
+        match get_variable(name).filter(|_| !name.is_empty()) {
+            Some(variable) => result.push_str(&variable),
+            None => result.push_str(&rest[..size]),
+        }
+        rest = &rest[size..];
+    }
+    result.push_str(rest);
+    result
+}
+
 const B_100: usize = 100;
 const KB: usize = 1_000;
 const KB_100: usize = 100 * KB;
@@ -444,9 +518,10 @@ pub fn format_duration(mut buf: BytesMut, ms: u64) -> BytesMut {
 #[cfg(test)]
 mod tests {
     use super::{
-        convert_tls_version, format_byte_size, format_duration, get_latency,
-        get_pkg_name, get_pkg_version, local_ip_list, remove_query_from_header,
-        resolve_path,
+        convert_certificate_bytes, convert_tls_version, format_byte_size,
+        format_duration, get_cookie_value, get_latency, get_pkg_name,
+        get_pkg_version, local_ip_list, remove_cookie_from_header,
+        remove_query_from_header, replace_variables, resolve_path,
     };
     use bytes::BytesMut;
     use pingora::{http::RequestHeader, tls::ssl::SslVersion};
@@ -465,6 +540,30 @@ mod tests {
         assert_eq!("/?name=pingap", req.uri.to_string());
     }
 
+    #[test]
+    fn test_remove_cookie_from_header() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("Cookie", "uid=123; jwt=abc==; lang=en")
+            .unwrap();
+        remove_cookie_from_header(&mut req, "jwt");
+        assert_eq!("uid=123; lang=en", req.headers.get("Cookie").unwrap());
+
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("Cookie", "jwt=abc").unwrap();
+        remove_cookie_from_header(&mut req, "jwt");
+        assert_eq!(true, req.headers.get("Cookie").is_none());
+    }
+
+    #[test]
+    fn test_get_cookie_value() {
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("Cookie", "uid=123; session=abc==")
+            .unwrap();
+        assert_eq!(Some("123"), get_cookie_value(&req, "uid"));
+        assert_eq!(Some("abc=="), get_cookie_value(&req, "session"));
+        assert_eq!(None, get_cookie_value(&req, "name"));
+    }
+
     #[test]
     fn test_get_pkg_info() {
         assert_eq!("pingap", get_pkg_name());
@@ -486,6 +585,44 @@ mod tests {
         assert_eq!(true, get_latency(&d).is_some());
     }
     #[test]
+    fn test_convert_certificate_bytes() {
+        let pem = "-----BEGIN CERTIFICATE-----";
+        assert_eq!(
+            pem.as_bytes().to_vec(),
+            convert_certificate_bytes(&Some(pem.to_string())).unwrap()
+        );
+        assert_eq!(
+            b"pingap".to_vec(),
+            convert_certificate_bytes(&Some("cGluZ2Fw".to_string())).unwrap()
+        );
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), pem).unwrap();
+        assert_eq!(
+            pem.as_bytes().to_vec(),
+            convert_certificate_bytes(&Some(
+                file.path().to_string_lossy().to_string()
+            ))
+            .unwrap()
+        );
+        assert_eq!(true, convert_certificate_bytes(&None).is_none());
+    }
+    #[test]
+    fn test_replace_variables() {
+        let get_variable = |name: &str| match name {
+            "tenant" => Some("pingap".to_string()),
+            _ => None,
+        };
+        assert_eq!(
+            "/pingap/api/pingap-v1/$1/$name/${",
+            replace_variables(
+                "/$tenant/api/${tenant}-v1/$1/$name/${",
+                get_variable
+            )
+        );
+        assert_eq!("$", replace_variables("$", get_variable));
+        assert_eq!("/api", replace_variables("/api", get_variable));
+    }
+    #[test]
     fn test_convert_tls_version() {
         assert_eq!(
             SslVersion::TLS1_1,
@@ -0,0 +1,288 @@
+// Copyright 2024 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Error;
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::{Digest, Md5};
+use openssl::hash::{hash, MessageDigest};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const CRYPT_ITOA64: &[u8] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+fn crypt_to64(buf: &mut String, mut value: u32, size: usize) {
+    for _ in 0..size {
+        buf.push(CRYPT_ITOA64[(value & 0x3f) as usize] as char);
+        value >>= 6;
+    }
+}
+
+/// Compute the md5 crypt hash of password, the magic of
+/// apache htpasswd is `$apr1$`.
+fn md5_crypt(password: &[u8], salt: &[u8], magic: &[u8]) -> String {
+    let alt = Md5::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+    let mut hasher = Md5::new()
+        .chain_update(password)
+        .chain_update(magic)
+        .chain_update(salt);
+    for chunk in (0..password.len()).step_by(16) {
+        let size = (password.len() - chunk).min(16);
+        hasher.update(&alt[..size]);
+    }
+    let mut i = password.len();
+    while i > 0 {
+        if i & 1 == 1 {
+            hasher.update([0]);
+        } else {
+            hasher.update(&password[..1]);
+        }
+        i >>= 1;
+    }
+    let mut result = hasher.finalize();
+    for i in 0..1000 {
+        let mut hasher = Md5::new();
+        if i & 1 == 1 {
+            hasher.update(password);
+        } else {
+            hasher.update(result);
+        }
+        if i % 3 != 0 {
+            hasher.update(salt);
+        }
+        if i % 7 != 0 {
+            hasher.update(password);
+        }
+        if i & 1 == 1 {
+            hasher.update(result);
+        } else {
+            hasher.update(password);
+        }
+        result = hasher.finalize();
+    }
+    let mut buf = String::new();
+    for (a, b, c) in
+        [(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)]
+    {
+        let value = ((result[a] as u32) << 16)
+            | ((result[b] as u32) << 8)
+            | result[c] as u32;
+        crypt_to64(&mut buf, value, 4);
+    }
+    crypt_to64(&mut buf, result[11] as u32, 2);
+    buf
+}
+
+/// Compare the data in constant time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && openssl::memcmp::eq(a, b)
+}
+
+/// The password of credential, it may be plain text or hash of
+/// bcrypt(`$2y$`), argon2(`$argon2id$`), apr1(`$apr1$`),
+/// sha1(`{SHA}`) and sha256(`{SHA256}`).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Password {
+    Plain(String),
+    Sha1(Vec<u8>),
+    Sha256(Vec<u8>),
+    Apr1 { salt: String, hash: String },
+    // the phc string of bcrypt, e.g. `$2y$10$...`
+    Bcrypt(String),
+    // the phc string of argon2, e.g. `$argon2id$v=19$...`
+    Argon2(String),
+}
+
+fn invalid(value: &str) -> Error {
+    Error::Invalid {
+        message: format!("password hash {value} is invalid"),
+    }
+}
+
+impl Password {
+    pub fn new(value: &str) -> Result<Self> {
+        if let Some(data) = value.strip_prefix("{SHA}") {
+            let data = STANDARD
+                .decode(data)
+                .map_err(|e| Error::Base64Decode { source: e })?;
+            return Ok(Password::Sha1(data));
+        }
+        if let Some(data) = value.strip_prefix("{SHA256}") {
+            let data = STANDARD
+                .decode(data)
+                .map_err(|e| Error::Base64Decode { source: e })?;
+            return Ok(Password::Sha256(data));
+        }
+        if let Some(data) = value.strip_prefix("$apr1$") {
+            let Some((salt, hash)) = data.split_once('$') else {
+                return Err(invalid(value));
+            };
+            return Ok(Password::Apr1 {
+                salt: salt.to_string(),
+                hash: hash.to_string(),
+            });
+        }
+        if ["$2a$", "$2b$", "$2y$"]
+            .iter()
+            .any(|v| value.starts_with(v))
+        {
+            // check the cost, salt and hash of bcrypt
+            let _ = value
+                .parse::<bcrypt::HashParts>()
+                .map_err(|_| invalid(value))?;
+            return Ok(Password::Bcrypt(value.to_string()));
+        }
+        if value.starts_with("$argon2") {
+            let _ = PasswordHash::new(value).map_err(|_| invalid(value))?;
+            return Ok(Password::Argon2(value.to_string()));
+        }
+        if value.starts_with('$') {
+            return Err(Error::Invalid {
+                message: format!("password hash {value} is not supported"),
+            });
+        }
+        Ok(Password::Plain(value.to_string()))
+    }
+    /// The bcrypt and argon2id are slow by design, so they
+    /// should not be verified in the async runtime.
+    pub fn is_expensive(&self) -> bool {
+        matches!(self, Password::Bcrypt(_) | Password::Argon2(_))
+    }
+    /// Verify the password in constant time.
+    pub fn verify(&self, password: &[u8]) -> bool {
+        match self {
+            Password::Plain(value) => {
+                // compare the digests, so the length is not leaked
+                let sha256 = |data: &[u8]| {
+                    hash(MessageDigest::sha256(), data)
+                        .map(|v| v.to_vec())
+                        .unwrap_or_default()
+                };
+                constant_time_eq(&sha256(value.as_bytes()), &sha256(password))
+            },
+            Password::Sha1(value) => hash(MessageDigest::sha1(), password)
+                .map(|v| constant_time_eq(&v, value))
+                .unwrap_or_default(),
+            Password::Sha256(value) => hash(MessageDigest::sha256(), password)
+                .map(|v| constant_time_eq(&v, value))
+                .unwrap_or_default(),
+            Password::Apr1 { salt, hash } => {
+                let value = md5_crypt(password, salt.as_bytes(), b"$apr1$");
+                constant_time_eq(value.as_bytes(), hash.as_bytes())
+            },
+            Password::Bcrypt(value) => {
+                bcrypt::verify(password, value).unwrap_or_default()
+            },
+            Password::Argon2(value) => PasswordHash::new(value)
+                .map(|hash| {
+                    Argon2::default().verify_password(password, &hash).is_ok()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Parse the credentials of htpasswd file, each line is `user:hash`.
+pub fn parse_htpasswd(data: &str) -> Result<Vec<(String, Password)>> {
+    let mut credentials = vec![];
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((user, value)) = line.split_once(':') else {
+            return Err(Error::Invalid {
+                message: format!("htpasswd line {line} is invalid"),
+            });
+        };
+        credentials.push((user.to_string(), Password::new(value)?));
+    }
+    Ok(credentials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_htpasswd, Password};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_password() {
+        let cases = [
+            ("pingap", "pingap"),
+            ("{SHA}jdPcvpdktMR2271BE/dMhZJegC4=", "pingap"),
+            ("{SHA256}1/HMZ1tMri9mMw+y2//nN5lz3+lAxAClZjlwCVYeu6g=", "pingap"),
+            ("$apr1$abcdefgh$DKG7DKqXLFT1328cV5wDe0", "pingap"),
+            (
+                "$2y$04$abcdefghijklmnopqrstuuh.pLFdOiKKF4SAufq8Znnqk23MEwr3i",
+                "pingap",
+            ),
+            (
+                "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW",
+                "U*U",
+            ),
+            (
+                "$argon2id$v=19$m=1024,t=2,p=1$c2FsdHNhbHRzYWx0$mEV2sn+8Vqnw5/QS3OVAizEG+KBLaGAFTDWRXTjHeHo",
+                "pingap",
+            ),
+        ];
+        for (value, password) in cases {
+            let hash = Password::new(value).unwrap();
+            assert_eq!(true, hash.verify(password.as_bytes()), "{value}");
+            assert_eq!(false, hash.verify(b"pingap1"), "{value}");
+        }
+        assert_eq!(
+            true,
+            Password::new(
+                "$2y$04$abcdefghijklmnopqrstuuh.pLFdOiKKF4SAufq8Znnqk23MEwr3i"
+            )
+            .unwrap()
+            .is_expensive()
+        );
+
+        assert_eq!(
+            "Invalid password hash $6$abc$def is not supported",
+            Password::new("$6$abc$def").err().unwrap().to_string()
+        );
+        assert_eq!(
+            "Invalid password hash $2y$04$abc is invalid",
+            Password::new("$2y$04$abc").err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_htpasswd() {
+        let credentials = parse_htpasswd(
+            r#"
+# users
+tree:$apr1$abcdefgh$DKG7DKqXLFT1328cV5wDe0
+pingap:{SHA}jdPcvpdktMR2271BE/dMhZJegC4=
+"#,
+        )
+        .unwrap();
+        assert_eq!(2, credentials.len());
+        assert_eq!("tree", credentials[0].0);
+        assert_eq!(true, credentials[1].1.verify(b"pingap"));
+
+        assert_eq!(
+            "Invalid htpasswd line tree is invalid",
+            parse_htpasswd("tree").err().unwrap().to_string()
+        );
+    }
+}
@@ -51,6 +51,7 @@ pub enum NotificationCategory {
     TlsValidity,
     ParseCertificateFail,
     ServiceDiscoverFail,
+    OcspFail,
 }
 
 impl Display for NotificationLevel {
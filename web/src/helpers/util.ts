@@ -23,6 +23,15 @@ export function newZodDuration() {
   return z.string().regex(reg);
 }
 
+// keep the fields of config which are not rendered in form,
+// otherwise they are removed when the config is updated
+export function mergeConfig(
+  original: object,
+  updated: Record<string, unknown>,
+): Record<string, unknown> {
+  return Object.assign({}, original, updated);
+}
+
 export function omitEmptyArray(data: Record<string, unknown>) {
   Object.keys(data).forEach((key) => {
     const value = data[key];
@@ -291,6 +291,12 @@ export default {
     cacheNamespacePlaceholder: "Input the namespace of cache",
     cacheMaxTtl: "Max Ttl",
     cacheMaxTtlPlaceholder: "Input the max cache ttl of cache(e.g. 1h)",
+    cacheStaleWhileRevalidate: "Stale While Revalidate",
+    cacheStaleWhileRevalidatePlaceholder:
+      "Input the duration to serve stale while revalidating(e.g. 10s)",
+    cacheStaleIfError: "Stale If Error",
+    cacheStaleIfErrorPlaceholder:
+      "Input the duration to serve stale if upstream fails(e.g. 1h)",
     cacheEviction: "Support Eviction",
     cachePredictor: "Support Predictor",
     checkCacheControl: "Check Cache-Control response header",
@@ -272,6 +272,10 @@ export default {
     cacheNamespacePlaceholder: "输入缓存使用的命名空间",
     cacheMaxTtl: "缓存最大ttl",
     cacheMaxTtlPlaceholder: "输入缓存的最大ttl(如1h)",
+    cacheStaleWhileRevalidate: "过期后台更新时长",
+    cacheStaleWhileRevalidatePlaceholder: "输入过期缓存在后台更新时可使用的时长(如10s)",
+    cacheStaleIfError: "出错使用过期缓存时长",
+    cacheStaleIfErrorPlaceholder: "输入上游出错时可使用过期缓存的时长(如1h)",
     cacheEviction: "支持缓存逐出",
     cachePredictor: "支持缓存状态记录",
     checkCacheControl: "校验Cache-Control响应头",
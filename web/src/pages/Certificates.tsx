@@ -11,7 +11,7 @@ import {
 } from "@/constants";
 import { useSearchParams } from "react-router-dom";
 import { useEffect } from "react";
-import { formatLabel } from "@/helpers/util";
+import { formatLabel, mergeConfig } from "@/helpers/util";
 
 function getCertificateConfig(
   name: string,
@@ -161,7 +161,8 @@ export default function Certificates() {
           if (name === newCertificate) {
             name = value["name"] as string;
           }
-          await update("certificate", name, value);
+          const data = mergeConfig(certificateConfig, value);
+          await update("certificate", name, data);
           handleSelectCertificate(name);
         }}
       />
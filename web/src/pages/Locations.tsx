@@ -6,7 +6,12 @@ import { ExForm, ExFormItem } from "@/components/ex-form";
 import { pascal } from "radash";
 import { z } from "zod";
 import { ExFormItemCategory, newStringOptions } from "@/constants";
-import { formatLabel, newZodBytes, omitEmptyArray } from "@/helpers/util";
+import {
+  formatLabel,
+  newZodBytes,
+  omitEmptyArray,
+  mergeConfig,
+} from "@/helpers/util";
 import { useSearchParams } from "react-router-dom";
 import { useEffect } from "react";
 
@@ -211,8 +216,9 @@ export default function Locations() {
           if (name === newLocation) {
             name = value["name"] as string;
           }
-          omitEmptyArray(value);
-          await update("location", name, value);
+          const data = mergeConfig(locationConfig, value);
+          omitEmptyArray(data);
+          await update("location", name, data);
           handleSelectLocation(name);
         }}
       />
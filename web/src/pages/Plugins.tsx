@@ -379,6 +379,22 @@ export default function Plugins() {
           span: 3,
           category: ExFormItemCategory.TEXT,
         },
+        {
+          name: "stale_while_revalidate",
+          label: pluginI18n("cacheStaleWhileRevalidate"),
+          placeholder: pluginI18n("cacheStaleWhileRevalidatePlaceholder"),
+          defaultValue: pluginConfig.stale_while_revalidate as string,
+          span: 3,
+          category: ExFormItemCategory.TEXT,
+        },
+        {
+          name: "stale_if_error",
+          label: pluginI18n("cacheStaleIfError"),
+          placeholder: pluginI18n("cacheStaleIfErrorPlaceholder"),
+          defaultValue: pluginConfig.stale_if_error as string,
+          span: 3,
+          category: ExFormItemCategory.TEXT,
+        },
         {
           name: "eviction",
           label: pluginI18n("cacheEviction"),
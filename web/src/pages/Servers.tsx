@@ -10,7 +10,12 @@ import {
   newStringOptions,
   newBooleanOptions,
 } from "@/constants";
-import { formatLabel, newZodDuration, omitEmptyArray } from "@/helpers/util";
+import {
+  formatLabel,
+  newZodDuration,
+  omitEmptyArray,
+  mergeConfig,
+} from "@/helpers/util";
 import { useSearchParams } from "react-router-dom";
 import { useEffect } from "react";
 
@@ -273,8 +278,9 @@ export default function Servers() {
           if (name === newServer) {
             name = value["name"] as string;
           }
-          omitEmptyArray(value);
-          await update("server", name, value);
+          const data = mergeConfig(serverConfig, value);
+          omitEmptyArray(data);
+          await update("server", name, data);
           handleSelectServer(name);
         }}
       />
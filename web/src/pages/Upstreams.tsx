@@ -14,6 +14,7 @@ import {
   newZodBytes,
   newZodDuration,
   omitEmptyArray,
+  mergeConfig,
 } from "@/helpers/util";
 import { useSearchParams } from "react-router-dom";
 import { useEffect } from "react";
@@ -323,8 +324,9 @@ export default function Upstreams() {
           if (name === newUpstream) {
             name = value["name"] as string;
           }
-          omitEmptyArray(value);
-          await update("upstream", name, value);
+          const data = mergeConfig(upstreamConfig, value);
+          omitEmptyArray(data);
+          await update("upstream", name, data);
           handleSelectUpstream(name);
         }}
       />